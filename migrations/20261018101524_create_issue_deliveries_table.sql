@@ -0,0 +1,22 @@
+-- Add migration script here
+CREATE TABLE issue_deliveries (
+    newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (newsletter_issue_id),
+    subscriber_email TEXT NOT NULL,
+    status TEXT NOT NULL,
+    n_attempts SMALLINT NOT NULL DEFAULT 0,
+    provider_message_id TEXT NULL,
+    last_error TEXT NULL,
+    updated_at timestamptz NOT NULL,
+    PRIMARY KEY(newsletter_issue_id, subscriber_email)
+);
+
+-- Keep track of the deliveries that were in flight when the table was created
+INSERT INTO issue_deliveries (newsletter_issue_id, subscriber_email, status, n_attempts, updated_at)
+SELECT newsletter_issue_id, subscriber_email, 'pending', n_retries, now()
+FROM issue_delivery_queue;
+
+INSERT INTO issue_deliveries (
+    newsletter_issue_id, subscriber_email, status, n_attempts, last_error, updated_at
+)
+SELECT newsletter_issue_id, subscriber_email, 'failed', n_retries + 1, last_error, failed_at
+FROM issue_delivery_failures;
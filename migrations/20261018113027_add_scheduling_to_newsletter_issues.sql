@@ -0,0 +1,11 @@
+-- Add migration script here
+BEGIN;
+    -- Scheduled issues have not been published yet
+    ALTER TABLE newsletter_issues
+        ALTER COLUMN published_at TYPE timestamptz USING published_at::timestamptz,
+        ALTER COLUMN published_at DROP NOT NULL;
+    ALTER TABLE newsletter_issues ADD COLUMN send_at timestamptz NULL;
+    -- One of 'scheduled', 'published' or 'cancelled'
+    ALTER TABLE newsletter_issues ADD COLUMN status TEXT NOT NULL DEFAULT 'published';
+    ALTER TABLE newsletter_issues ALTER COLUMN status DROP DEFAULT;
+COMMIT;
@@ -0,0 +1,13 @@
+-- Add migration script here
+BEGIN;
+    ALTER TABLE newsletter_issues ADD COLUMN slug TEXT NULL UNIQUE;
+    ALTER TABLE newsletter_issues ADD COLUMN in_public_archive BOOLEAN NOT NULL DEFAULT true;
+    -- Issues get a slug when they are published
+    UPDATE newsletter_issues
+        SET slug = concat_ws(
+            '-',
+            NULLIF(trim(BOTH '-' FROM lower(regexp_replace(title, '[^a-zA-Z0-9]+', '-', 'g'))), ''),
+            left(replace(newsletter_issue_id::text, '-', ''), 8)
+        )
+        WHERE status <> 'draft';
+COMMIT;
@@ -0,0 +1,25 @@
+-- Add migration script here
+BEGIN;
+    -- What the email provider tells us about the emails we sent
+    CREATE TABLE email_events (
+        id uuid PRIMARY KEY,
+        -- The id the provider gave to the event, to ignore redeliveries
+        provider_event_id BIGINT NULL UNIQUE,
+        record_type TEXT NOT NULL,
+        -- e.g. HardBounce or SoftBounce, for bounces
+        event_type TEXT NOT NULL,
+        email TEXT NOT NULL,
+        provider_message_id TEXT NULL,
+        description TEXT NULL,
+        occurred_at timestamptz NOT NULL,
+        received_at timestamptz NOT NULL
+    );
+    CREATE INDEX email_events_email_idx ON email_events (lower(email));
+
+    -- Addresses we must never email again, subscribed or not
+    CREATE TABLE suppressed_emails (
+        email TEXT PRIMARY KEY,
+        reason TEXT NOT NULL,
+        suppressed_at timestamptz NOT NULL
+    );
+COMMIT;
@@ -0,0 +1,3 @@
+-- Add migration script here
+-- Reset whenever an email is known to have reached the subscriber
+ALTER TABLE subscriptions ADD COLUMN consecutive_soft_bounces SMALLINT NOT NULL DEFAULT 0;
@@ -0,0 +1,19 @@
+-- Add migration script here
+BEGIN;
+    ALTER TABLE newsletter_issues ADD COLUMN track_engagement BOOLEAN NOT NULL DEFAULT true;
+
+    -- Opens (the tracking pixel was loaded) and clicks (a tracked link was followed)
+    CREATE TABLE issue_engagement_events (
+        id uuid PRIMARY KEY,
+        newsletter_issue_id uuid NOT NULL
+            REFERENCES newsletter_issues (newsletter_issue_id),
+        subscriber_id uuid NOT NULL
+            REFERENCES subscriptions (id) ON DELETE CASCADE,
+        kind TEXT NOT NULL,
+        -- Where the link pointed to, for clicks
+        url TEXT NULL,
+        occurred_at timestamptz NOT NULL
+    );
+    CREATE INDEX issue_engagement_events_issue_idx
+        ON issue_engagement_events (newsletter_issue_id);
+COMMIT;
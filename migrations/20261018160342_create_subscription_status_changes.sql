@@ -0,0 +1,36 @@
+-- Add migration script here
+BEGIN;
+    -- Every status a subscriber went through, filled in by the triggers below
+    -- so that no code path changing a status can forget about it
+    CREATE TABLE subscription_status_changes (
+        id BIGSERIAL PRIMARY KEY,
+        subscriber_id uuid NOT NULL
+            REFERENCES subscriptions (id) ON DELETE CASCADE,
+        status TEXT NOT NULL,
+        changed_at timestamptz NOT NULL
+    );
+    CREATE INDEX subscription_status_changes_subscriber_idx
+        ON subscription_status_changes (subscriber_id);
+
+    CREATE FUNCTION record_subscription_status_change() RETURNS trigger AS $$
+    BEGIN
+        INSERT INTO subscription_status_changes (subscriber_id, status, changed_at)
+        VALUES (NEW.id, NEW.status, now());
+        RETURN NEW;
+    END;
+    $$ LANGUAGE plpgsql;
+
+    CREATE TRIGGER subscription_created
+        AFTER INSERT ON subscriptions
+        FOR EACH ROW
+        EXECUTE FUNCTION record_subscription_status_change();
+    CREATE TRIGGER subscription_status_changed
+        AFTER UPDATE OF status ON subscriptions
+        FOR EACH ROW
+        WHEN (OLD.status IS DISTINCT FROM NEW.status)
+        EXECUTE FUNCTION record_subscription_status_change();
+
+    -- We only know the current status of existing subscribers
+    INSERT INTO subscription_status_changes (subscriber_id, status, changed_at)
+        SELECT id, status, subscribed_at FROM subscriptions;
+COMMIT;
@@ -0,0 +1,26 @@
+-- Add migration script here
+BEGIN;
+    CREATE TABLE subscriber_imports (
+        import_id uuid PRIMARY KEY,
+        author_id uuid NULL REFERENCES users (user_id),
+        -- The status imported subscribers were given
+        status TEXT NOT NULL,
+        n_imported INT NOT NULL DEFAULT 0,
+        n_rejected INT NOT NULL DEFAULT 0,
+        -- Pending subscribers we could not send a confirmation email to
+        n_unsent_confirmations INT NOT NULL DEFAULT 0,
+        started_at timestamptz NOT NULL,
+        finished_at timestamptz NULL
+    );
+
+    -- The lines of an import that did not make it, and why
+    CREATE TABLE subscriber_import_rejections (
+        import_id uuid NOT NULL
+            REFERENCES subscriber_imports (import_id) ON DELETE CASCADE,
+        line BIGINT NOT NULL,
+        email TEXT NOT NULL,
+        name TEXT NOT NULL,
+        reason TEXT NOT NULL,
+        PRIMARY KEY (import_id, line)
+    );
+COMMIT;
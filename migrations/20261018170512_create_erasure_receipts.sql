@@ -0,0 +1,24 @@
+-- Add migration script here
+BEGIN;
+    -- The opens and clicks of erased subscribers are kept under a pseudonym,
+    -- for the statistics of past issues to stay the same
+    ALTER TABLE issue_engagement_events
+        DROP CONSTRAINT issue_engagement_events_subscriber_id_fkey;
+
+    -- One receipt per erasure. Every receipt is signed together with the one
+    -- before it, so that a receipt can't be edited or removed unnoticed.
+    CREATE TABLE erasure_receipts (
+        id BIGSERIAL PRIMARY KEY,
+        erasure_id uuid NOT NULL UNIQUE,
+        -- A keyed hash of the lowercased address: it tells whether an address
+        -- was erased, without keeping the address around
+        email_digest TEXT NOT NULL,
+        requested_by TEXT NOT NULL,
+        erased_at timestamptz NOT NULL,
+        -- How many rows were deleted or anonymised, per table, as JSON
+        affected_rows TEXT NOT NULL,
+        previous_signature TEXT NULL,
+        signature TEXT NOT NULL
+    );
+    CREATE INDEX erasure_receipts_email_digest_idx ON erasure_receipts (email_digest);
+COMMIT;
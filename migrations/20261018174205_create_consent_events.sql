@@ -0,0 +1,17 @@
+-- Add migration script here
+-- When, where and how subscribers agreed to hear from us
+CREATE TABLE consent_events (
+    id BIGSERIAL PRIMARY KEY,
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id) ON DELETE CASCADE,
+    -- subscribed, confirmed or imported
+    kind TEXT NOT NULL,
+    -- The form that was filled in, the import the subscriber came from...
+    source TEXT NOT NULL,
+    ip_address TEXT NULL,
+    user_agent TEXT NULL,
+    -- The version of the privacy policy in force at the time
+    privacy_policy_version TEXT NULL,
+    occurred_at timestamptz NOT NULL
+);
+CREATE INDEX consent_events_subscriber_idx ON consent_events (subscriber_id);
@@ -0,0 +1,19 @@
+-- Add migration script here
+BEGIN;
+    -- Only a SHA-256 digest of each token is kept: a leaked table should
+    -- not be enough to confirm anyone
+    ALTER TABLE subscription_tokens RENAME COLUMN subscription_token TO token_hash;
+    UPDATE subscription_tokens
+        SET token_hash = encode(sha256(convert_to(token_hash, 'UTF8')), 'hex');
+    ALTER TABLE subscription_tokens
+        ADD COLUMN created_at timestamptz NULL,
+        ADD COLUMN expires_at timestamptz NULL;
+    -- We don't know when the existing tokens were sent: they get a full
+    -- lifetime from now
+    UPDATE subscription_tokens
+        SET created_at = now(), expires_at = now() + interval '7 days';
+    ALTER TABLE subscription_tokens
+        ALTER COLUMN created_at SET NOT NULL,
+        ALTER COLUMN expires_at SET NOT NULL;
+    CREATE INDEX subscription_tokens_subscriber_idx ON subscription_tokens (subscriber_id);
+COMMIT;
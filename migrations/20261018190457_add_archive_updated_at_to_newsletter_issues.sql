@@ -0,0 +1,4 @@
+-- Add migration script here
+-- When an issue last entered or left the public archive, for the feeds to
+-- know they changed even though nothing was published
+ALTER TABLE newsletter_issues ADD COLUMN archive_updated_at timestamptz NULL;
@@ -0,0 +1,9 @@
+-- Add migration script here
+-- Imported subscribers waiting for the delivery worker to send them a
+-- confirmation email
+CREATE TABLE confirmation_email_queue (
+    subscriber_id uuid PRIMARY KEY
+        REFERENCES subscriptions (id) ON DELETE CASCADE,
+    import_id uuid NOT NULL
+        REFERENCES subscriber_imports (import_id) ON DELETE CASCADE
+);
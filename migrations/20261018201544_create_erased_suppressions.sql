@@ -0,0 +1,13 @@
+-- Add migration script here
+BEGIN;
+    -- For `hmac`, see `routes::enqueue_delivery_tasks`
+    CREATE EXTENSION IF NOT EXISTS pgcrypto;
+
+    -- The suppressions of addresses that have been erased: all that is left
+    -- of the address is its digest, see `erasure::email_digest`
+    CREATE TABLE erased_suppressions (
+        email_digest TEXT PRIMARY KEY,
+        reason TEXT NOT NULL,
+        suppressed_at timestamptz NOT NULL
+    );
+COMMIT;
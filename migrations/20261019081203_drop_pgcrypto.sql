@@ -0,0 +1,3 @@
+-- Add migration script here
+-- Email digests are only computed in Rust now, see `erasure::email_digest`
+DROP EXTENSION IF EXISTS pgcrypto;
@@ -0,0 +1,3 @@
+-- Add migration script here
+-- Subscribers are looked up by address regardless of its case
+CREATE INDEX subscriptions_lower_email_idx ON subscriptions (lower(email));
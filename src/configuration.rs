@@ -1,11 +1,18 @@
+use std::net::IpAddr;
+
 use secrecy::{ExposeSecret, Secret};
-use serde_aux::field_attributes::deserialize_number_from_string;
+use serde_aux::field_attributes::{
+    deserialize_number_from_string, deserialize_vec_from_string_or_vec,
+};
 use sqlx::{
     postgres::{PgConnectOptions, PgSslMode},
     ConnectOptions,
 };
 
-use crate::{domain::SubscriberEmail, email_client::EmailClient};
+use crate::{
+    domain::SubscriberEmail,
+    email_client::{EmailClient, OutboxTransport, PostmarkTransport, SmtpTransport},
+};
 
 #[derive(serde::Deserialize, Clone)]
 pub struct Settings {
@@ -13,26 +20,123 @@ pub struct Settings {
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
     pub redis_uri: Secret<String>,
+    pub delivery_worker: DeliveryWorkerSettings,
+    pub postmark_webhook: PostmarkWebhookSettings,
+    /// Set from `APP_ENVIRONMENT`, not from the configuration files.
+    pub environment: Environment,
+}
+
+impl Settings {
+    /// The client for the configured email transport.
+    pub fn email_client(&self) -> EmailClient {
+        self.email_client
+            .clone()
+            .client(self.postmark_webhook.delivery_events_enabled)
+    }
+}
+
+/// The basic auth credentials Postmark must use to call our webhooks.
+#[derive(serde::Deserialize, Clone)]
+pub struct PostmarkWebhookSettings {
+    pub username: String,
+    pub password: Secret<String>,
+    /// Whether the Delivery webhook is enabled on the Postmark server, next
+    /// to the Bounce and SpamComplaint ones. Without it, an email Postmark
+    /// accepted counts as delivered.
+    #[serde(default)]
+    pub delivery_events_enabled: bool,
 }
 
 #[derive(serde::Deserialize, Clone)]
 pub struct EmailClientSettings {
+    #[serde(default)]
+    pub transport: EmailTransportKind,
     pub base_url: String,
     pub sender_email: String,
     pub authorization_token: Secret<String>,
     pub timeout_milliseconds: u64,
+    /// Only required when `transport` is `smtp`.
+    pub smtp: Option<SmtpSettings>,
+    /// Only required when `transport` is `outbox`.
+    pub outbox: Option<OutboxSettings>,
+}
+
+/// How emails leave the application.
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailTransportKind {
+    /// Postmark's HTTP API, reached at `base_url`.
+    #[default]
+    Postmark,
+    /// An SMTP relay, as described by the `smtp` settings.
+    Smtp,
+    /// Files in a local directory, as described by the `outbox` settings.
+    Outbox,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct OutboxSettings {
+    /// Where emails are written, one JSON file each.
+    pub directory: String,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct SmtpSettings {
+    pub host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    /// Authenticate with AUTH PLAIN or AUTH LOGIN if set.
+    pub username: Option<String>,
+    pub password: Option<Secret<String>>,
+    #[serde(default)]
+    pub tls: SmtpTls,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    /// Upgrade the connection with STARTTLS, usually on port 587.
+    #[default]
+    StartTls,
+    /// Speak TLS from the start, usually on port 465.
+    Implicit,
+    /// No encryption at all: only sensible for a local relay.
+    Plaintext,
 }
 
 impl EmailClientSettings {
-    pub fn client(self) -> EmailClient {
+    /// `delivery_events_enabled` is only relevant to Postmark, see
+    /// `PostmarkWebhookSettings`.
+    pub fn client(self, delivery_events_enabled: bool) -> EmailClient {
         let sender_email = self.sender().expect("invalid sender email address");
         let timeout = self.timeout();
-        EmailClient::new(
-            self.base_url,
-            sender_email,
-            self.authorization_token,
-            timeout,
-        )
+        match self.transport {
+            EmailTransportKind::Postmark => EmailClient::new(
+                sender_email,
+                PostmarkTransport::new(self.base_url, self.authorization_token, timeout)
+                    .with_delivery_webhooks(delivery_events_enabled),
+            ),
+            EmailTransportKind::Smtp => {
+                let settings = self.smtp.expect("missing SMTP settings");
+                let transport =
+                    SmtpTransport::new(&settings, timeout).expect("invalid SMTP settings");
+                EmailClient::new(sender_email, transport)
+            }
+            EmailTransportKind::Outbox => {
+                let transport = self.outbox().expect("missing outbox settings");
+                EmailClient::new(sender_email, transport)
+            }
+        }
+    }
+
+    /// The outbox emails are written to, if the outbox transport is in use.
+    pub fn outbox(&self) -> Option<OutboxTransport> {
+        match (self.transport, &self.outbox) {
+            (EmailTransportKind::Outbox, Some(settings)) => {
+                Some(OutboxTransport::new(&settings.directory))
+            }
+            _ => None,
+        }
     }
 
     pub fn sender(&self) -> Result<SubscriberEmail, String> {
@@ -44,6 +148,19 @@ impl EmailClientSettings {
     }
 }
 
+#[derive(serde::Deserialize, Clone)]
+pub struct DeliveryWorkerSettings {
+    /// How many emails can be in flight at the same time.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub concurrency: usize,
+    /// How many delivery tasks are claimed from the queue in one go.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub batch_size: i64,
+    /// How many soft bounces in a row make a subscriber inactive.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub soft_bounce_threshold: i16,
+}
+
 #[derive(serde::Deserialize, Clone)]
 pub struct ApplicationSettings {
     #[serde(deserialize_with = "deserialize_number_from_string")]
@@ -51,6 +168,21 @@ pub struct ApplicationSettings {
     pub host: String,
     pub base_url: String,
     pub hmac_secret: Secret<String>,
+    /// How long the server and the delivery worker get to finish their
+    /// in-flight work once a shutdown has been requested.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub shutdown_timeout_seconds: u64,
+    pub privacy_policy_version: String,
+    /// The proxies in front of the application, whose forwarding headers
+    /// can be believed about where a request came from.
+    #[serde(default, deserialize_with = "deserialize_vec_from_string_or_vec")]
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+impl ApplicationSettings {
+    pub fn shutdown_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.shutdown_timeout_seconds)
+    }
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -102,10 +234,13 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
         config::File::from(configuration_directory.join(environment.as_str())).required(true),
     )?;
     settings.merge(config::Environment::with_prefix("app").separator("__"))?;
+    settings.set("environment", environment.as_str())?;
 
     settings.try_into()
 }
 
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
 pub enum Environment {
     Local,
     Production,
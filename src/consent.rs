@@ -0,0 +1,155 @@
+use std::net::{IpAddr, SocketAddr};
+
+use actix_web::{web, HttpRequest};
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+use crate::startup::TrustedProxies;
+
+/// How long a form-provided source or a user agent can be: they are free
+/// text coming from whoever sent the request.
+const MAX_FIELD_LENGTH: usize = 256;
+
+#[derive(Debug, Clone, Copy)]
+pub enum ConsentKind {
+    /// The subscription form was filled in.
+    Subscribed,
+    /// The confirmation link was followed, or an admin confirmed on the
+    /// subscriber's behalf.
+    Confirmed,
+    /// The subscriber came from a list imported by an admin.
+    Imported,
+}
+
+impl ConsentKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConsentKind::Subscribed => "subscribed",
+            ConsentKind::Confirmed => "confirmed",
+            ConsentKind::Imported => "imported",
+        }
+    }
+}
+
+/// One step of the proof that a subscriber agreed to hear from us.
+#[derive(Debug)]
+pub struct ConsentEvent {
+    pub kind: ConsentKind,
+    pub source: String,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub privacy_policy_version: Option<String>,
+}
+
+impl ConsentEvent {
+    /// An event for what `request` did, recorded with where it came from.
+    pub fn from_request(
+        kind: ConsentKind,
+        source: &str,
+        request: &HttpRequest,
+        privacy_policy_version: &str,
+    ) -> Self {
+        let user_agent = request
+            .headers()
+            .get("User-Agent")
+            .and_then(|h| h.to_str().ok())
+            .map(truncate);
+        Self {
+            kind,
+            source: truncate(source),
+            ip_address: client_ip(request),
+            user_agent,
+            privacy_policy_version: Some(privacy_policy_version.to_owned()),
+        }
+    }
+}
+
+/// The address the request came from. Forwarding headers are only believed
+/// when a trusted proxy set them: anybody can, and the trail must hold up as
+/// evidence.
+fn client_ip(request: &HttpRequest) -> Option<String> {
+    let peer = request.peer_addr()?.ip();
+    let is_trusted = request
+        .app_data::<web::Data<TrustedProxies>>()
+        .is_some_and(|proxies| proxies.0.contains(&peer));
+    if !is_trusted {
+        return Some(peer.to_string());
+    }
+    let connection_info = request.connection_info();
+    let forwarded = connection_info.realip_remote_addr().unwrap_or_default();
+    // The proxy may have forwarded the client's port along with its address
+    let ip = forwarded
+        .parse::<IpAddr>()
+        .or_else(|_| forwarded.parse::<SocketAddr>().map(|a| a.ip()))
+        .unwrap_or(peer);
+    Some(ip.to_string())
+}
+
+fn truncate(s: &str) -> String {
+    s.chars().take(MAX_FIELD_LENGTH).collect()
+}
+
+#[tracing::instrument(name = "record a consent event", skip(executor))]
+pub async fn record_consent_event(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+    event: &ConsentEvent,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO consent_events (
+            subscriber_id,
+            kind,
+            source,
+            ip_address,
+            user_agent,
+            privacy_policy_version,
+            occurred_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+        subscriber_id,
+        event.kind.as_str(),
+        event.source,
+        event.ip_address,
+        event.user_agent,
+        event.privacy_policy_version,
+        Utc::now()
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// A consent event as it was recorded.
+#[derive(Debug, serde::Serialize)]
+pub struct ConsentRecord {
+    pub kind: String,
+    pub source: String,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub privacy_policy_version: Option<String>,
+    pub occurred_at: DateTime<Utc>,
+}
+
+/// Every consent event of a subscriber, oldest first.
+#[tracing::instrument(name = "get the consent trail of a subscriber", skip(executor))]
+pub async fn get_consent_trail(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+) -> Result<Vec<ConsentRecord>, sqlx::Error> {
+    sqlx::query_as!(
+        ConsentRecord,
+        r#"
+        SELECT kind, source, ip_address, user_agent, privacy_policy_version, occurred_at
+        FROM consent_events
+        WHERE subscriber_id = $1
+        ORDER BY id
+        "#,
+        subscriber_id
+    )
+    .fetch_all(executor)
+    .await
+}
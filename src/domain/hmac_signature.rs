@@ -0,0 +1,59 @@
+use anyhow::Context;
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+
+fn mac(secret: &Secret<String>, parts: &[&[u8]]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.expose_secret().as_bytes())
+        .expect("HMAC can take a key of any size");
+    for part in parts {
+        mac.update(part);
+    }
+    mac
+}
+
+/// The hex encoded HMAC-SHA256 of `parts`, one after the other. Start them
+/// with what is signed, e.g. `b"unsubscribe:"`, so that a signature made
+/// for one purpose can't be used for another.
+pub fn hmac_sign(secret: &Secret<String>, parts: &[&[u8]]) -> String {
+    hex::encode(mac(secret, parts).finalize().into_bytes())
+}
+
+/// Checks, in constant time, that `signature` is what `hmac_sign` gives for
+/// `parts`.
+pub fn hmac_verify(
+    secret: &Secret<String>,
+    parts: &[&[u8]],
+    signature: &str,
+) -> Result<(), anyhow::Error> {
+    let tag = hex::decode(signature).context("the signature is not valid hex")?;
+    mac(secret, parts)
+        .verify_slice(&tag)
+        .context("the signature does not match")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_ok};
+    use secrecy::Secret;
+
+    use super::{hmac_sign, hmac_verify};
+
+    fn secret() -> Secret<String> {
+        Secret::new("a-long-and-very-secret-key".to_string())
+    }
+
+    #[test]
+    fn a_signature_is_valid_for_what_was_signed() {
+        let signature = hmac_sign(&secret(), &[b"test:", b"message"]);
+        assert_ok!(hmac_verify(&secret(), &[b"test:", b"message"], &signature));
+    }
+
+    #[test]
+    fn a_signature_is_rejected_for_anything_else() {
+        let signature = hmac_sign(&secret(), &[b"test:", b"message"]);
+        assert_err!(hmac_verify(&secret(), &[b"test:", b"massage"], &signature));
+        assert_err!(hmac_verify(&secret(), &[b"test:", b"message"], "not-hex"));
+    }
+}
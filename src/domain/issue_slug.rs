@@ -0,0 +1,60 @@
+use uuid::Uuid;
+
+/// The human-readable part of the permalink of a published issue,
+/// e.g. `spring-news-3f2a9c1d`.
+///
+/// The title alone is not enough to tell issues apart, so we append the
+/// beginning of the issue id.
+#[derive(Debug)]
+pub struct IssueSlug(String);
+
+impl IssueSlug {
+    pub fn new(title: &str, newsletter_issue_id: Uuid) -> Self {
+        let mut slug = String::new();
+        for word in title
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|w| !w.is_empty())
+        {
+            slug.push_str(&word.to_ascii_lowercase());
+            slug.push('-');
+        }
+        let id = newsletter_issue_id.to_simple().to_string();
+        slug.push_str(&id[..8]);
+        Self(slug)
+    }
+}
+
+impl AsRef<str> for IssueSlug {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::IssueSlug;
+
+    fn issue_id() -> Uuid {
+        Uuid::parse_str("3f2a9c1d-0000-4000-8000-000000000000").unwrap()
+    }
+
+    #[test]
+    fn the_title_is_lowercased_and_joined_with_dashes() {
+        let slug = IssueSlug::new("Spring News, 2022 edition!", issue_id());
+        assert_eq!(slug.as_ref(), "spring-news-2022-edition-3f2a9c1d");
+    }
+
+    #[test]
+    fn characters_that_are_not_url_safe_are_dropped() {
+        let slug = IssueSlug::new("Ünïcödé & <html>", issue_id());
+        assert_eq!(slug.as_ref(), "n-c-d-html-3f2a9c1d");
+    }
+
+    #[test]
+    fn an_empty_title_still_gives_a_slug() {
+        let slug = IssueSlug::new("", issue_id());
+        assert_eq!(slug.as_ref(), "3f2a9c1d");
+    }
+}
@@ -1,7 +1,15 @@
+mod hmac_signature;
+mod issue_slug;
 mod new_subscriber;
 mod subscriber_email;
 mod subscriber_name;
+mod tracking_token;
+mod unsubscribe_token;
 
+pub use hmac_signature::*;
+pub use issue_slug::*;
 pub use new_subscriber::*;
 pub use subscriber_email::*;
 pub use subscriber_name::*;
+pub use tracking_token::*;
+pub use unsubscribe_token::*;
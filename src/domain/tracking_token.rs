@@ -0,0 +1,132 @@
+use anyhow::Context;
+use secrecy::Secret;
+use uuid::Uuid;
+
+use super::{hmac_sign, hmac_verify};
+
+/// An HMAC signature binding a tracking link to an issue, a subscriber and,
+/// for clicks, the destination of the link. Without it the click endpoint
+/// would be an open redirect, and anybody could make up engagement data.
+#[derive(Debug)]
+pub struct TrackingToken(String);
+
+impl TrackingToken {
+    pub fn generate(
+        newsletter_issue_id: Uuid,
+        subscriber_id: Uuid,
+        url: Option<&str>,
+        secret: &Secret<String>,
+    ) -> Self {
+        let parts = Self::signed_parts(&newsletter_issue_id, &subscriber_id, url);
+        Self(hmac_sign(secret, &parts))
+    }
+
+    /// Checks, in constant time, that `token` was issued for this link.
+    pub fn verify(
+        token: &str,
+        newsletter_issue_id: Uuid,
+        subscriber_id: Uuid,
+        url: Option<&str>,
+        secret: &Secret<String>,
+    ) -> Result<(), anyhow::Error> {
+        let parts = Self::signed_parts(&newsletter_issue_id, &subscriber_id, url);
+        hmac_verify(secret, &parts, token).context("the tracking token does not match the link")
+    }
+
+    fn signed_parts<'a>(
+        newsletter_issue_id: &'a Uuid,
+        subscriber_id: &'a Uuid,
+        url: Option<&'a str>,
+    ) -> Vec<&'a [u8]> {
+        match url {
+            Some(url) => vec![
+                b"click:",
+                newsletter_issue_id.as_bytes(),
+                subscriber_id.as_bytes(),
+                url.as_bytes(),
+            ],
+            None => vec![
+                b"open:",
+                newsletter_issue_id.as_bytes(),
+                subscriber_id.as_bytes(),
+            ],
+        }
+    }
+}
+
+impl AsRef<str> for TrackingToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_ok};
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    use super::TrackingToken;
+
+    fn secret() -> Secret<String> {
+        Secret::new("a-long-and-very-secret-key".to_string())
+    }
+
+    #[test]
+    fn a_generated_token_is_valid_for_its_link() {
+        let (issue_id, subscriber_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let url = Some("https://example.com/");
+        let token = TrackingToken::generate(issue_id, subscriber_id, url, &secret());
+        assert_ok!(TrackingToken::verify(
+            token.as_ref(),
+            issue_id,
+            subscriber_id,
+            url,
+            &secret()
+        ));
+    }
+
+    #[test]
+    fn a_token_is_rejected_for_a_different_destination() {
+        let (issue_id, subscriber_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let token = TrackingToken::generate(
+            issue_id,
+            subscriber_id,
+            Some("https://example.com/"),
+            &secret(),
+        );
+        assert_err!(TrackingToken::verify(
+            token.as_ref(),
+            issue_id,
+            subscriber_id,
+            Some("https://evil.example.com/"),
+            &secret()
+        ));
+    }
+
+    #[test]
+    fn an_open_token_is_not_a_click_token() {
+        let (issue_id, subscriber_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let token = TrackingToken::generate(issue_id, subscriber_id, None, &secret());
+        assert_err!(TrackingToken::verify(
+            token.as_ref(),
+            issue_id,
+            subscriber_id,
+            Some(""),
+            &secret()
+        ));
+    }
+
+    #[test]
+    fn a_token_is_rejected_for_a_different_subscriber() {
+        let issue_id = Uuid::new_v4();
+        let token = TrackingToken::generate(issue_id, Uuid::new_v4(), None, &secret());
+        assert_err!(TrackingToken::verify(
+            token.as_ref(),
+            issue_id,
+            Uuid::new_v4(),
+            None,
+            &secret()
+        ));
+    }
+}
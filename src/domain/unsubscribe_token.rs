@@ -0,0 +1,90 @@
+use anyhow::Context;
+use secrecy::Secret;
+use uuid::Uuid;
+
+use super::{hmac_sign, hmac_verify};
+
+/// An HMAC signature binding an unsubscribe link to a single subscriber.
+/// Without it anybody could unsubscribe someone else by guessing their id.
+#[derive(Debug)]
+pub struct UnsubscribeToken(String);
+
+impl UnsubscribeToken {
+    pub fn generate(subscriber_id: Uuid, secret: &Secret<String>) -> Self {
+        Self(hmac_sign(
+            secret,
+            &[b"unsubscribe:", subscriber_id.as_bytes()],
+        ))
+    }
+
+    /// Checks, in constant time, that `token` was issued for `subscriber_id`.
+    pub fn verify(
+        token: &str,
+        subscriber_id: Uuid,
+        secret: &Secret<String>,
+    ) -> Result<(), anyhow::Error> {
+        hmac_verify(secret, &[b"unsubscribe:", subscriber_id.as_bytes()], token)
+            .context("the unsubscribe token does not match the subscriber")
+    }
+}
+
+impl AsRef<str> for UnsubscribeToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_ok};
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    use super::UnsubscribeToken;
+
+    fn secret() -> Secret<String> {
+        Secret::new("a-long-and-very-secret-key".to_string())
+    }
+
+    #[test]
+    fn a_generated_token_is_valid_for_its_subscriber() {
+        let subscriber_id = Uuid::new_v4();
+        let token = UnsubscribeToken::generate(subscriber_id, &secret());
+        assert_ok!(UnsubscribeToken::verify(
+            token.as_ref(),
+            subscriber_id,
+            &secret()
+        ));
+    }
+
+    #[test]
+    fn a_token_is_rejected_for_a_different_subscriber() {
+        let token = UnsubscribeToken::generate(Uuid::new_v4(), &secret());
+        assert_err!(UnsubscribeToken::verify(
+            token.as_ref(),
+            Uuid::new_v4(),
+            &secret()
+        ));
+    }
+
+    #[test]
+    fn a_token_signed_with_a_different_secret_is_rejected() {
+        let subscriber_id = Uuid::new_v4();
+        let token = UnsubscribeToken::generate(subscriber_id, &secret());
+        let other_secret = Secret::new("another-secret-key".to_string());
+        assert_err!(UnsubscribeToken::verify(
+            token.as_ref(),
+            subscriber_id,
+            &other_secret
+        ));
+    }
+
+    #[test]
+    fn a_token_that_is_not_hex_is_rejected() {
+        assert_err!(UnsubscribeToken::verify(
+            "not-a-token",
+            Uuid::new_v4(),
+            &secret()
+        ));
+    }
+}
@@ -0,0 +1,166 @@
+mod outbox;
+mod postmark;
+mod smtp;
+
+pub use outbox::{OutboxEmail, OutboxTransport};
+pub use postmark::PostmarkTransport;
+pub use smtp::SmtpTransport;
+
+use crate::domain::SubscriberEmail;
+
+/// An outgoing email, as handed over to an `EmailTransport`.
+pub struct Email<'a> {
+    pub from: &'a SubscriberEmail,
+    pub to: &'a SubscriberEmail,
+    pub subject: &'a str,
+    pub html_content: &'a str,
+    pub text_content: &'a str,
+    pub headers: &'a [EmailHeader],
+}
+
+/// Something that can deliver emails: an email API, an SMTP relay, ...
+#[async_trait::async_trait]
+pub trait EmailTransport: Send + Sync {
+    /// Returns the id the transport assigned to the message, if any.
+    async fn send(&self, email: &Email<'_>) -> Result<Option<String>, anyhow::Error>;
+
+    /// Whether `send_batch` sends the emails with a single call, rather than
+    /// one at a time.
+    fn supports_batch(&self) -> bool {
+        false
+    }
+
+    /// Whether the provider tells us, through a webhook, when an email has
+    /// actually been delivered - rather than just accepted for delivery.
+    fn has_delivery_webhooks(&self) -> bool {
+        false
+    }
+
+    /// Send several emails, returning the outcome of each of them, in order.
+    /// Transports without a batch API send them one at a time.
+    async fn send_batch(&self, emails: &[Email<'_>]) -> Vec<Result<Option<String>, anyhow::Error>> {
+        let mut outcomes = Vec::with_capacity(emails.len());
+        for email in emails {
+            outcomes.push(self.send(email).await);
+        }
+        outcomes
+    }
+}
+
+/// The provider refused an email because of its recipient - a mailbox that
+/// does not exist or is full, an address it won't send to - rather than
+/// because it is down or we are misconfigured. Transports return it, wrapped
+/// in an `anyhow::Error`, so that only these count as soft bounces.
+#[derive(thiserror::Error, Debug)]
+#[error("the recipient was rejected: {0}")]
+pub struct RecipientRejected(pub String);
+
+/// One of the emails of a batch, see `EmailClient::send_batch`.
+pub struct BatchEmail<'a> {
+    pub recipient: &'a SubscriberEmail,
+    pub subject: &'a str,
+    pub html_content: &'a str,
+    pub text_content: &'a str,
+    pub headers: &'a [EmailHeader],
+}
+
+/// Sends emails on behalf of our sender address, through whichever
+/// transport has been configured.
+pub struct EmailClient {
+    sender: SubscriberEmail,
+    transport: Box<dyn EmailTransport>,
+}
+
+impl EmailClient {
+    pub fn new(sender: SubscriberEmail, transport: impl EmailTransport + 'static) -> Self {
+        Self {
+            sender,
+            transport: Box::new(transport),
+        }
+    }
+
+    pub fn sender(&self) -> &SubscriberEmail {
+        &self.sender
+    }
+
+    /// Whether `send_batch` is any cheaper than sending the emails one by one.
+    pub fn supports_batch(&self) -> bool {
+        self.transport.supports_batch()
+    }
+
+    pub async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<(), anyhow::Error> {
+        self.send_email_with_headers(recipient, subject, html_content, text_content, &[])
+            .await?;
+        Ok(())
+    }
+
+    /// Returns the id the transport assigned to the message, if it told us.
+    pub async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[EmailHeader],
+    ) -> Result<Option<String>, anyhow::Error> {
+        let email = Email {
+            from: &self.sender,
+            to: recipient,
+            subject,
+            html_content,
+            text_content,
+            headers,
+        };
+        self.transport.send(&email).await
+    }
+
+    /// Whether the provider tells us when an email reached its recipient,
+    /// see `routes::webhooks`.
+    pub fn has_delivery_webhooks(&self) -> bool {
+        self.transport.has_delivery_webhooks()
+    }
+
+    /// Send several emails at once, which is a lot cheaper than sending them
+    /// one by one with some transports. Each email succeeds or fails on its
+    /// own: the outcomes are returned in the same order as the emails.
+    pub async fn send_batch(
+        &self,
+        emails: &[BatchEmail<'_>],
+    ) -> Vec<Result<Option<String>, anyhow::Error>> {
+        let emails: Vec<_> = emails
+            .iter()
+            .map(|e| Email {
+                from: &self.sender,
+                to: e.recipient,
+                subject: e.subject,
+                html_content: e.html_content,
+                text_content: e.text_content,
+                headers: e.headers,
+            })
+            .collect();
+        self.transport.send_batch(&emails).await
+    }
+}
+
+/// A custom header to be added to an outgoing email.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct EmailHeader {
+    pub name: String,
+    pub value: String,
+}
+
+impl EmailHeader {
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            value: value.into(),
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use super::{Email, EmailHeader, EmailTransport};
+
+/// Writes every email as a JSON file into a directory instead of sending
+/// it, so that it can be looked at without any network access.
+#[derive(Clone)]
+pub struct OutboxTransport {
+    directory: PathBuf,
+}
+
+/// An email sitting in the outbox.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct OutboxEmail {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub from: String,
+    pub to: String,
+    pub subject: String,
+    pub html_content: String,
+    pub text_content: String,
+    pub headers: Vec<EmailHeader>,
+}
+
+impl OutboxTransport {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+        }
+    }
+
+    fn path(&self, id: Uuid) -> PathBuf {
+        self.directory.join(format!("{}.json", id))
+    }
+
+    /// All the emails in the outbox, the most recent first.
+    pub async fn emails(&self) -> Result<Vec<OutboxEmail>, anyhow::Error> {
+        let mut entries = match tokio::fs::read_dir(&self.directory).await {
+            Ok(entries) => entries,
+            // Nothing has been sent yet
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).context("failed to read the outbox directory"),
+        };
+        let mut emails = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            let contents = tokio::fs::read(&path).await?;
+            match serde_json::from_slice(&contents) {
+                Ok(email) => emails.push(email),
+                Err(e) => tracing::warn!(
+                    error.message = %e,
+                    path = %path.display(),
+                    "skipping a file of the outbox that is not an email"
+                ),
+            }
+        }
+        emails.sort_by_key(|e: &OutboxEmail| std::cmp::Reverse(e.created_at));
+        Ok(emails)
+    }
+
+    pub async fn email(&self, id: Uuid) -> Result<Option<OutboxEmail>, anyhow::Error> {
+        let contents = match tokio::fs::read(self.path(id)).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).context("failed to read an email from the outbox"),
+        };
+        let email = serde_json::from_slice(&contents).context("failed to parse an outbox email")?;
+        Ok(Some(email))
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for OutboxTransport {
+    /// Returns the id of the email in the outbox.
+    async fn send(&self, email: &Email<'_>) -> Result<Option<String>, anyhow::Error> {
+        let email = OutboxEmail {
+            id: Uuid::new_v4(),
+            created_at: Utc::now(),
+            from: email.from.as_ref().to_owned(),
+            to: email.to.as_ref().to_owned(),
+            subject: email.subject.to_owned(),
+            html_content: email.html_content.to_owned(),
+            text_content: email.text_content.to_owned(),
+            headers: email.headers.to_vec(),
+        };
+        tokio::fs::create_dir_all(&self.directory)
+            .await
+            .context("failed to create the outbox directory")?;
+        tokio::fs::write(self.path(email.id), serde_json::to_vec_pretty(&email)?)
+            .await
+            .context("failed to write an email to the outbox")?;
+        Ok(Some(email.id.to_string()))
+    }
+}
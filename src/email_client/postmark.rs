@@ -0,0 +1,520 @@
+use reqwest::{Client, StatusCode};
+use secrecy::{ExposeSecret, Secret};
+
+use super::{Email, EmailHeader, EmailTransport, RecipientRejected};
+
+/// The most messages Postmark accepts in a single batch.
+const MAX_BATCH_SIZE: usize = 500;
+/// The error codes Postmark uses when the problem is the recipient: an
+/// invalid address (300) or one that bounced or complained before (406).
+/// Any other code is about our account or our request.
+const RECIPIENT_ERROR_CODES: [i64; 2] = [300, 406];
+
+fn rejection(error_code: i64, message: &str) -> anyhow::Error {
+    let message = format!(
+        "Postmark rejected the email (error code {}): {}",
+        error_code, message
+    );
+    if RECIPIENT_ERROR_CODES.contains(&error_code) {
+        anyhow::Error::new(RecipientRejected(message))
+    } else {
+        anyhow::anyhow!(message)
+    }
+}
+
+/// Delivers emails through Postmark's HTTP API.
+pub struct PostmarkTransport {
+    http_client: Client,
+    base_url: String,
+    authorization_token: Secret<String>,
+    delivery_webhooks: bool,
+}
+
+impl PostmarkTransport {
+    pub fn new(
+        base_url: String,
+        authorization_token: Secret<String>,
+        timeout: std::time::Duration,
+    ) -> Self {
+        let http_client = Client::builder().timeout(timeout).build().unwrap();
+
+        Self {
+            http_client,
+            base_url,
+            authorization_token,
+            delivery_webhooks: false,
+        }
+    }
+
+    /// Tell whether the Delivery webhook is enabled on the Postmark server,
+    /// see `routes::webhooks`. It is not, by default.
+    pub fn with_delivery_webhooks(mut self, enabled: bool) -> Self {
+        self.delivery_webhooks = enabled;
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for PostmarkTransport {
+    /// Returns the id Postmark assigned to the message, if it told us.
+    async fn send(&self, email: &Email<'_>) -> Result<Option<String>, anyhow::Error> {
+        let url = format!("{}/email", self.base_url);
+        let request_body = SendEmailRequest::new(email);
+        let response = self
+            .http_client
+            .post(&url)
+            .header(
+                "X-Postmark-Server-Token",
+                self.authorization_token.expose_secret(),
+            )
+            .json(&request_body)
+            .send()
+            .await?;
+        // Postmark tells why it refused an email in the body of a 422
+        if response.status() == StatusCode::UNPROCESSABLE_ENTITY {
+            if let Ok(r) = response.json::<BatchEmailResult>().await {
+                return Err(rejection(r.error_code, &r.message));
+            }
+            anyhow::bail!("Postmark rejected the email");
+        }
+        let response = response.error_for_status()?;
+
+        // The email has been accepted at this point: a body we can't make
+        // sense of shouldn't turn the delivery into a failure.
+        let message_id = response
+            .json::<SendEmailResponse>()
+            .await
+            .ok()
+            .map(|r| r.message_id);
+        Ok(message_id)
+    }
+
+    /// Uses Postmark's batch API, `MAX_BATCH_SIZE` emails per request.
+    fn supports_batch(&self) -> bool {
+        true
+    }
+
+    fn has_delivery_webhooks(&self) -> bool {
+        self.delivery_webhooks
+    }
+
+    async fn send_batch(&self, emails: &[Email<'_>]) -> Vec<Result<Option<String>, anyhow::Error>> {
+        let mut outcomes = Vec::with_capacity(emails.len());
+        for chunk in emails.chunks(MAX_BATCH_SIZE) {
+            match self.send_chunk(chunk).await {
+                Ok(chunk_outcomes) => outcomes.extend(chunk_outcomes),
+                Err(e) => {
+                    // The whole request failed: so did every email in it
+                    let error = e.to_string();
+                    outcomes.extend(chunk.iter().map(|_| Err(anyhow::anyhow!("{}", error))));
+                }
+            }
+        }
+        outcomes
+    }
+}
+
+impl PostmarkTransport {
+    async fn send_chunk(
+        &self,
+        emails: &[Email<'_>],
+    ) -> Result<Vec<Result<Option<String>, anyhow::Error>>, anyhow::Error> {
+        let url = format!("{}/email/batch", self.base_url);
+        let request_body: Vec<_> = emails.iter().map(SendEmailRequest::new).collect();
+        let response = self
+            .http_client
+            .post(&url)
+            .header(
+                "X-Postmark-Server-Token",
+                self.authorization_token.expose_secret(),
+            )
+            .json(&request_body)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        // Postmark answers with one result per email, in the same order.
+        // As for single emails, a body we can't make sense of is not a
+        // reason to send them again.
+        let results = response
+            .json::<Vec<BatchEmailResult>>()
+            .await
+            .unwrap_or_default();
+        let outcomes = (0..emails.len())
+            .map(|i| match results.get(i) {
+                Some(r) if r.error_code != 0 => Err(rejection(r.error_code, &r.message)),
+                Some(r) => Ok(r.message_id.clone()),
+                None => Ok(None),
+            })
+            .collect();
+        Ok(outcomes)
+    }
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    subject: &'a str,
+    html_body: &'a str,
+    text_body: &'a str,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    headers: &'a [EmailHeader],
+}
+
+impl<'a> SendEmailRequest<'a> {
+    fn new(email: &Email<'a>) -> Self {
+        Self {
+            from: email.from.as_ref(),
+            to: email.to.as_ref(),
+            subject: email.subject,
+            html_body: email.html_content,
+            text_body: email.text_content,
+            headers: email.headers,
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct BatchEmailResult {
+    error_code: i64,
+    #[serde(default)]
+    message: String,
+    #[serde(rename = "MessageID")]
+    message_id: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct SendEmailResponse {
+    #[serde(rename = "MessageID")]
+    message_id: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_ok};
+    use fake::faker::lorem::en::{Paragraph, Sentence};
+    use fake::Faker;
+    use fake::{faker::internet::en::SafeEmail, Fake};
+    use secrecy::Secret;
+    use wiremock::matchers::{any, header, header_exists, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::domain::SubscriberEmail;
+
+    use crate::email_client::{
+        BatchEmail, EmailClient, EmailHeader, PostmarkTransport, RecipientRejected,
+    };
+
+    struct SendEmailBodyMatcher;
+
+    impl wiremock::Match for SendEmailBodyMatcher {
+        fn matches(&self, request: &wiremock::Request) -> bool {
+            let result: Result<serde_json::Value, _> = serde_json::from_slice(&request.body);
+            if let Ok(body) = result {
+                body.get("From").is_some()
+                    && body.get("To").is_some()
+                    && body.get("Subject").is_some()
+                    && body.get("HtmlBody").is_some()
+                    && body.get("TextBody").is_some()
+            } else {
+                false
+            }
+        }
+    }
+
+    /// Generate a random email subject
+    fn subject() -> String {
+        Sentence(1..2).fake()
+    }
+
+    /// Generate random email content
+    fn content() -> String {
+        Paragraph(1..10).fake()
+    }
+
+    /// Generate a random subscriber email
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    /// Get a test instance of `EmailClient`
+    fn email_client(base_url: String) -> EmailClient {
+        let transport = PostmarkTransport::new(
+            base_url,
+            Secret::new(Faker.fake()),
+            std::time::Duration::from_millis(200),
+        );
+        EmailClient::new(email(), transport)
+    }
+
+    #[tokio::test]
+    async fn send_email_fires_a_request_to_base_url() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(header("Content-Type", "application/json"))
+            .and(path("/email"))
+            .and(method("POST"))
+            .and(SendEmailBodyMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let _ = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Mock expectations are checked on drop
+    }
+
+    struct HeadersMatcher;
+
+    impl wiremock::Match for HeadersMatcher {
+        fn matches(&self, request: &wiremock::Request) -> bool {
+            let result: Result<serde_json::Value, _> = serde_json::from_slice(&request.body);
+            if let Ok(body) = result {
+                body["Headers"]
+                    == serde_json::json!([{ "Name": "List-Unsubscribe-Post", "Value": "List-Unsubscribe=One-Click" }])
+            } else {
+                false
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn send_email_with_headers_forwards_the_custom_headers() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .and(HeadersMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let headers = [EmailHeader::new(
+            "List-Unsubscribe-Post",
+            "List-Unsubscribe=One-Click",
+        )];
+        let _ = email_client
+            .send_email_with_headers(&email(), &subject(), &content(), &content(), &headers)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn send_email_succeeds_if_the_server_returns_200() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_with_headers_returns_the_message_id_assigned_by_postmark() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        let response = ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "To": "receiver@example.com",
+            "SubmittedAt": "2014-02-17T07:25:01.4178645-05:00",
+            "MessageID": "0a129aee-e1cd-480d-b08d-4f48548ff48d",
+            "ErrorCode": 0,
+            "Message": "OK"
+        }));
+        Mock::given(any())
+            .respond_with(response)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email_with_headers(&email(), &subject(), &content(), &content(), &[])
+            .await;
+
+        assert_eq!(
+            outcome.unwrap().as_deref(),
+            Some("0a129aee-e1cd-480d-b08d-4f48548ff48d")
+        );
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_server_returns_500() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_tells_when_postmark_rejects_the_recipient() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        let response = ResponseTemplate::new(422).set_body_json(serde_json::json!({
+            "ErrorCode": 406,
+            "Message": "You tried to send to a recipient that has been marked as inactive."
+        }));
+        Mock::given(any())
+            .respond_with(response)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert!(outcome.unwrap_err().is::<RecipientRejected>());
+    }
+
+    #[tokio::test]
+    async fn send_email_times_out_if_the_server_takes_too_long() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        let response = ResponseTemplate::new(200).set_delay(std::time::Duration::from_secs(180));
+        Mock::given(any())
+            .respond_with(response)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_batch_fires_a_single_request_to_the_batch_endpoint() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        let response = ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            { "ErrorCode": 0, "Message": "OK", "MessageID": "first-id" },
+            { "ErrorCode": 0, "Message": "OK", "MessageID": "second-id" },
+        ]));
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(path("/email/batch"))
+            .and(method("POST"))
+            .respond_with(response)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let (subject, content) = (subject(), content());
+        let recipients = [email(), email()];
+        let batch: Vec<_> = recipients
+            .iter()
+            .map(|recipient| BatchEmail {
+                recipient,
+                subject: &subject,
+                html_content: &content,
+                text_content: &content,
+                headers: &[],
+            })
+            .collect();
+        let outcomes = email_client.send_batch(&batch).await;
+
+        let message_ids: Vec<_> = outcomes.into_iter().map(|o| o.unwrap()).collect();
+        assert_eq!(
+            message_ids,
+            [Some("first-id".to_string()), Some("second-id".to_string())]
+        );
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(body[1]["To"], recipients[1].as_ref());
+    }
+
+    #[tokio::test]
+    async fn send_batch_reports_the_emails_postmark_rejected() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        let response = ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            { "ErrorCode": 406, "Message": "Inactive recipient" },
+            { "ErrorCode": 0, "Message": "OK", "MessageID": "second-id" },
+        ]));
+        Mock::given(any())
+            .respond_with(response)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let (subject, content) = (subject(), content());
+        let recipients = [email(), email()];
+        let batch: Vec<_> = recipients
+            .iter()
+            .map(|recipient| BatchEmail {
+                recipient,
+                subject: &subject,
+                html_content: &content,
+                text_content: &content,
+                headers: &[],
+            })
+            .collect();
+        let outcomes = email_client.send_batch(&batch).await;
+
+        assert!(outcomes[0].as_ref().unwrap_err().is::<RecipientRejected>());
+        assert_ok!(&outcomes[1]);
+    }
+
+    #[tokio::test]
+    async fn send_batch_fails_every_email_if_the_server_returns_500() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let (subject, content) = (subject(), content());
+        let recipients = [email(), email()];
+        let batch: Vec<_> = recipients
+            .iter()
+            .map(|recipient| BatchEmail {
+                recipient,
+                subject: &subject,
+                html_content: &content,
+                text_content: &content,
+                headers: &[],
+            })
+            .collect();
+        let outcomes = email_client.send_batch(&batch).await;
+
+        assert_eq!(outcomes.len(), 2);
+        assert!(outcomes
+            .iter()
+            .all(|o| !o.as_ref().unwrap_err().is::<RecipientRejected>()));
+    }
+}
@@ -0,0 +1,334 @@
+use anyhow::Context;
+use lettre::message::MultiPart;
+use lettre::transport::smtp::authentication::{Credentials, Mechanism};
+use lettre::transport::smtp::client::{Tls, TlsParameters};
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use secrecy::ExposeSecret;
+use uuid::Uuid;
+
+use super::{Email, EmailTransport, RecipientRejected};
+use crate::configuration::{SmtpSettings, SmtpTls};
+
+/// Delivers emails to an SMTP relay.
+pub struct SmtpTransport {
+    mailer: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpTransport {
+    pub fn new(
+        settings: &SmtpSettings,
+        timeout: std::time::Duration,
+    ) -> Result<Self, anyhow::Error> {
+        let tls = match settings.tls {
+            SmtpTls::StartTls => Tls::Required(TlsParameters::new(settings.host.clone())?),
+            SmtpTls::Implicit => Tls::Wrapper(TlsParameters::new(settings.host.clone())?),
+            SmtpTls::Plaintext => Tls::None,
+        };
+        let mut builder = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&settings.host)
+            .port(settings.port)
+            .tls(tls)
+            .timeout(Some(timeout));
+        if let Some(username) = &settings.username {
+            let password = settings
+                .password
+                .as_ref()
+                .map(|p| p.expose_secret().clone())
+                .unwrap_or_default();
+            builder = builder
+                .credentials(Credentials::new(username.clone(), password))
+                .authentication(vec![Mechanism::Plain, Mechanism::Login]);
+        }
+
+        Ok(Self {
+            mailer: builder.build(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for SmtpTransport {
+    /// Returns the `Message-ID` we gave to the email.
+    async fn send(&self, email: &Email<'_>) -> Result<Option<String>, anyhow::Error> {
+        let sender_domain = email
+            .from
+            .as_ref()
+            .rsplit('@')
+            .next()
+            .unwrap_or("localhost");
+        let message_id = format!("{}@{}", Uuid::new_v4(), sender_domain);
+        let message = Message::builder()
+            .from(email.from.as_ref().parse()?)
+            .to(email.to.as_ref().parse()?)
+            .subject(email.subject)
+            .message_id(Some(format!("<{}>", message_id)))
+            .multipart(MultiPart::alternative_plain_html(
+                email.text_content.to_owned(),
+                email.html_content.to_owned(),
+            ))
+            .context("failed to build the email")?;
+
+        // lettre only knows about typed headers: custom ones are prepended
+        // to the formatted message instead.
+        let mut raw = Vec::new();
+        for header in email.headers {
+            anyhow::ensure!(
+                !header.name.is_empty()
+                    && header
+                        .name
+                        .bytes()
+                        .all(|b| b.is_ascii_graphic() && b != b':'),
+                "invalid email header name: {:?}",
+                header.name
+            );
+            anyhow::ensure!(
+                !header.value.contains(['\r', '\n']),
+                "email header values can't span several lines"
+            );
+            raw.extend_from_slice(format!("{}: {}\r\n", header.name, header.value).as_bytes());
+        }
+        raw.extend(message.formatted());
+
+        match self.mailer.send_raw(message.envelope(), &raw).await {
+            Ok(_) => Ok(Some(message_id)),
+            Err(e) if is_recipient_rejection(&e) => {
+                Err(anyhow::Error::new(RecipientRejected(e.to_string())))
+            }
+            Err(e) => Err(anyhow::Error::new(e).context("the SMTP relay rejected the email")),
+        }
+    }
+}
+
+/// Whether the relay refused the email because of its recipient, going by
+/// the enhanced status code its reply starts with (RFC 3463): X.1.1 to X.1.3
+/// and X.1.6 are about the destination address, X.2.* about the mailbox.
+/// Replies without one can't be told apart from a refused sender.
+fn is_recipient_rejection(error: &lettre::transport::smtp::Error) -> bool {
+    if error.status().is_none() {
+        return false;
+    }
+    let reply = match std::error::Error::source(error) {
+        Some(reply) => reply.to_string(),
+        None => return false,
+    };
+    let enhanced_code = reply.split_whitespace().next().unwrap_or_default();
+    let mut parts = enhanced_code.split('.');
+    matches!(
+        (parts.next(), parts.next(), parts.next()),
+        (Some("4" | "5"), Some("1"), Some("1" | "2" | "3" | "6"))
+            | (Some("4" | "5"), Some("2"), Some(_))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use claim::{assert_err, assert_ok};
+    use fake::faker::internet::en::SafeEmail;
+    use fake::Fake;
+    use secrecy::Secret;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    use crate::configuration::{SmtpSettings, SmtpTls};
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailClient, EmailHeader, RecipientRejected, SmtpTransport};
+
+    /// What the sink says to the emails it is handed.
+    #[derive(Clone, Copy)]
+    enum Reply {
+        Accept,
+        RejectSender,
+        /// As if the recipient's mailbox were full.
+        RejectRecipient,
+    }
+
+    /// A bare-bones SMTP server that replies to every email the same way
+    /// and records everything its clients say.
+    struct SmtpSink {
+        port: u16,
+        transcript: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl SmtpSink {
+        async fn start(reply: Reply) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let transcript = Arc::new(Mutex::new(Vec::new()));
+            let t = transcript.clone();
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(serve(stream, t.clone(), reply));
+                }
+            });
+            Self { port, transcript }
+        }
+
+        fn transcript(&self) -> Vec<String> {
+            self.transcript.lock().unwrap().clone()
+        }
+    }
+
+    async fn serve(
+        stream: tokio::net::TcpStream,
+        transcript: Arc<Mutex<Vec<String>>>,
+        reply: Reply,
+    ) {
+        let (read, mut write) = stream.into_split();
+        let mut lines = BufReader::new(read).lines();
+        write.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+        let mut in_data = false;
+        while let Ok(Some(line)) = lines.next_line().await {
+            transcript.lock().unwrap().push(line.clone());
+            let reply: &[u8] = if in_data {
+                if line != "." {
+                    continue;
+                }
+                in_data = false;
+                b"250 OK: queued\r\n"
+            } else {
+                match line.get(..4).unwrap_or("").to_ascii_uppercase().as_str() {
+                    "EHLO" => b"250-localhost\r\n250-AUTH PLAIN LOGIN\r\n250 8BITMIME\r\n",
+                    "AUTH" => b"235 2.7.0 Authentication successful\r\n",
+                    "MAIL" if matches!(reply, Reply::RejectSender) => {
+                        b"550 5.7.1 Relaying denied\r\n"
+                    }
+                    "RCPT" if matches!(reply, Reply::RejectRecipient) => {
+                        b"452 4.2.2 Mailbox full\r\n"
+                    }
+                    "DATA" => {
+                        in_data = true;
+                        b"354 End data with <CR><LF>.<CR><LF>\r\n"
+                    }
+                    "QUIT" => {
+                        write.write_all(b"221 Bye\r\n").await.unwrap();
+                        return;
+                    }
+                    _ => b"250 OK\r\n",
+                }
+            };
+            write.write_all(reply).await.unwrap();
+        }
+    }
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    fn email_client(port: u16, username: Option<&str>) -> EmailClient {
+        let settings = SmtpSettings {
+            host: "127.0.0.1".into(),
+            port,
+            username: username.map(Into::into),
+            password: Some(Secret::new("password".into())),
+            tls: SmtpTls::Plaintext,
+        };
+        let transport = SmtpTransport::new(&settings, std::time::Duration::from_secs(5)).unwrap();
+        EmailClient::new(email(), transport)
+    }
+
+    #[tokio::test]
+    async fn send_email_hands_the_email_over_to_the_relay() {
+        let sink = SmtpSink::start(Reply::Accept).await;
+        let email_client = email_client(sink.port, None);
+        let recipient = email();
+
+        let outcome = email_client
+            .send_email_with_headers(
+                &recipient,
+                "Greetings",
+                "<p>Hello there</p>",
+                "Hello there",
+                &[EmailHeader::new(
+                    "List-Unsubscribe-Post",
+                    "List-Unsubscribe=One-Click",
+                )],
+            )
+            .await;
+
+        let message_id = outcome.unwrap().unwrap();
+        let transcript = sink.transcript();
+        assert!(transcript.contains(&format!("MAIL FROM:<{}>", email_client.sender().as_ref())));
+        assert!(transcript.contains(&format!("RCPT TO:<{}>", recipient.as_ref())));
+        assert!(transcript.contains(&"Subject: Greetings".to_string()));
+        assert!(transcript.contains(&format!("Message-ID: <{}>", message_id)));
+        assert!(
+            transcript.contains(&"List-Unsubscribe-Post: List-Unsubscribe=One-Click".to_string())
+        );
+        assert!(transcript.contains(&"<p>Hello there</p>".to_string()));
+    }
+
+    #[tokio::test]
+    async fn send_email_authenticates_when_credentials_are_configured() {
+        let sink = SmtpSink::start(Reply::Accept).await;
+        let email_client = email_client(sink.port, Some("user"));
+
+        let outcome = email_client
+            .send_email(&email(), "Greetings", "<p>Hello</p>", "Hello")
+            .await;
+
+        assert_ok!(outcome);
+        // "\0user\0password", base64-encoded
+        assert!(sink
+            .transcript()
+            .contains(&"AUTH PLAIN AHVzZXIAcGFzc3dvcmQ=".to_string()));
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_relay_rejects_the_email() {
+        let sink = SmtpSink::start(Reply::RejectSender).await;
+        let email_client = email_client(sink.port, None);
+
+        let outcome = email_client
+            .send_email(&email(), "Greetings", "<p>Hello</p>", "Hello")
+            .await;
+
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn a_refused_sender_is_not_a_rejection_of_the_recipient() {
+        let sink = SmtpSink::start(Reply::RejectSender).await;
+        let email_client = email_client(sink.port, None);
+
+        let outcome = email_client
+            .send_email(&email(), "Greetings", "<p>Hello</p>", "Hello")
+            .await;
+
+        assert!(!outcome.unwrap_err().is::<RecipientRejected>());
+    }
+
+    #[tokio::test]
+    async fn send_email_tells_when_the_relay_rejects_the_recipient() {
+        let sink = SmtpSink::start(Reply::RejectRecipient).await;
+        let email_client = email_client(sink.port, None);
+
+        let outcome = email_client
+            .send_email(&email(), "Greetings", "<p>Hello</p>", "Hello")
+            .await;
+
+        assert!(outcome.unwrap_err().is::<RecipientRejected>());
+    }
+
+    #[tokio::test]
+    async fn headers_spanning_several_lines_are_rejected() {
+        let sink = SmtpSink::start(Reply::Accept).await;
+        let email_client = email_client(sink.port, None);
+
+        let outcome = email_client
+            .send_email_with_headers(
+                &email(),
+                "Greetings",
+                "<p>Hello</p>",
+                "Hello",
+                &[EmailHeader::new(
+                    "X-Injected",
+                    "a\r\nBcc: someone@example.com",
+                )],
+            )
+            .await;
+
+        assert_err!(outcome);
+        assert!(sink.transcript().is_empty());
+    }
+}
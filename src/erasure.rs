@@ -0,0 +1,478 @@
+use anyhow::Context;
+use chrono::{DateTime, SecondsFormat, SubsecRound, Utc};
+use secrecy::Secret;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::domain::{hmac_sign, hmac_verify};
+
+/// Who asked for an erasure.
+#[derive(Debug, Clone, Copy)]
+pub enum ErasureRequester {
+    Admin(Uuid),
+    /// Through the link in the emails we sent them.
+    Subscriber,
+}
+
+impl std::fmt::Display for ErasureRequester {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErasureRequester::Admin(user_id) => write!(f, "admin:{}", user_id),
+            ErasureRequester::Subscriber => write!(f, "subscriber"),
+        }
+    }
+}
+
+/// How many rows an erasure deleted or anonymised, per table.
+#[derive(Debug, serde::Serialize)]
+struct AffectedRows {
+    subscriptions: u64,
+    subscription_status_changes: u64,
+    consent_events: u64,
+    subscription_tokens: u64,
+    issue_delivery_queue: u64,
+    issue_deliveries: u64,
+    issue_delivery_failures: u64,
+    email_events: u64,
+    issue_engagement_events: u64,
+    suppressed_emails: u64,
+    subscriber_import_rejections: u64,
+}
+
+/// The proof that a subscriber was erased. Receipts form a chain: each one
+/// is signed together with the signature of the receipt before it, so
+/// editing or removing a receipt breaks the chain from there on.
+#[derive(Debug)]
+pub struct ErasureReceipt {
+    pub erasure_id: Uuid,
+    pub email_digest: String,
+    pub requested_by: String,
+    pub erased_at: DateTime<Utc>,
+    pub affected_rows: String,
+    pub previous_signature: Option<String>,
+    pub signature: String,
+}
+
+impl ErasureReceipt {
+    /// What is signed: every field, one per line.
+    fn signed_content(&self) -> String {
+        [
+            self.previous_signature.as_deref().unwrap_or_default(),
+            &self.erasure_id.to_string(),
+            &self.email_digest,
+            &self.requested_by,
+            &self.erased_at.to_rfc3339_opts(SecondsFormat::Micros, true),
+            &self.affected_rows,
+        ]
+        .iter()
+        .map(|field| format!("{}\n", field))
+        .collect()
+    }
+
+    fn sign(&self, secret: &Secret<String>) -> String {
+        let content = self.signed_content();
+        hmac_sign(secret, &[b"erasure-receipt:", content.as_bytes()])
+    }
+
+    /// Whether we signed the receipt as it is, right after the receipt
+    /// signed with `previous_signature`.
+    fn is_intact(&self, previous_signature: Option<&str>, secret: &Secret<String>) -> bool {
+        self.previous_signature.as_deref() == previous_signature
+            && hmac_verify(
+                secret,
+                &[b"erasure-receipt:", self.signed_content().as_bytes()],
+                &self.signature,
+            )
+            .is_ok()
+    }
+}
+
+/// A keyed hash of an address: the same address always gets the same
+/// digest, but the address can't be recovered from it.
+/// Only ASCII letters are case-folded, as Postgres' `lower` does on our
+/// database: digests must only be computed here, never in SQL.
+pub fn email_digest(email: &str, secret: &Secret<String>) -> String {
+    hmac_sign(
+        secret,
+        &[b"erased-email:", email.to_ascii_lowercase().as_bytes()],
+    )
+}
+
+/// Delete everything we hold about a subscriber. What statistics are made
+/// of (deliveries, provider events, opens and clicks) is kept, but moved to
+/// a pseudonym that can't be traced back to them.
+///
+/// Returns `None` if there is no such subscriber, e.g. because they have
+/// already been erased.
+#[tracing::instrument(name = "erase a subscriber", skip(pool, secret))]
+pub async fn erase_subscriber(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    requested_by: ErasureRequester,
+    secret: &Secret<String>,
+) -> Result<Option<ErasureReceipt>, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("failed to acquire a Postgres connection from the pool")?;
+    // Receipts are chained: erasures must take turns to add theirs
+    sqlx::query!("LOCK TABLE erasure_receipts IN SHARE ROW EXCLUSIVE MODE")
+        .execute(&mut transaction)
+        .await
+        .context("failed to lock the erasure receipts")?;
+    let email = match sqlx::query!(
+        r#"SELECT email FROM subscriptions WHERE id = $1 FOR UPDATE"#,
+        subscriber_id
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("failed to retrieve the subscriber to erase")?
+    {
+        Some(r) => r.email,
+        None => return Ok(None),
+    };
+
+    let erasure_id = Uuid::new_v4();
+    let pseudonym = format!("erased:{}", erasure_id);
+    let digest = email_digest(&email, secret);
+
+    let subscription_tokens = sqlx::query!(
+        r#"DELETE FROM subscription_tokens WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to erase the confirmation tokens")?
+    .rows_affected();
+    let issue_delivery_queue = sqlx::query!(
+        r#"DELETE FROM issue_delivery_queue WHERE lower(subscriber_email) = lower($1)"#,
+        email
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to erase the queued deliveries")?
+    .rows_affected();
+    // The provider's message ids and error messages lead back to the address
+    let issue_deliveries = sqlx::query!(
+        r#"
+        UPDATE issue_deliveries
+        SET subscriber_email = $2, provider_message_id = NULL, last_error = NULL
+        WHERE lower(subscriber_email) = lower($1)
+        "#,
+        email,
+        pseudonym
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to anonymise the deliveries")?
+    .rows_affected();
+    let issue_delivery_failures = sqlx::query!(
+        r#"
+        UPDATE issue_delivery_failures
+        SET subscriber_email = $2, last_error = ''
+        WHERE lower(subscriber_email) = lower($1)
+        "#,
+        email,
+        pseudonym
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to anonymise the delivery failures")?
+    .rows_affected();
+    let email_events = sqlx::query!(
+        r#"
+        UPDATE email_events
+        SET email = $2, provider_message_id = NULL, description = NULL
+        WHERE lower(email) = lower($1)
+        "#,
+        email,
+        pseudonym
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to anonymise the email events")?
+    .rows_affected();
+    let issue_engagement_events = sqlx::query!(
+        r#"
+        UPDATE issue_engagement_events
+        SET subscriber_id = $2
+        WHERE subscriber_id = $1
+        "#,
+        subscriber_id,
+        erasure_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to anonymise the engagement events")?
+    .rows_affected();
+    // The address must stay suppressed, see `suppression_list::is_suppressed`
+    let suppressed_emails = sqlx::query!(
+        r#"
+        WITH erased AS (
+            DELETE FROM suppressed_emails WHERE email = lower($1)
+            RETURNING reason, suppressed_at
+        )
+        INSERT INTO erased_suppressions (email_digest, reason, suppressed_at)
+        SELECT $2, reason, suppressed_at FROM erased
+        ON CONFLICT (email_digest) DO NOTHING
+        "#,
+        email,
+        digest
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to anonymise the suppression")?
+    .rows_affected();
+    let subscriber_import_rejections = sqlx::query!(
+        r#"DELETE FROM subscriber_import_rejections WHERE lower(email) = lower($1)"#,
+        email
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to erase the rejected import lines")?
+    .rows_affected();
+    let subscription_status_changes = sqlx::query!(
+        r#"DELETE FROM subscription_status_changes WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to erase the status history")?
+    .rows_affected();
+    let consent_events = sqlx::query!(
+        r#"DELETE FROM consent_events WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to erase the consent trail")?
+    .rows_affected();
+    let subscriptions = sqlx::query!(r#"DELETE FROM subscriptions WHERE id = $1"#, subscriber_id)
+        .execute(&mut transaction)
+        .await
+        .context("failed to erase the subscription")?
+        .rows_affected();
+    let affected = AffectedRows {
+        subscriptions,
+        subscription_status_changes,
+        consent_events,
+        subscription_tokens,
+        issue_delivery_queue,
+        issue_deliveries,
+        issue_delivery_failures,
+        email_events,
+        issue_engagement_events,
+        suppressed_emails,
+        subscriber_import_rejections,
+    };
+
+    let previous_signature =
+        sqlx::query!(r#"SELECT signature FROM erasure_receipts ORDER BY id DESC LIMIT 1"#)
+            .fetch_optional(&mut transaction)
+            .await
+            .context("failed to retrieve the last erasure receipt")?
+            .map(|r| r.signature);
+    let mut receipt = ErasureReceipt {
+        erasure_id,
+        email_digest: digest,
+        requested_by: requested_by.to_string(),
+        // As precise as Postgres stores it, for the signature to still match
+        erased_at: Utc::now().trunc_subsecs(6),
+        affected_rows: serde_json::to_string(&affected)
+            .context("failed to serialize the affected rows")?,
+        previous_signature,
+        signature: String::new(),
+    };
+    receipt.signature = receipt.sign(secret);
+    sqlx::query!(
+        r#"
+        INSERT INTO erasure_receipts (
+            erasure_id,
+            email_digest,
+            requested_by,
+            erased_at,
+            affected_rows,
+            previous_signature,
+            signature
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+        receipt.erasure_id,
+        receipt.email_digest,
+        receipt.requested_by,
+        receipt.erased_at,
+        receipt.affected_rows,
+        receipt.previous_signature,
+        receipt.signature
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to store the erasure receipt")?;
+    transaction
+        .commit()
+        .await
+        .context("failed to commit SQL transaction to erase a subscriber")?;
+
+    Ok(Some(receipt))
+}
+
+/// Every receipt, oldest first, or only those of the address with this
+/// digest.
+#[tracing::instrument(name = "get erasure receipts", skip(pool))]
+pub async fn get_erasure_receipts(
+    pool: &PgPool,
+    email_digest: Option<&str>,
+) -> Result<Vec<ErasureReceipt>, anyhow::Error> {
+    let receipts = sqlx::query_as!(
+        ErasureReceipt,
+        r#"
+        SELECT
+            erasure_id,
+            email_digest,
+            requested_by,
+            erased_at,
+            affected_rows,
+            previous_signature,
+            signature
+        FROM erasure_receipts
+        WHERE $1::text IS NULL OR email_digest = $1
+        ORDER BY id
+        "#,
+        email_digest
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to retrieve erasure receipts")?;
+
+    Ok(receipts)
+}
+
+/// The outcome of checking the chain of erasure receipts.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReceiptChain {
+    Intact {
+        n_receipts: usize,
+    },
+    /// This receipt was edited, or the one before it was edited or removed.
+    BrokenAt {
+        erasure_id: Uuid,
+    },
+}
+
+/// Check every receipt against its signature and the one before it.
+/// Removing the latest receipts can't be told from them never having
+/// existed: compare the count with a copy kept elsewhere to catch that.
+pub fn check_receipt_chain(receipts: &[ErasureReceipt], secret: &Secret<String>) -> ReceiptChain {
+    let mut previous_signature = None;
+    for receipt in receipts {
+        if !receipt.is_intact(previous_signature, secret) {
+            return ReceiptChain::BrokenAt {
+                erasure_id: receipt.erasure_id,
+            };
+        }
+        previous_signature = Some(receipt.signature.as_str());
+    }
+    ReceiptChain::Intact {
+        n_receipts: receipts.len(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{SubsecRound, Utc};
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    use super::{check_receipt_chain, email_digest, ErasureReceipt, ReceiptChain};
+
+    fn secret() -> Secret<String> {
+        Secret::new("a-long-and-very-secret-key".to_string())
+    }
+
+    fn chain(n: usize) -> Vec<ErasureReceipt> {
+        let mut receipts: Vec<ErasureReceipt> = Vec::new();
+        for i in 0..n {
+            let mut receipt = ErasureReceipt {
+                erasure_id: Uuid::new_v4(),
+                email_digest: email_digest(&format!("subscriber{i}@example.com"), &secret()),
+                requested_by: "subscriber".into(),
+                erased_at: Utc::now().trunc_subsecs(6),
+                affected_rows: r#"{"subscriptions":1}"#.into(),
+                previous_signature: receipts.last().map(|r| r.signature.clone()),
+                signature: String::new(),
+            };
+            receipt.signature = receipt.sign(&secret());
+            receipts.push(receipt);
+        }
+        receipts
+    }
+
+    #[test]
+    fn a_chain_of_signed_receipts_is_intact() {
+        assert_eq!(
+            check_receipt_chain(&chain(3), &secret()),
+            ReceiptChain::Intact { n_receipts: 3 }
+        );
+        assert_eq!(
+            check_receipt_chain(&[], &secret()),
+            ReceiptChain::Intact { n_receipts: 0 }
+        );
+    }
+
+    #[test]
+    fn an_edited_receipt_breaks_the_chain() {
+        let mut receipts = chain(3);
+        receipts[1].affected_rows = r#"{"subscriptions":0}"#.into();
+        let erasure_id = receipts[1].erasure_id;
+
+        assert_eq!(
+            check_receipt_chain(&receipts, &secret()),
+            ReceiptChain::BrokenAt { erasure_id }
+        );
+    }
+
+    #[test]
+    fn a_removed_receipt_breaks_the_chain() {
+        let mut receipts = chain(3);
+        receipts.remove(1);
+        let erasure_id = receipts[1].erasure_id;
+
+        assert_eq!(
+            check_receipt_chain(&receipts, &secret()),
+            ReceiptChain::BrokenAt { erasure_id }
+        );
+    }
+
+    #[test]
+    fn receipts_signed_with_another_secret_are_not_intact() {
+        let receipts = chain(1);
+        let other_secret = Secret::new("another-secret".to_string());
+
+        assert_eq!(
+            check_receipt_chain(&receipts, &other_secret),
+            ReceiptChain::BrokenAt {
+                erasure_id: receipts[0].erasure_id
+            }
+        );
+    }
+
+    #[test]
+    fn the_email_digest_ignores_case() {
+        assert_eq!(
+            email_digest("Ursula@Example.com", &secret()),
+            email_digest("ursula@example.com", &secret())
+        );
+    }
+
+    #[test]
+    fn the_email_digest_only_folds_ascii_letters() {
+        assert_eq!(
+            email_digest("Ünïcödé@Example.com", &secret()),
+            email_digest("Ünïcödé@example.com", &secret())
+        );
+        assert_ne!(
+            email_digest("Ünïcödé@example.com", &secret()),
+            email_digest("ünïcödé@example.com", &secret())
+        );
+    }
+}
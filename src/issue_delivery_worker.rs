@@ -1,12 +1,30 @@
-use std::time::Duration;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
 
+use chrono::Utc;
+use futures::future::join_all;
+use rand::{thread_rng, Rng};
+use secrecy::Secret;
+use sqlx::postgres::PgListener;
 use sqlx::{PgPool, Postgres, Transaction};
-use tracing::{field::display, Span};
+use tokio::sync::Semaphore;
+use tokio_util::sync::CancellationToken;
+use tracing::Span;
 use uuid::Uuid;
 
 use crate::{
-    configuration::Settings, domain::SubscriberEmail, email_client::EmailClient,
+    configuration::{DeliveryWorkerSettings, Settings},
+    domain::SubscriberEmail,
+    email_client::{BatchEmail, EmailClient, EmailHeader, RecipientRejected},
+    routes::{
+        click_tracking_link, confirmation_email_bodies, delete_tokens, enqueue_delivery_tasks,
+        generate_subscription_token, issue_permalink, open_tracking_pixel_url, store_token,
+        track_links, unsubscribe_link, CONFIRMATION_EMAIL_SUBJECT,
+    },
     startup::get_connection_pool,
+    suppression_list::{mark_inactive_subscribers, record_soft_bounce, reset_soft_bounces},
 };
 
 pub enum ExecutionOutcome {
@@ -14,46 +32,343 @@ pub enum ExecutionOutcome {
     EmptyQueue,
 }
 
-#[tracing::instrument(
-    skip_all,
-    fields(
-        newsletter_issue_id=tracing::field::Empty,
-        subscriber_email=tracing::field::Empty,
-    ),
-    err
-)]
+/// Execute a single delivery task.
 pub async fn try_execute_task(
     pool: &PgPool,
     email_client: &EmailClient,
+    base_url: &str,
+    hmac_secret: &Secret<String>,
 ) -> Result<ExecutionOutcome, anyhow::Error> {
-    let task = dequeue_task(pool).await?;
-    if task.is_none() {
+    try_execute_tasks(
+        pool,
+        email_client,
+        base_url,
+        hmac_secret,
+        1,
+        &Semaphore::new(1),
+    )
+    .await
+}
+
+/// Claim up to `batch_size` delivery tasks and execute them. If the email
+/// transport has a batch API, the emails for the same issue are sent together
+/// with a single call to it; otherwise each email is sent on its own. Either
+/// way, the calls are made in parallel as long as a permit can be acquired
+/// from `send_permits`.
+///
+/// The tasks stay locked until all of them have been executed and their
+/// outcome has been committed: if the worker dies halfway through, the whole
+/// batch is picked up again - some subscribers might get the same issue
+/// twice, but none of them will miss it.
+#[tracing::instrument(skip_all, fields(n_tasks = tracing::field::Empty), err)]
+pub async fn try_execute_tasks(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    hmac_secret: &Secret<String>,
+    batch_size: i64,
+    send_permits: &Semaphore,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let (mut transaction, tasks) = dequeue_tasks(pool, batch_size).await?;
+    if tasks.is_empty() {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    }
+    Span::current().record("n_tasks", &tasks.len());
+
+    let mut tasks_by_issue: Vec<(NewsletterIssue, Vec<&Task>)> = Vec::new();
+    let mut issue_indexes = HashMap::new();
+    for task in &tasks {
+        let index = match issue_indexes.entry(task.newsletter_issue_id) {
+            Entry::Occupied(entry) => *entry.get(),
+            Entry::Vacant(entry) => {
+                let issue = get_issue(pool, task.newsletter_issue_id).await?;
+                tasks_by_issue.push((issue, Vec::new()));
+                *entry.insert(tasks_by_issue.len() - 1)
+            }
+        };
+        tasks_by_issue[index].1.push(task);
+    }
+
+    let groups: Vec<(&NewsletterIssue, Vec<&Task>)> = if email_client.supports_batch() {
+        tasks_by_issue
+            .iter()
+            .map(|(issue, tasks)| (issue, tasks.clone()))
+            .collect()
+    } else {
+        tasks_by_issue
+            .iter()
+            .flat_map(|(issue, tasks)| tasks.iter().map(move |task| (issue, vec![*task])))
+            .collect()
+    };
+
+    let outcomes = join_all(groups.iter().map(|(issue, tasks)| async move {
+        let _permit = send_permits.acquire().await?;
+        Ok::<_, anyhow::Error>(
+            execute_tasks(tasks, issue, email_client, base_url, hmac_secret).await,
+        )
+    }))
+    .await;
+
+    let has_delivery_webhooks = email_client.has_delivery_webhooks();
+    for ((_, tasks), outcomes) in groups.iter().zip(outcomes) {
+        for (task, outcome) in tasks.iter().zip(outcomes?) {
+            complete_task(&mut transaction, task, outcome, has_delivery_webhooks).await?;
+        }
+    }
+    transaction.commit().await?;
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+/// Send up to `batch_size` of the confirmation emails queued by subscriber
+/// imports, each with a new confirmation link. The subscribers we could not
+/// email stay pending: they can be sent a new confirmation email from their
+/// page.
+#[tracing::instrument(skip_all, fields(n_emails = tracing::field::Empty), err)]
+pub async fn try_send_queued_confirmations(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    batch_size: i64,
+    send_permits: &Semaphore,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let queued = sqlx::query!(
+        r#"
+        SELECT subscriber_id, import_id, email, status
+        FROM confirmation_email_queue
+        JOIN subscriptions ON subscriptions.id = confirmation_email_queue.subscriber_id
+        FOR UPDATE OF confirmation_email_queue
+        SKIP LOCKED
+        LIMIT $1
+        "#,
+        batch_size
+    )
+    .fetch_all(&mut transaction)
+    .await?;
+    if queued.is_empty() {
         return Ok(ExecutionOutcome::EmptyQueue);
     }
-    let (transaction, issue_id, email) = task.unwrap();
-    Span::current()
-        .record("newsletter_issue_id", &display(issue_id))
-        .record("subscriber_email", &display(&email));
-
-    match SubscriberEmail::parse(email.clone()) {
-        Ok(email) => {
-            let issue = get_issue(pool, issue_id).await?;
-            if let Err(e) = email_client
-                .send_email(
-                    &email,
+    Span::current().record("n_emails", &queued.len());
+
+    let mut emails = Vec::with_capacity(queued.len());
+    let mut n_unsent: HashMap<Uuid, i32> = HashMap::new();
+    for r in &queued {
+        // They have been confirmed, or unsubscribed, in the meantime
+        if r.status != "pending_confirmation" {
+            continue;
+        }
+        let recipient = match SubscriberEmail::parse(r.email.clone()) {
+            Ok(recipient) => recipient,
+            Err(e) => {
+                tracing::warn!(
+                    error.message = %e,
+                    subscriber_id = %r.subscriber_id,
+                    "skipping an imported subscriber with an invalid email address"
+                );
+                *n_unsent.entry(r.import_id).or_default() += 1;
+                continue;
+            }
+        };
+        // Only the link in the latest email works
+        delete_tokens(&mut transaction, r.subscriber_id).await?;
+        let subscription_token = generate_subscription_token();
+        store_token(&mut transaction, r.subscriber_id, &subscription_token).await?;
+        let (html_body, plain_body) = confirmation_email_bodies(base_url, &subscription_token);
+        emails.push((r, recipient, html_body, plain_body));
+    }
+
+    let batch: Vec<_> = emails
+        .iter()
+        .map(|(_, recipient, html_body, plain_body)| BatchEmail {
+            recipient,
+            subject: CONFIRMATION_EMAIL_SUBJECT,
+            html_content: html_body,
+            text_content: plain_body,
+            headers: &[],
+        })
+        .collect();
+    // Same as issues: a single call if there is a batch API, one call per
+    // email in parallel otherwise
+    let outcomes = if email_client.supports_batch() {
+        let _permit = send_permits.acquire().await?;
+        email_client.send_batch(&batch).await
+    } else {
+        join_all(batch.iter().map(|email| async move {
+            let _permit = send_permits.acquire().await?;
+            email_client
+                .send_batch(std::slice::from_ref(email))
+                .await
+                .remove(0)
+        }))
+        .await
+    };
+    for ((r, ..), outcome) in emails.iter().zip(outcomes) {
+        if let Err(e) = outcome {
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                subscriber_id = %r.subscriber_id,
+                "failed to send a confirmation email to an imported subscriber"
+            );
+            *n_unsent.entry(r.import_id).or_default() += 1;
+        }
+    }
+
+    for (import_id, n_unsent) in n_unsent {
+        sqlx::query!(
+            r#"
+            UPDATE subscriber_imports
+            SET n_unsent_confirmations = n_unsent_confirmations + $2
+            WHERE import_id = $1
+            "#,
+            import_id,
+            n_unsent
+        )
+        .execute(&mut transaction)
+        .await?;
+    }
+    let subscriber_ids: Vec<Uuid> = queued.iter().map(|r| r.subscriber_id).collect();
+    sqlx::query!(
+        "DELETE FROM confirmation_email_queue WHERE subscriber_id = ANY($1)",
+        &subscriber_ids[..]
+    )
+    .execute(&mut transaction)
+    .await?;
+    transaction.commit().await?;
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+pub(crate) enum TaskOutcome {
+    Sent {
+        message_id: Option<String>,
+    },
+    SendFailed {
+        error: String,
+        /// The provider refused it because of the recipient, rather than
+        /// being unreachable or failing on its own.
+        recipient_rejected: bool,
+    },
+    InvalidEmail {
+        error: String,
+    },
+    /// The subscriber is no longer confirmed.
+    Skipped,
+}
+
+/// An issue, as it is sent to one of its subscribers.
+pub(crate) struct PersonalisedEmail {
+    pub(crate) recipient: SubscriberEmail,
+    pub(crate) html_content: String,
+    pub(crate) text_content: String,
+    pub(crate) headers: [EmailHeader; 2],
+}
+
+/// Try to deliver an issue to the subscribers of `tasks`, in a single batch
+/// if there are several of them. Returns the outcome of each task, in order.
+/// This doesn't touch the queue: see `complete_task` for that.
+#[tracing::instrument(
+    skip_all,
+    fields(n_tasks = tasks.len(), title = %issue.title)
+)]
+async fn execute_tasks(
+    tasks: &[&Task],
+    issue: &NewsletterIssue,
+    email_client: &EmailClient,
+    base_url: &str,
+    hmac_secret: &Secret<String>,
+) -> Vec<TaskOutcome> {
+    let mut outcomes = Vec::with_capacity(tasks.len());
+    let mut emails = Vec::new();
+    for (i, task) in tasks.iter().enumerate() {
+        match personalise_email(task, issue, email_client.sender(), base_url, hmac_secret) {
+            Ok(email) => {
+                // Filled in once the email has been sent
+                outcomes.push(TaskOutcome::Skipped);
+                emails.push((i, email));
+            }
+            Err(outcome) => outcomes.push(outcome),
+        }
+    }
+
+    let results = match emails.as_slice() {
+        [] => Vec::new(),
+        [(_, email)] => vec![
+            email_client
+                .send_email_with_headers(
+                    &email.recipient,
                     &issue.title,
-                    &issue.html_content,
-                    &issue.text_content,
+                    &email.html_content,
+                    &email.text_content,
+                    &email.headers,
                 )
-                .await
-            {
-                tracing::error!(
+                .await,
+        ],
+        emails => {
+            let batch: Vec<_> = emails
+                .iter()
+                .map(|(_, email)| BatchEmail {
+                    recipient: &email.recipient,
+                    subject: &issue.title,
+                    html_content: &email.html_content,
+                    text_content: &email.text_content,
+                    headers: &email.headers,
+                })
+                .collect();
+            email_client.send_batch(&batch).await
+        }
+    };
+
+    for ((i, email), result) in emails.iter().zip(results) {
+        outcomes[*i] = match result {
+            Ok(message_id) => TaskOutcome::Sent { message_id },
+            Err(e) => {
+                tracing::warn!(
                     error.cause_chain = ?e,
                     error.message = %e,
-                    "failed to deliver issue to a confirmed subscriber. Skipping."
+                    subscriber_email = %email.recipient.as_ref(),
+                    "failed to deliver issue to a confirmed subscriber"
                 );
+                TaskOutcome::SendFailed {
+                    error: e.to_string(),
+                    recipient_rejected: e.is::<RecipientRejected>(),
+                }
             }
+        };
+    }
+    outcomes
+}
+
+/// Add the subscriber-specific bits to an issue: unsubscribe link and headers.
+/// Fails with the outcome of the task if there is nothing to send.
+#[tracing::instrument(
+    skip_all,
+    fields(
+        newsletter_issue_id=%task.newsletter_issue_id,
+        subscriber_email=%task.subscriber_email,
+        n_retries=%task.n_retries,
+    )
+)]
+pub(crate) fn personalise_email(
+    task: &Task,
+    issue: &NewsletterIssue,
+    sender: &SubscriberEmail,
+    base_url: &str,
+    hmac_secret: &Secret<String>,
+) -> Result<PersonalisedEmail, TaskOutcome> {
+    let subscriber_id = match task.subscriber_id {
+        Some(subscriber_id) => subscriber_id,
+        None => {
+            tracing::info!(
+                "skipping a subscriber that is no longer confirmed. \
+                They might have unsubscribed after the issue was published."
+            );
+            return Err(TaskOutcome::Skipped);
         }
+    };
+    let recipient = match SubscriberEmail::parse(task.subscriber_email.clone()) {
+        Ok(email) => email,
         Err(e) => {
             tracing::error!(
                 error.cause_chain = ?e,
@@ -61,70 +376,382 @@ pub async fn try_execute_task(
                 "skipping a confirmed subscriber. \
                 Their stored contact details are invalid."
             );
+            return Err(TaskOutcome::InvalidEmail { error: e });
         }
+    };
+
+    let unsubscribe_link = unsubscribe_link(base_url, subscriber_id, hmac_secret);
+    let mut html_content = String::new();
+    let mut text_content = String::new();
+    if let (true, Some(slug)) = (issue.in_public_archive, &issue.slug) {
+        let permalink = issue_permalink(base_url, slug);
+        html_content = format!(
+            "<p><a href=\"{}\">View this email in your browser</a></p>",
+            permalink
+        );
+        text_content = format!("View this email in your browser: {}\n\n", permalink);
+    }
+    if issue.track_engagement {
+        let newsletter_issue_id = task.newsletter_issue_id;
+        let body = track_links(&issue.html_content, |url| {
+            click_tracking_link(
+                base_url,
+                newsletter_issue_id,
+                subscriber_id,
+                url,
+                hmac_secret,
+            )
+        });
+        let pixel_url =
+            open_tracking_pixel_url(base_url, newsletter_issue_id, subscriber_id, hmac_secret);
+        write!(
+            html_content,
+            "{}<p><a href=\"{}\">Unsubscribe</a></p>\
+            <img src=\"{}\" width=\"1\" height=\"1\" alt=\"\">",
+            body,
+            unsubscribe_link,
+            pixel_url.replace('&', "&amp;")
+        )
+        .unwrap();
+    } else {
+        write!(
+            html_content,
+            "{}<p><a href=\"{}\">Unsubscribe</a></p>",
+            issue.html_content, unsubscribe_link
+        )
+        .unwrap();
     }
-    delete_task(transaction, issue_id, &email).await?;
+    write!(
+        text_content,
+        "{}\n\nUnsubscribe: {}",
+        issue.text_content, unsubscribe_link
+    )
+    .unwrap();
+    let headers = list_unsubscribe_headers(sender, &unsubscribe_link);
 
-    Ok(ExecutionOutcome::TaskCompleted)
+    Ok(PersonalisedEmail {
+        recipient,
+        html_content,
+        text_content,
+        headers,
+    })
+}
+
+/// Update the queue and the delivery log according to the outcome of a task.
+/// Unless `has_delivery_webhooks`, an email the transport accepted counts as
+/// delivered as far as soft bounces are concerned.
+#[tracing::instrument(skip_all)]
+async fn complete_task(
+    transaction: &mut PgTransaction,
+    task: &Task,
+    outcome: TaskOutcome,
+    has_delivery_webhooks: bool,
+) -> Result<(), anyhow::Error> {
+    let is_recipient_rejection = matches!(
+        outcome,
+        TaskOutcome::SendFailed {
+            recipient_rejected: true,
+            ..
+        }
+    );
+    match outcome {
+        TaskOutcome::Sent { message_id } => {
+            record_delivery(
+                transaction,
+                task,
+                DeliveryStatus::Sent,
+                message_id.as_deref(),
+                None,
+            )
+            .await?;
+            if !has_delivery_webhooks {
+                // Nothing else will tell us the email got through
+                reset_soft_bounces(&mut *transaction, &task.subscriber_email).await?;
+            }
+            delete_task(transaction, task).await?;
+        }
+        TaskOutcome::SendFailed { error, .. } if task.n_retries < MAX_RETRIES => {
+            record_delivery(
+                transaction,
+                task,
+                DeliveryStatus::Pending,
+                None,
+                Some(&error),
+            )
+            .await?;
+            reschedule_task(transaction, task).await?;
+        }
+        TaskOutcome::SendFailed { error, .. } | TaskOutcome::InvalidEmail { error } => {
+            if is_recipient_rejection {
+                // The recipient was refused up to the last retry. Other
+                // failures, e.g. the provider being down, say nothing about
+                // the address: bounces are left to the Bounce webhook.
+                record_soft_bounce(&mut *transaction, &task.subscriber_email).await?;
+            }
+            tracing::error!(
+                newsletter_issue_id = %task.newsletter_issue_id,
+                subscriber_email = %task.subscriber_email,
+                "giving up on delivering an issue to a subscriber"
+            );
+            record_delivery(
+                transaction,
+                task,
+                DeliveryStatus::Failed,
+                None,
+                Some(&error),
+            )
+            .await?;
+            move_task_to_failures(transaction, task, &error).await?;
+        }
+        TaskOutcome::Skipped => {
+            record_delivery(transaction, task, DeliveryStatus::Skipped, None, None).await?;
+            delete_task(transaction, task).await?;
+        }
+    }
+    Ok(())
+}
+
+/// The headers mail clients use to show their own unsubscribe button.
+/// `List-Unsubscribe-Post` tells them they can unsubscribe with a single
+/// `POST` to the https link, without opening it in a browser (RFC 8058).
+fn list_unsubscribe_headers(sender: &SubscriberEmail, unsubscribe_link: &str) -> [EmailHeader; 2] {
+    [
+        EmailHeader::new(
+            "List-Unsubscribe",
+            format!("<mailto:{sender}?subject=unsubscribe>, <{unsubscribe_link}>"),
+        ),
+        EmailHeader::new("List-Unsubscribe-Post", "List-Unsubscribe=One-Click"),
+    ]
 }
 
 type PgTransaction = Transaction<'static, Postgres>;
 
-#[tracing::instrument(skip_all)]
-async fn dequeue_task(
+/// Where the delivery of an issue to a subscriber is at, as tracked in
+/// the `issue_deliveries` table.
+#[derive(Debug, Clone, Copy)]
+pub enum DeliveryStatus {
+    /// Waiting in the queue, possibly after some failed attempts.
+    Pending,
+    Sent,
+    /// We gave up on it.
+    Failed,
+    /// The subscriber was no longer confirmed when we got to it.
+    Skipped,
+}
+
+impl DeliveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryStatus::Pending => "pending",
+            DeliveryStatus::Sent => "sent",
+            DeliveryStatus::Failed => "failed",
+            DeliveryStatus::Skipped => "skipped",
+        }
+    }
+}
+
+/// How many times a failed delivery is retried before giving up on it.
+const MAX_RETRIES: i16 = 5;
+const BASE_RETRY_DELAY: Duration = Duration::from_secs(30);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+
+pub(crate) struct Task {
+    pub(crate) newsletter_issue_id: Uuid,
+    pub(crate) subscriber_email: String,
+    /// `None` if the subscriber is no longer confirmed.
+    pub(crate) subscriber_id: Option<Uuid>,
+    pub(crate) n_retries: i16,
+}
+
+/// Lock up to `batch_size` delivery tasks that are due for execution.
+/// Other workers skip the locked rows instead of waiting for them.
+#[tracing::instrument(skip(pool))]
+async fn dequeue_tasks(
     pool: &PgPool,
-) -> Result<Option<(PgTransaction, Uuid, String)>, anyhow::Error> {
+    batch_size: i64,
+) -> Result<(PgTransaction, Vec<Task>), anyhow::Error> {
     let mut transaction = pool.begin().await?;
-    let r = sqlx::query!(
+    let tasks = sqlx::query!(
         r#"
-        SELECT newsletter_issue_id, subscriber_email
+        SELECT
+            newsletter_issue_id,
+            subscriber_email,
+            (
+                SELECT id FROM subscriptions
+                WHERE email = subscriber_email AND status = 'confirmed'
+            ) AS subscriber_id,
+            n_retries
         FROM issue_delivery_queue
+        WHERE execute_after <= now()
         FOR UPDATE
         SKIP LOCKED
-        LIMIT 1
-        "#
+        LIMIT $1
+        "#,
+        batch_size
     )
-    .fetch_optional(&mut transaction)
+    .fetch_all(&mut transaction)
+    .await?
+    .into_iter()
+    .map(|r| Task {
+        newsletter_issue_id: r.newsletter_issue_id,
+        subscriber_email: r.subscriber_email,
+        subscriber_id: r.subscriber_id,
+        n_retries: r.n_retries,
+    })
+    .collect();
+
+    Ok((transaction, tasks))
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(transaction: &mut PgTransaction, task: &Task) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE
+            newsletter_issue_id = $1  AND
+            subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email
+    )
+    .execute(transaction)
     .await?;
 
-    if let Some(r) = r {
-        return Ok(Some((
-            transaction,
-            r.newsletter_issue_id,
-            r.subscriber_email,
-        )));
-    }
+    Ok(())
+}
 
-    Ok(None)
+/// Keep track of the outcome of an attempt at executing a task.
+/// Skipping a subscriber does not count as an attempt at delivering to them.
+#[tracing::instrument(skip(transaction, task, provider_message_id, error))]
+async fn record_delivery(
+    transaction: &mut PgTransaction,
+    task: &Task,
+    status: DeliveryStatus,
+    provider_message_id: Option<&str>,
+    error: Option<&str>,
+) -> Result<(), anyhow::Error> {
+    let n_attempts: i16 = match status {
+        DeliveryStatus::Skipped => 0,
+        _ => 1,
+    };
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_deliveries (
+            newsletter_issue_id,
+            subscriber_email,
+            status,
+            n_attempts,
+            provider_message_id,
+            last_error,
+            updated_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, now())
+        ON CONFLICT (newsletter_issue_id, subscriber_email) DO UPDATE
+        SET
+            status = EXCLUDED.status,
+            n_attempts = issue_deliveries.n_attempts + EXCLUDED.n_attempts,
+            provider_message_id = COALESCE(
+                EXCLUDED.provider_message_id,
+                issue_deliveries.provider_message_id
+            ),
+            last_error = COALESCE(EXCLUDED.last_error, issue_deliveries.last_error),
+            updated_at = EXCLUDED.updated_at
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        status.as_str(),
+        n_attempts,
+        provider_message_id,
+        error,
+    )
+    .execute(transaction)
+    .await?;
+
+    Ok(())
 }
 
+/// Put a failed task back in the queue, to be picked up again after a delay.
 #[tracing::instrument(skip_all)]
-async fn delete_task(
-    mut transaction: PgTransaction,
-    issue_id: Uuid,
-    email: &str,
+async fn reschedule_task(
+    transaction: &mut PgTransaction,
+    task: &Task,
 ) -> Result<(), anyhow::Error> {
+    let execute_after = Utc::now() + chrono::Duration::from_std(retry_delay(task.n_retries))?;
     sqlx::query!(
         r#"
-        DELETE FROM issue_delivery_queue
+        UPDATE issue_delivery_queue
+        SET
+            n_retries = n_retries + 1,
+            execute_after = $3
         WHERE
             newsletter_issue_id = $1  AND
             subscriber_email = $2
         "#,
-        issue_id,
-        email
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        execute_after
     )
-    .execute(&mut transaction)
+    .execute(transaction)
     .await?;
 
-    transaction.commit().await?;
     Ok(())
 }
 
-struct NewsletterIssue {
-    title: String,
-    text_content: String,
-    html_content: String,
+/// Give up on a task, keeping a record of it for the admins to look into.
+#[tracing::instrument(skip_all)]
+async fn move_task_to_failures(
+    transaction: &mut PgTransaction,
+    task: &Task,
+    error: &str,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_failures (
+            newsletter_issue_id,
+            subscriber_email,
+            n_retries,
+            last_error,
+            failed_at
+        )
+        VALUES ($1, $2, $3, $4, now())
+        ON CONFLICT (newsletter_issue_id, subscriber_email) DO UPDATE
+        SET
+            n_retries = EXCLUDED.n_retries,
+            last_error = EXCLUDED.last_error,
+            failed_at = EXCLUDED.failed_at
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        task.n_retries,
+        error
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    delete_task(transaction, task).await
+}
+
+/// Exponential backoff with jitter: the n-th retry waits a random amount of
+/// time between half and all of `BASE_RETRY_DELAY * 2^n`, capped to
+/// `MAX_RETRY_DELAY`, so that tasks which failed together do not all
+/// hit the email API again at the same time.
+fn retry_delay(n_retries: i16) -> Duration {
+    let exponent = n_retries.clamp(0, 16) as u32;
+    let delay = BASE_RETRY_DELAY
+        .saturating_mul(2u32.pow(exponent))
+        .min(MAX_RETRY_DELAY);
+    let jitter = thread_rng().gen_range(0.5..=1.0);
+    delay.mul_f64(jitter)
+}
+
+pub(crate) struct NewsletterIssue {
+    pub(crate) title: String,
+    pub(crate) text_content: String,
+    pub(crate) html_content: String,
+    pub(crate) slug: Option<String>,
+    pub(crate) in_public_archive: bool,
+    pub(crate) track_engagement: bool,
 }
 
 #[tracing::instrument(skip_all)]
@@ -132,7 +759,7 @@ async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, any
     let issue = sqlx::query_as!(
         NewsletterIssue,
         r#"
-        SELECT title, text_content, html_content
+        SELECT title, text_content, html_content, slug, in_public_archive, track_engagement
         FROM newsletter_issues
         WHERE
             newsletter_issue_id = $1
@@ -145,23 +772,274 @@ async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, any
     Ok(issue)
 }
 
-async fn worker_loop(pool: PgPool, email_client: EmailClient) -> Result<(), anyhow::Error> {
-    loop {
-        match try_execute_task(&pool, &email_client).await {
-            Ok(ExecutionOutcome::EmptyQueue) => {
-                tokio::time::sleep(Duration::from_secs(10)).await;
+/// Publish the scheduled issues whose `send_at` has come, queueing their
+/// deliveries. Returns how many issues were published.
+#[tracing::instrument(skip_all)]
+pub async fn publish_due_issues(
+    pool: &PgPool,
+    hmac_secret: &Secret<String>,
+) -> Result<usize, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let due_issues = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id
+        FROM newsletter_issues
+        WHERE status = 'scheduled' AND send_at <= now()
+        FOR UPDATE
+        SKIP LOCKED
+        "#
+    )
+    .fetch_all(&mut transaction)
+    .await?;
+
+    for issue in &due_issues {
+        sqlx::query!(
+            r#"
+            UPDATE newsletter_issues
+            SET status = 'published', published_at = now()
+            WHERE newsletter_issue_id = $1
+            "#,
+            issue.newsletter_issue_id
+        )
+        .execute(&mut transaction)
+        .await?;
+        enqueue_delivery_tasks(&mut transaction, issue.newsletter_issue_id, hmac_secret).await?;
+    }
+    transaction.commit().await?;
+
+    Ok(due_issues.len())
+}
+
+/// The channel `enqueue_delivery_tasks` notifies when there is new work.
+const NEW_TASKS_CHANNEL: &str = "issue_delivery_queue";
+/// Notifications can get lost, e.g. while the listener is reconnecting:
+/// we never wait longer than this before checking the queue again.
+const SAFETY_NET_POLL_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// How often we check the queue if we can't listen for notifications.
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+/// How often we look for subscribers who keep soft-bouncing.
+const LIST_HYGIENE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Wake up the delivery worker once `transaction` is committed.
+#[tracing::instrument(skip_all)]
+pub async fn notify_new_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!("SELECT pg_notify($1, '')", NEW_TASKS_CHANNEL)
+        .execute(transaction)
+        .await?;
+    Ok(())
+}
+
+async fn listen_for_new_tasks(pool: &PgPool) -> Result<PgListener, sqlx::Error> {
+    let mut listener = PgListener::connect_with(pool).await?;
+    listener.listen(NEW_TASKS_CHANNEL).await?;
+    Ok(listener)
+}
+
+/// Sleep until we are notified of new tasks or a rescheduled task is due,
+/// whichever comes first.
+async fn wait_for_new_tasks(pool: &PgPool, listener: Option<&mut PgListener>) {
+    let listener = match listener {
+        Some(listener) => listener,
+        None => return tokio::time::sleep(POLL_INTERVAL).await,
+    };
+
+    let timeout = match time_until_next_task(pool).await {
+        Ok(Some(delay)) => delay.min(SAFETY_NET_POLL_INTERVAL),
+        Ok(None) => SAFETY_NET_POLL_INTERVAL,
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "failed to check when the next delivery task is due"
+            );
+            POLL_INTERVAL
+        }
+    };
+    if let Ok(Err(e)) = tokio::time::timeout(timeout, listener.recv()).await {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "failed to receive a notification for new delivery tasks"
+        );
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+/// How long until a task that has been rescheduled for later, or a scheduled
+/// issue, is due. `None` if there is nothing to wait for.
+/// Tasks that are already due are left out: we only get here when there is
+/// none we can lock, so they are being executed by another worker.
+#[tracing::instrument(skip_all)]
+pub async fn time_until_next_task(pool: &PgPool) -> Result<Option<Duration>, anyhow::Error> {
+    let r = sqlx::query!(
+        r#"
+        SELECT LEAST(
+            (SELECT MIN(execute_after) FROM issue_delivery_queue WHERE execute_after > now()),
+            (
+                SELECT MIN(send_at) FROM newsletter_issues
+                WHERE status = 'scheduled' AND send_at > now()
+            )
+        ) AS next_execute_after
+        "#
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(r.next_execute_after
+        .map(|next| (next - Utc::now()).to_std().unwrap_or(Duration::ZERO)))
+}
+
+/// Resolves once `shutdown` has been cancelled and `deadline` has elapsed
+/// since: the batch in flight at that point is abandoned.
+async fn shutdown_deadline(shutdown: &CancellationToken, deadline: Duration) {
+    shutdown.cancelled().await;
+    tokio::time::sleep(deadline).await;
+}
+
+async fn worker_loop(
+    pool: PgPool,
+    email_client: EmailClient,
+    base_url: String,
+    hmac_secret: Secret<String>,
+    settings: DeliveryWorkerSettings,
+    shutdown: CancellationToken,
+    shutdown_timeout: Duration,
+) -> Result<(), anyhow::Error> {
+    let send_permits = Semaphore::new(settings.concurrency.max(1));
+    let mut listener = match listen_for_new_tasks(&pool).await {
+        Ok(listener) => Some(listener),
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "failed to listen for new delivery tasks. Falling back to polling."
+            );
+            None
+        }
+    };
+    let mut last_list_hygiene: Option<Instant> = None;
+    while !shutdown.is_cancelled() {
+        if let Err(e) = publish_due_issues(&pool, &hmac_secret).await {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "failed to publish scheduled newsletter issues"
+            );
+        }
+        if last_list_hygiene.is_none_or(|t| t.elapsed() >= LIST_HYGIENE_INTERVAL) {
+            last_list_hygiene = Some(Instant::now());
+            match mark_inactive_subscribers(&pool, settings.soft_bounce_threshold).await {
+                Ok(0) => {}
+                Ok(n) => tracing::info!(n_subscribers = n, "marked subscribers as inactive"),
+                Err(e) => tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "failed to mark soft-bouncing subscribers as inactive"
+                ),
+            }
+        }
+        let confirmations = tokio::select! {
+            outcome = try_send_queued_confirmations(
+                &pool,
+                &email_client,
+                &base_url,
+                settings.batch_size,
+                &send_permits,
+            ) => outcome,
+            _ = shutdown_deadline(&shutdown, shutdown_timeout) => {
+                tracing::warn!("the current confirmation batch did not complete in time, rolling it back");
+                break;
+            }
+        };
+        let outcome = tokio::select! {
+            outcome = try_execute_tasks(
+                &pool,
+                &email_client,
+                &base_url,
+                &hmac_secret,
+                settings.batch_size,
+                &send_permits,
+            ) => outcome,
+            _ = shutdown_deadline(&shutdown, shutdown_timeout) => {
+                // Dropping the batch drops its transaction, which rolls back:
+                // the tasks go back to the queue for the next worker to pick up.
+                tracing::warn!("the current delivery batch did not complete in time, rolling it back");
+                break;
+            }
+        };
+        let sent_confirmations = matches!(confirmations, Ok(ExecutionOutcome::TaskCompleted));
+        match outcome {
+            Ok(ExecutionOutcome::EmptyQueue) if !sent_confirmations => {
+                tokio::select! {
+                    _ = wait_for_new_tasks(&pool, listener.as_mut()) => {}
+                    _ = shutdown.cancelled() => {}
+                }
             }
             Err(_) => {
-                tokio::time::sleep(Duration::from_secs(1)).await;
+                tokio::select! {
+                    _ = tokio::time::sleep(Duration::from_secs(1)) => {}
+                    _ = shutdown.cancelled() => {}
+                }
             }
-            Ok(ExecutionOutcome::TaskCompleted) => {}
+            Ok(_) => {}
         }
     }
+    Ok(())
 }
 
-pub async fn run_worker_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+/// Deliver newsletter issues until `shutdown` is cancelled, then finish the
+/// batch in progress and return.
+pub async fn run_worker_until_stopped(
+    configuration: Settings,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
     let connection_pool = get_connection_pool(&configuration.database);
-    let email_client = configuration.email_client.client();
+    let email_client = configuration.email_client();
+    let shutdown_timeout = configuration.application.shutdown_timeout();
+
+    worker_loop(
+        connection_pool,
+        email_client,
+        configuration.application.base_url,
+        configuration.application.hmac_secret,
+        configuration.delivery_worker,
+        shutdown,
+        shutdown_timeout,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{retry_delay, BASE_RETRY_DELAY, MAX_RETRY_DELAY};
+
+    #[test]
+    fn the_first_retry_waits_at_most_the_base_delay() {
+        for _ in 0..100 {
+            let delay = retry_delay(0);
+            assert!(delay >= BASE_RETRY_DELAY / 2);
+            assert!(delay <= BASE_RETRY_DELAY);
+        }
+    }
 
-    worker_loop(connection_pool, email_client).await
+    #[test]
+    fn the_retry_delay_grows_exponentially() {
+        for _ in 0..100 {
+            let delay = retry_delay(3);
+            assert!(delay >= BASE_RETRY_DELAY * 4);
+            assert!(delay <= BASE_RETRY_DELAY * 8);
+        }
+    }
+
+    #[test]
+    fn the_retry_delay_is_capped() {
+        for n_retries in [10, 100, i16::MAX] {
+            assert!(retry_delay(n_retries) <= MAX_RETRY_DELAY);
+            assert!(retry_delay(n_retries) >= Duration::from_secs(60 * 30));
+        }
+    }
 }
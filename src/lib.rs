@@ -1,11 +1,14 @@
 pub mod authentication;
 pub mod configuration;
+pub mod consent;
 pub mod domain;
 pub mod email_client;
+pub mod erasure;
 pub mod idempotency;
 pub mod issue_delivery_worker;
 pub mod routes;
 pub mod session_state;
 pub mod startup;
+pub mod suppression_list;
 pub mod telemetry;
 pub mod utils;
@@ -1,6 +1,7 @@
 use std::fmt::{Debug, Display};
 
 use tokio::task::JoinError;
+use tokio_util::sync::CancellationToken;
 use zero2prod::configuration::get_configuration;
 use zero2prod::issue_delivery_worker::run_worker_until_stopped;
 use zero2prod::startup::Application;
@@ -13,17 +14,55 @@ async fn main() -> anyhow::Result<()> {
 
     let configuration = get_configuration().expect("failed to read configuration");
     let application = Application::build(configuration.clone()).await?;
-    let application_task = tokio::spawn(application.run_until_stopped());
-    let worker_task = tokio::spawn(run_worker_until_stopped(configuration));
 
+    let shutdown = CancellationToken::new();
+    tokio::spawn(cancel_on_signal(shutdown.clone()));
+    let mut application_task = tokio::spawn(application.run_until_stopped(shutdown.clone()));
+    let mut worker_task = tokio::spawn(run_worker_until_stopped(configuration, shutdown.clone()));
+
+    // Whichever task exits first, make sure the other one is given the
+    // chance to wind down instead of being killed halfway through.
     tokio::select! {
-        o = application_task => report_exit("API", o),
-        o = worker_task => report_exit("Background worker", o)
+        o = &mut application_task => {
+            report_exit("API", o);
+            shutdown.cancel();
+            report_exit("Background worker", worker_task.await);
+        }
+        o = &mut worker_task => {
+            report_exit("Background worker", o);
+            shutdown.cancel();
+            report_exit("API", application_task.await);
+        }
     }
 
     Ok(())
 }
 
+/// Cancel `shutdown` on Ctrl-C or, on Unix, when we receive a SIGTERM.
+async fn cancel_on_signal(shutdown: CancellationToken) {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to listen for Ctrl-C");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    tracing::info!("shutdown requested, finishing in-flight work");
+    shutdown.cancel();
+}
+
 fn report_exit(task_name: &str, outcome: Result<Result<(), impl Debug + Display>, JoinError>) {
     match outcome {
         Ok(Ok(())) => {
@@ -5,14 +5,20 @@ use sqlx::PgPool;
 use uuid::Uuid;
 
 use crate::authentication::UserId;
+use crate::email_client::OutboxTransport;
 use crate::utils::e500;
 
 pub async fn admin_dashboard(
     pool: web::Data<PgPool>,
     user_id: web::ReqData<UserId>,
+    outbox: Option<web::Data<OutboxTransport>>,
 ) -> Result<HttpResponse, actix_web::Error> {
     let user_id = user_id.into_inner();
     let username = get_username(*user_id, &pool).await.map_err(e500)?;
+    let outbox_html = match outbox {
+        Some(_) => r#"<li><a href="/admin/dev/outbox">Outbox</a></li>"#,
+        None => "",
+    };
 
     Ok(HttpResponse::Ok()
         .content_type(ContentType::html())
@@ -28,6 +34,10 @@ pub async fn admin_dashboard(
         <p>Available actions:</p>
         <ol>
             <li><a href="/admin/newsletters">Create new newsletter</a></li>
+            <li><a href="/admin/newsletters/history">Past issues</a></li>
+            <li><a href="/admin/newsletters/failures">Failed deliveries</a></li>
+            <li><a href="/admin/subscribers">Subscribers</a></li>
+            {outbox_html}
             <li><a href="/admin/password">Change password</a></li>
             <li>
               <form name="logoutForm" action="/admin/logout" method="post">
@@ -1,9 +1,13 @@
 mod dashboard;
 mod logout;
 mod newsletters;
+mod outbox;
 mod password;
+mod subscribers;
 
 pub use dashboard::admin_dashboard;
 pub use logout::log_out;
 pub use newsletters::*;
+pub use outbox::{dev_outbox, dev_outbox_email};
 pub use password::*;
+pub use subscribers::*;
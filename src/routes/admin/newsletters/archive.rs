@@ -0,0 +1,54 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::utils::{e500, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct ArchiveFormData {
+    in_public_archive: bool,
+}
+
+/// Add an issue to, or remove it from, the public archive at `/issues`.
+#[tracing::instrument(name = "change the archive visibility of an issue", skip(form, pool))]
+pub async fn set_archive_visibility(
+    newsletter_issue_id: web::Path<Uuid>,
+    form: web::Form<ArchiveFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET
+            in_public_archive = $2,
+            archive_updated_at = CASE
+                WHEN in_public_archive = $2 THEN archive_updated_at
+                ELSE now()
+            END
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+        form.in_public_archive
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("failed to update the archive visibility of the issue")
+    .map_err(e500)?
+    .rows_affected();
+    if n_updated == 0 {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    if form.in_public_archive {
+        FlashMessage::info("The issue is now in the public archive.").send();
+    } else {
+        FlashMessage::info("The issue has been removed from the public archive.").send();
+    }
+    Ok(see_other(&format!(
+        "/admin/newsletters/{}",
+        newsletter_issue_id
+    )))
+}
@@ -0,0 +1,446 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use chrono::Utc;
+use htmlescape::{encode_attribute, encode_minimal};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::post::{insert_newsletter_issue, publish_issue, scheduled_message, success_message};
+use super::schedule::parse_send_at;
+use crate::authentication::UserId;
+use crate::domain::{IssueSlug, SubscriberEmail};
+use crate::email_client::EmailClient;
+use crate::idempotency::{save_response, try_processing, IdempotencyKey, NextAction};
+use crate::issue_delivery_worker::{personalise_email, NewsletterIssue, Task};
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+use crate::utils::{e400, e500, see_other};
+
+struct IssueContent {
+    title: String,
+    text_content: String,
+    html_content: String,
+    status: String,
+    in_public_archive: bool,
+    track_engagement: bool,
+}
+
+#[derive(serde::Deserialize)]
+pub struct DraftFormData {
+    title: String,
+    text: String,
+    html: String,
+    /// Keep the issue out of the public archive at `/issues`.
+    #[serde(default)]
+    hide_from_archive: bool,
+}
+
+fn draft_page(newsletter_issue_id: Uuid) -> String {
+    format!("/admin/newsletters/drafts/{}", newsletter_issue_id)
+}
+
+#[tracing::instrument(
+    name = "save a new draft",
+    skip_all,
+    fields(user_id=%*user_id)
+)]
+pub async fn create_draft(
+    form: web::Form<DraftFormData>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    let issue_id = insert_newsletter_issue(
+        &mut transaction,
+        *user_id,
+        &form.title,
+        &form.text,
+        &form.html,
+        !form.hide_from_archive,
+    )
+    .await
+    .context("failed to store the draft")
+    .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("failed to commit the new draft")
+        .map_err(e500)?;
+
+    FlashMessage::info("The draft has been saved.").send();
+    Ok(see_other(&draft_page(issue_id)))
+}
+
+pub async fn edit_draft_form(
+    newsletter_issue_id: web::Path<Uuid>,
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let issue = match get_issue_content(&pool, newsletter_issue_id)
+        .await
+        .map_err(e500)?
+    {
+        Some(issue) => issue,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    if issue.status != "draft" {
+        return Ok(see_other(&format!(
+            "/admin/newsletters/{}",
+            newsletter_issue_id
+        )));
+    }
+
+    let msg_html: String = flash_messages
+        .iter()
+        .map(|m| format!("<p><i>{}</i></p>", m.content()))
+        .collect();
+    let idempotency_key = Uuid::new_v4().to_string();
+    let title = encode_attribute(&issue.title);
+    let html_content = encode_minimal(&issue.html_content);
+    let text_content = encode_minimal(&issue.text_content);
+    let draft_page = draft_page(newsletter_issue_id);
+    let hidden_from_archive = if issue.in_public_archive {
+        ""
+    } else {
+        "checked"
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Edit draft</title>
+    </head>
+    <body>
+        {msg_html}
+        <form action="{draft_page}" method="post">
+            <h1>Edit draft</h1>
+            <label>Title
+                <input
+                    type="text"
+                    placeholder="Add title"
+                    name="title"
+                    value="{title}"
+                >
+            </label>
+            <br>
+            <h2>Content</h2>
+            <label>Html content</label><br>
+                <textarea
+                    placeholder="Add html newsletter"
+                    name="html"
+                >{html_content}</textarea>
+            <br>
+            <label>Plain text content</label><br>
+            <textarea
+                placeholder="Add plain text content"
+                name="text"
+            >{text_content}</textarea>
+            <br>
+            <label>
+                <input type="checkbox" name="hide_from_archive" value="true" {hidden_from_archive}>
+                Keep out of the public archive
+            </label>
+            <br>
+            <button type="submit">Save draft</button>
+        </form>
+        <p><a href="{draft_page}/preview">Preview</a></p>
+        <h2>Send a test email</h2>
+        <form action="{draft_page}/test" method="post">
+            <label>Email
+                <input
+                    type="email"
+                    placeholder="Enter an email address"
+                    name="email"
+                >
+            </label>
+            <button type="submit">Send test email</button>
+        </form>
+        <h2>Publish</h2>
+        <form action="{draft_page}/publish" method="post">
+            <label>Send at (UTC, leave empty to send now)
+                <input
+                    type="datetime-local"
+                    name="send_at"
+                >
+            </label>
+            <input hidden type="text" name="idempotency_key" value="{idempotency_key}" >
+            <button type="submit">Publish</button>
+        </form>
+        <p><a href="/admin/newsletters">&lt;- Back</a></p>
+    </body>
+</html>"#,
+        )))
+}
+
+#[tracing::instrument(name = "save a draft", skip(form, pool))]
+pub async fn save_draft(
+    newsletter_issue_id: web::Path<Uuid>,
+    form: web::Form<DraftFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET title = $2, text_content = $3, html_content = $4, in_public_archive = $5
+        WHERE newsletter_issue_id = $1 AND status = 'draft'
+        "#,
+        newsletter_issue_id,
+        form.title,
+        form.text,
+        form.html,
+        !form.hide_from_archive
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("failed to update the draft")
+    .map_err(e500)?
+    .rows_affected();
+
+    if n_updated == 0 {
+        FlashMessage::error("The issue is not a draft anymore - it can no longer be edited.")
+            .send();
+        return Ok(see_other(&format!(
+            "/admin/newsletters/{}",
+            newsletter_issue_id
+        )));
+    }
+    FlashMessage::info("The draft has been saved.").send();
+    Ok(see_other(&draft_page(newsletter_issue_id)))
+}
+
+/// Show both bodies of an issue the way they were written. The HTML body is
+/// rendered in a sandboxed frame, so that it can't run scripts as the admin.
+pub async fn preview_draft(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let issue = match get_issue_content(&pool, newsletter_issue_id)
+        .await
+        .map_err(e500)?
+    {
+        Some(issue) => issue,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    let title = encode_minimal(&issue.title);
+    let html_content = encode_attribute(&issue.html_content);
+    let text_content = encode_minimal(&issue.text_content);
+    let draft_page = draft_page(newsletter_issue_id);
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Preview: {title}</title>
+    </head>
+    <body>
+        <h1>{title}</h1>
+        <h2>HTML</h2>
+        <iframe sandbox srcdoc="{html_content}" width="100%" height="400"></iframe>
+        <h2>Plain text</h2>
+        <pre>{text_content}</pre>
+        <p><a href="{draft_page}">&lt;- Back</a></p>
+    </body>
+</html>"#,
+        )))
+}
+
+#[derive(serde::Deserialize)]
+pub struct TestEmailFormData {
+    email: String,
+}
+
+/// The draft as subscribers will get it once published, with its links
+/// pointing to a subscriber who doesn't exist.
+#[tracing::instrument(
+    name = "send a test email",
+    skip(form, pool, email_client, base_url, hmac_secret)
+)]
+pub async fn send_test_email(
+    newsletter_issue_id: web::Path<Uuid>,
+    form: web::Form<TestEmailFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let draft_page = draft_page(newsletter_issue_id);
+    let issue = match get_issue_content(&pool, newsletter_issue_id)
+        .await
+        .map_err(e500)?
+    {
+        Some(issue) => issue,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let recipient = match SubscriberEmail::parse(form.0.email) {
+        Ok(recipient) => recipient,
+        Err(_) => {
+            FlashMessage::error("The test email address is not valid.").send();
+            return Ok(see_other(&draft_page));
+        }
+    };
+
+    let subject = format!("[Test] {}", issue.title);
+    let task = Task {
+        newsletter_issue_id,
+        subscriber_email: recipient.as_ref().to_owned(),
+        subscriber_id: Some(Uuid::nil()),
+        n_retries: 0,
+    };
+    // Drafts get their permalink when they are published
+    let slug = IssueSlug::new(&issue.title, newsletter_issue_id);
+    let issue = NewsletterIssue {
+        title: issue.title,
+        text_content: issue.text_content,
+        html_content: issue.html_content,
+        slug: Some(slug.as_ref().to_owned()),
+        in_public_archive: issue.in_public_archive,
+        track_engagement: issue.track_engagement,
+    };
+    let email = personalise_email(
+        &task,
+        &issue,
+        email_client.sender(),
+        &base_url.0,
+        &hmac_secret.0,
+    )
+    .map_err(|_| e500("the test email could not be personalised"))?;
+    if let Err(e) = email_client
+        .send_email_with_headers(
+            &email.recipient,
+            &subject,
+            &email.html_content,
+            &email.text_content,
+            &email.headers,
+        )
+        .await
+    {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "failed to send a test email"
+        );
+        FlashMessage::error("The test email could not be sent.").send();
+        return Ok(see_other(&draft_page));
+    }
+
+    FlashMessage::info(format!(
+        "A test email has been sent to {}.",
+        encode_minimal(recipient.as_ref())
+    ))
+    .send();
+    Ok(see_other(&draft_page))
+}
+
+#[derive(serde::Deserialize)]
+pub struct PublishDraftFormData {
+    /// When to send the issue, empty to send it straight away.
+    #[serde(default)]
+    send_at: String,
+    idempotency_key: String,
+}
+
+#[tracing::instrument(
+    name = "publish a draft"
+    skip(form, user_id, pool, hmac_secret),
+    fields(user_id=%*user_id)
+)]
+pub async fn publish_draft(
+    newsletter_issue_id: web::Path<Uuid>,
+    form: web::Form<PublishDraftFormData>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let PublishDraftFormData {
+        send_at,
+        idempotency_key,
+    } = form.0;
+    let idempotency_key: IdempotencyKey = idempotency_key.try_into().map_err(e400)?;
+    let send_at = match parse_send_at(&send_at) {
+        // A time in the past is as good as no time at all
+        Ok(send_at) => send_at.filter(|send_at| *send_at > Utc::now()),
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&draft_page(newsletter_issue_id)));
+        }
+    };
+
+    let mut transaction = match try_processing(&pool, &idempotency_key, *user_id)
+        .await
+        .map_err(e500)?
+    {
+        NextAction::StartProcessing(t) => *t,
+        NextAction::ReturnSavedResponse(saved_response) => {
+            success_message().send();
+            return Ok(saved_response);
+        }
+    };
+
+    let published = publish_issue(
+        &mut transaction,
+        newsletter_issue_id,
+        send_at,
+        &hmac_secret.0,
+    )
+    .await
+    .context("failed to publish the draft")
+    .map_err(e500)?;
+    if !published {
+        FlashMessage::error("The issue is not a draft anymore - it has already been published.")
+            .send();
+        return Ok(see_other(&format!(
+            "/admin/newsletters/{}",
+            newsletter_issue_id
+        )));
+    }
+    let response = see_other("/admin/newsletters");
+    let response = save_response(transaction, &idempotency_key, *user_id, response)
+        .await
+        .map_err(e500)?;
+    match send_at {
+        Some(send_at) => scheduled_message(send_at).send(),
+        None => success_message().send(),
+    }
+    Ok(response)
+}
+
+#[tracing::instrument(name = "get newsletter issue content", skip(pool))]
+async fn get_issue_content(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<IssueContent>, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        IssueContent,
+        r#"
+        SELECT title, text_content, html_content, status, in_public_archive, track_engagement
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("failed to retrieve newsletter issue")?;
+
+    Ok(issue)
+}
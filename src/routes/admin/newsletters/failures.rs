@@ -0,0 +1,90 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::utils::e500;
+
+struct DeliveryFailure {
+    newsletter_issue_id: Uuid,
+    title: String,
+    subscriber_email: String,
+    n_retries: i16,
+    last_error: String,
+    failed_at: DateTime<Utc>,
+}
+
+pub async fn delivery_failures(pool: web::Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+    let failures = get_delivery_failures(&pool).await.map_err(e500)?;
+
+    let mut rows_html = String::new();
+    for f in &failures {
+        writeln!(
+            rows_html,
+            "<tr><td><a href=\"/admin/newsletters/{}\">{}</a></td>\
+            <td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            f.newsletter_issue_id,
+            encode_minimal(&f.title),
+            encode_minimal(&f.subscriber_email),
+            f.n_retries,
+            encode_minimal(&f.last_error),
+            f.failed_at.to_rfc2822(),
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Failed deliveries</title>
+    </head>
+    <body>
+        <h1>Failed deliveries</h1>
+        <p>These emails could not be delivered, even after retrying.</p>
+        <table>
+            <tr>
+                <th>Issue</th>
+                <th>Recipient</th>
+                <th>Retries</th>
+                <th>Last error</th>
+                <th>Failed at</th>
+            </tr>
+            {rows_html}
+        </table>
+        <p><a href="/admin/dashboard">&lt;- Back</a></p>
+    </body>
+</html>"#,
+        )))
+}
+
+#[tracing::instrument(name = "get delivery failures", skip(pool))]
+async fn get_delivery_failures(pool: &PgPool) -> Result<Vec<DeliveryFailure>, anyhow::Error> {
+    let failures = sqlx::query_as!(
+        DeliveryFailure,
+        r#"
+        SELECT
+            newsletter_issue_id,
+            newsletter_issues.title,
+            issue_delivery_failures.subscriber_email,
+            issue_delivery_failures.n_retries,
+            issue_delivery_failures.last_error,
+            issue_delivery_failures.failed_at
+        FROM issue_delivery_failures
+        JOIN newsletter_issues USING (newsletter_issue_id)
+        ORDER BY failed_at DESC
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to retrieve delivery failures")?;
+
+    Ok(failures)
+}
@@ -1,16 +1,53 @@
 use actix_web::http::header::ContentType;
-use actix_web::HttpResponse;
+use actix_web::{web, HttpResponse};
 use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
 use uuid::Uuid;
 
+use crate::utils::e500;
+
 pub async fn send_newsletter_form(
     flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
 ) -> Result<HttpResponse, actix_web::Error> {
     let idempotency_key = Uuid::new_v4().to_string();
     let msg_html: String = flash_messages
         .iter()
         .map(|m| format!("<p><i>{}</i></p>", m.content()))
         .collect();
+    let drafts_html: String = get_drafts(&pool)
+        .await
+        .map_err(e500)?
+        .into_iter()
+        .map(|(id, title)| {
+            format!(
+                r#"<li><a href="/admin/newsletters/drafts/{id}">{}</a></li>"#,
+                encode_minimal(&title)
+            )
+        })
+        .collect();
+    let recent_issues_html: String = get_recent_issues(&pool)
+        .await
+        .map_err(e500)?
+        .into_iter()
+        .map(|issue| {
+            let status = match (issue.status.as_str(), issue.send_at) {
+                ("scheduled", Some(send_at)) => {
+                    format!(" (scheduled for {})", send_at.to_rfc2822())
+                }
+                ("cancelled", _) => " (cancelled)".into(),
+                _ => String::new(),
+            };
+            format!(
+                r#"<li><a href="/admin/newsletters/{}">{}</a>{status}</li>"#,
+                issue.newsletter_issue_id,
+                encode_minimal(&issue.title)
+            )
+        })
+        .collect();
 
     Ok(HttpResponse::Ok()
         .content_type(ContentType::html())
@@ -45,12 +82,79 @@ pub async fn send_newsletter_form(
                 placeholder="Add plain text content"
                 name="text"
             ></textarea>
+            <br>
+            <label>Send at (UTC, leave empty to send now)
+                <input
+                    type="datetime-local"
+                    name="send_at"
+                >
+            </label>
+            <br>
+            <label>
+                <input type="checkbox" name="hide_from_archive" value="true">
+                Keep out of the public archive
+            </label>
             <input hidden type="text" name="idempotency_key" value="{idempotency_key}" >
             <br>
             <button type="submit">Send newsletter</button>
+            <button type="submit" formaction="/admin/newsletters/drafts">Save as draft</button>
         </form>
+        <h2>Drafts</h2>
+        <ul>
+            {drafts_html}
+        </ul>
+        <h2>Recent issues</h2>
+        <ul>
+            {recent_issues_html}
+        </ul>
         <p><a href="/admin/dashboard">&lt;- Back</a></p>
     </body>
 </html>"#,
         )))
 }
+
+struct RecentIssue {
+    newsletter_issue_id: Uuid,
+    title: String,
+    status: String,
+    send_at: Option<DateTime<Utc>>,
+}
+
+#[tracing::instrument(name = "get recent newsletter issues", skip(pool))]
+async fn get_recent_issues(pool: &PgPool) -> Result<Vec<RecentIssue>, anyhow::Error> {
+    let issues = sqlx::query_as!(
+        RecentIssue,
+        r#"
+        SELECT newsletter_issue_id, title, status, send_at
+        FROM newsletter_issues
+        WHERE status <> 'draft'
+        ORDER BY COALESCE(published_at, send_at) DESC
+        LIMIT 10
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to retrieve recent newsletter issues")?;
+
+    Ok(issues)
+}
+
+#[tracing::instrument(name = "get draft newsletter issues", skip(pool))]
+async fn get_drafts(pool: &PgPool) -> Result<Vec<(Uuid, String)>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id, title
+        FROM newsletter_issues
+        WHERE status = 'draft'
+        ORDER BY title
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to retrieve draft newsletter issues")?;
+
+    Ok(rows
+        .into_iter()
+        .map(|r| (r.newsletter_issue_id, r.title))
+        .collect())
+}
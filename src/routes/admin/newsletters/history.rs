@@ -0,0 +1,183 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use htmlescape::{encode_attribute, encode_minimal};
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::utils::{contains_pattern, e500};
+
+const ISSUES_PER_PAGE: i64 = 20;
+
+#[derive(serde::Deserialize)]
+pub struct HistoryParameters {
+    /// Only show issues whose title contains this.
+    #[serde(default)]
+    q: String,
+    #[serde(default = "first_page")]
+    page: i64,
+}
+
+fn first_page() -> i64 {
+    1
+}
+
+struct PastIssue {
+    newsletter_issue_id: Uuid,
+    title: String,
+    published_at: DateTime<Utc>,
+    author: Option<String>,
+    sent: i64,
+    pending: i64,
+    failed: i64,
+}
+
+pub async fn newsletter_history(
+    parameters: web::Query<HistoryParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let HistoryParameters { q, page } = parameters.into_inner();
+    let title_pattern = contains_pattern(&q);
+    let n_issues = count_past_issues(&pool, &title_pattern)
+        .await
+        .map_err(e500)?;
+    let n_pages = ((n_issues + ISSUES_PER_PAGE - 1) / ISSUES_PER_PAGE).max(1);
+    let page = page.clamp(1, n_pages);
+    let issues = get_past_issues(&pool, &title_pattern, page)
+        .await
+        .map_err(e500)?;
+
+    let mut rows_html = String::new();
+    for i in &issues {
+        writeln!(
+            rows_html,
+            "<tr><td><a href=\"/admin/newsletters/{}\">{}</a></td>\
+            <td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            i.newsletter_issue_id,
+            encode_minimal(&i.title),
+            i.published_at.to_rfc2822(),
+            encode_minimal(i.author.as_deref().unwrap_or("unknown")),
+            i.sent,
+            i.pending,
+            i.failed,
+        )
+        .unwrap();
+    }
+    let page_link = |page: i64| {
+        format!(
+            "/admin/newsletters/history?q={}&page={}",
+            urlencoding::encode(&q),
+            page
+        )
+    };
+    let mut pagination_html = format!("Page {} of {}", page, n_pages);
+    if page > 1 {
+        pagination_html = format!(
+            r#"<a href="{}">Previous</a> {pagination_html}"#,
+            page_link(page - 1)
+        );
+    }
+    if page < n_pages {
+        write!(
+            pagination_html,
+            r#" <a href="{}">Next</a>"#,
+            page_link(page + 1)
+        )
+        .unwrap();
+    }
+    let q = encode_attribute(&q);
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Past issues</title>
+    </head>
+    <body>
+        <h1>Past issues</h1>
+        <form action="/admin/newsletters/history" method="get">
+            <label>Title
+                <input
+                    type="search"
+                    placeholder="Search by title"
+                    name="q"
+                    value="{q}"
+                >
+            </label>
+            <button type="submit">Search</button>
+        </form>
+        <table>
+            <tr>
+                <th>Title</th>
+                <th>Published at</th>
+                <th>Author</th>
+                <th>Sent</th>
+                <th>Pending</th>
+                <th>Failed</th>
+            </tr>
+            {rows_html}
+        </table>
+        <p>{pagination_html}</p>
+        <p><a href="/admin/dashboard">&lt;- Back</a></p>
+    </body>
+</html>"#,
+        )))
+}
+
+#[tracing::instrument(name = "count past newsletter issues", skip(pool))]
+async fn count_past_issues(pool: &PgPool, title_pattern: &str) -> Result<i64, anyhow::Error> {
+    let r = sqlx::query!(
+        r#"
+        SELECT count(*) AS "count!"
+        FROM newsletter_issues
+        WHERE status = 'published' AND title ILIKE $1
+        "#,
+        title_pattern
+    )
+    .fetch_one(pool)
+    .await
+    .context("failed to count past newsletter issues")?;
+
+    Ok(r.count)
+}
+
+#[tracing::instrument(name = "get past newsletter issues", skip(pool))]
+async fn get_past_issues(
+    pool: &PgPool,
+    title_pattern: &str,
+    page: i64,
+) -> Result<Vec<PastIssue>, anyhow::Error> {
+    let issues = sqlx::query_as!(
+        PastIssue,
+        r#"
+        SELECT
+            newsletter_issues.newsletter_issue_id,
+            newsletter_issues.title,
+            newsletter_issues.published_at AS "published_at!",
+            users.username AS "author?",
+            count(*) FILTER (WHERE issue_deliveries.status = 'sent') AS "sent!",
+            count(*) FILTER (WHERE issue_deliveries.status = 'pending') AS "pending!",
+            count(*) FILTER (WHERE issue_deliveries.status = 'failed') AS "failed!"
+        FROM newsletter_issues
+        LEFT JOIN users ON users.user_id = newsletter_issues.author_id
+        LEFT JOIN issue_deliveries USING (newsletter_issue_id)
+        WHERE newsletter_issues.status = 'published' AND title ILIKE $1
+        GROUP BY newsletter_issues.newsletter_issue_id, users.username
+        ORDER BY newsletter_issues.published_at DESC
+        LIMIT $2 OFFSET $3
+        "#,
+        title_pattern,
+        ISSUES_PER_PAGE,
+        (page - 1) * ISSUES_PER_PAGE
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to retrieve past newsletter issues")?;
+
+    Ok(issues)
+}
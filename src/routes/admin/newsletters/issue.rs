@@ -0,0 +1,392 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use htmlescape::{encode_attribute, encode_minimal};
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::utils::e500;
+
+struct IssueSummary {
+    title: String,
+    text_content: String,
+    html_content: String,
+    status: String,
+    send_at: Option<DateTime<Utc>>,
+    published_at: Option<DateTime<Utc>>,
+    author: Option<String>,
+    slug: Option<String>,
+    in_public_archive: bool,
+    track_engagement: bool,
+}
+
+struct FailingDelivery {
+    subscriber_email: String,
+    status: String,
+    n_attempts: i16,
+    last_error: Option<String>,
+    updated_at: DateTime<Utc>,
+}
+
+/// How many recipients opened the issue, or clicked one of its links.
+struct EngagementCounts {
+    opened: i64,
+    clicked: i64,
+}
+
+struct LinkClicks {
+    url: String,
+    clicks: i64,
+    recipients: i64,
+}
+
+#[derive(Default)]
+struct DeliveryCounts {
+    pending: i64,
+    sent: i64,
+    failed: i64,
+    skipped: i64,
+}
+
+pub async fn newsletter_issue(
+    newsletter_issue_id: web::Path<Uuid>,
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let issue = match get_issue_summary(&pool, newsletter_issue_id)
+        .await
+        .map_err(e500)?
+    {
+        Some(issue) => issue,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let counts = get_delivery_counts(&pool, newsletter_issue_id)
+        .await
+        .map_err(e500)?;
+    let failing = get_failing_deliveries(&pool, newsletter_issue_id)
+        .await
+        .map_err(e500)?;
+    let engagement = get_engagement_counts(&pool, newsletter_issue_id)
+        .await
+        .map_err(e500)?;
+    let top_links = get_top_links(&pool, newsletter_issue_id)
+        .await
+        .map_err(e500)?;
+
+    let msg_html: String = flash_messages
+        .iter()
+        .map(|m| format!("<p><i>{}</i></p>", m.content()))
+        .collect();
+    let title = encode_minimal(&issue.title);
+    let schedule_html = match (issue.status.as_str(), issue.send_at) {
+        ("scheduled", Some(send_at)) => format!(
+            r#"<p>Scheduled for {}.</p>
+        <form action="/admin/newsletters/{newsletter_issue_id}/schedule" method="post">
+            <label>Send at (UTC)
+                <input type="datetime-local" name="send_at" value="{}">
+            </label>
+            <button type="submit">Reschedule</button>
+        </form>
+        <form action="/admin/newsletters/{newsletter_issue_id}/cancel" method="post">
+            <button type="submit">Cancel</button>
+        </form>"#,
+            send_at.to_rfc2822(),
+            send_at.format("%Y-%m-%dT%H:%M"),
+        ),
+        ("cancelled", _) => "<p>This issue has been cancelled.</p>".into(),
+        ("draft", _) => format!(
+            r#"<p>This issue is a draft - <a href="/admin/newsletters/drafts/{newsletter_issue_id}">edit it</a>.</p>"#
+        ),
+        _ => String::new(),
+    };
+    let published_html = match issue.published_at {
+        Some(published_at) => format!(
+            "<p>Published on {} by {}.</p>",
+            published_at.to_rfc2822(),
+            encode_minimal(issue.author.as_deref().unwrap_or("unknown"))
+        ),
+        None => String::new(),
+    };
+    let archive_html = match (issue.in_public_archive, issue.slug.as_deref()) {
+        (true, Some(slug)) if issue.status == "published" => {
+            format!(r#"<p>This issue is in the <a href="/issues/{slug}">public archive</a>.</p>"#)
+        }
+        (true, _) => "<p>This issue will be in the public archive.</p>".into(),
+        (false, _) => "<p>This issue is not in the public archive.</p>".into(),
+    };
+    let (archive_action, archive_value) = if issue.in_public_archive {
+        ("Remove from the public archive", "false")
+    } else {
+        ("Add to the public archive", "true")
+    };
+    let (tracking_html, tracking_action, tracking_value) = if issue.track_engagement {
+        (
+            "<p>Opens and clicks are tracked.</p>",
+            "Stop tracking opens and clicks",
+            "false",
+        )
+    } else {
+        (
+            "<p>Opens and clicks are not tracked.</p>",
+            "Track opens and clicks",
+            "true",
+        )
+    };
+    let open_rate = rate(engagement.opened, counts.sent);
+    let click_rate = rate(engagement.clicked, counts.sent);
+    let mut top_links_html = String::new();
+    for l in &top_links {
+        writeln!(
+            top_links_html,
+            r#"<tr><td><a href="{}">{}</a></td><td>{}</td><td>{}</td></tr>"#,
+            encode_attribute(&l.url),
+            encode_minimal(&l.url),
+            l.clicks,
+            l.recipients,
+        )
+        .unwrap();
+    }
+    let html_content = encode_attribute(&issue.html_content);
+    let text_content = encode_minimal(&issue.text_content);
+    let DeliveryCounts {
+        pending,
+        sent,
+        failed,
+        skipped,
+    } = counts;
+    let mut failing_html = String::new();
+    for d in &failing {
+        writeln!(
+            failing_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            encode_minimal(&d.subscriber_email),
+            d.status,
+            d.n_attempts,
+            encode_minimal(d.last_error.as_deref().unwrap_or_default()),
+            d.updated_at.to_rfc2822(),
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>{title}</title>
+    </head>
+    <body>
+        {msg_html}
+        <h1>{title}</h1>
+        {published_html}
+        {schedule_html}
+        {archive_html}
+        <form action="/admin/newsletters/{newsletter_issue_id}/archive" method="post">
+            <input hidden type="text" name="in_public_archive" value="{archive_value}">
+            <button type="submit">{archive_action}</button>
+        </form>
+        <h2>Deliveries</h2>
+        <ul>
+            <li>Sent: {sent}</li>
+            <li>Pending: {pending}</li>
+            <li>Failed: {failed}</li>
+            <li>Skipped: {skipped}</li>
+        </ul>
+        <h2>Engagement</h2>
+        {tracking_html}
+        <form action="/admin/newsletters/{newsletter_issue_id}/tracking" method="post">
+            <input hidden type="text" name="track_engagement" value="{tracking_value}">
+            <button type="submit">{tracking_action}</button>
+        </form>
+        <ul>
+            <li>Open rate: {open_rate}</li>
+            <li>Click rate: {click_rate}</li>
+        </ul>
+        <h3>Top links</h3>
+        <table>
+            <tr>
+                <th>Link</th>
+                <th>Clicks</th>
+                <th>Recipients</th>
+            </tr>
+            {top_links_html}
+        </table>
+        <h2>Failing recipients</h2>
+        <table>
+            <tr>
+                <th>Recipient</th>
+                <th>Status</th>
+                <th>Attempts</th>
+                <th>Last error</th>
+                <th>Updated at</th>
+            </tr>
+            {failing_html}
+        </table>
+        <h2>Content</h2>
+        <h3>HTML</h3>
+        <iframe sandbox srcdoc="{html_content}" width="100%" height="400"></iframe>
+        <h3>Plain text</h3>
+        <pre>{text_content}</pre>
+        <p><a href="/admin/newsletters">&lt;- Back</a></p>
+    </body>
+</html>"#,
+        )))
+}
+
+#[tracing::instrument(name = "get newsletter issue summary", skip(pool))]
+async fn get_issue_summary(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<IssueSummary>, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        IssueSummary,
+        r#"
+        SELECT
+            title,
+            text_content,
+            html_content,
+            status,
+            send_at,
+            published_at,
+            users.username AS "author?",
+            slug,
+            in_public_archive,
+            track_engagement
+        FROM newsletter_issues
+        LEFT JOIN users ON users.user_id = newsletter_issues.author_id
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("failed to retrieve newsletter issue")?;
+
+    Ok(issue)
+}
+
+#[tracing::instrument(name = "count deliveries by status", skip(pool))]
+async fn get_delivery_counts(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<DeliveryCounts, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT status, count(*) AS "count!"
+        FROM issue_deliveries
+        WHERE newsletter_issue_id = $1
+        GROUP BY status
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to count deliveries")?;
+
+    let mut counts = DeliveryCounts::default();
+    for r in rows {
+        match r.status.as_str() {
+            "pending" => counts.pending = r.count,
+            "sent" => counts.sent = r.count,
+            "failed" => counts.failed = r.count,
+            "skipped" => counts.skipped = r.count,
+            other => tracing::warn!("unknown delivery status: {other}"),
+        }
+    }
+    Ok(counts)
+}
+
+/// Deliveries that failed for good, or that are waiting to be retried.
+#[tracing::instrument(name = "get failing deliveries", skip(pool))]
+async fn get_failing_deliveries(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Vec<FailingDelivery>, anyhow::Error> {
+    let deliveries = sqlx::query_as!(
+        FailingDelivery,
+        r#"
+        SELECT subscriber_email, status, n_attempts, last_error, updated_at
+        FROM issue_deliveries
+        WHERE
+            newsletter_issue_id = $1 AND
+            last_error IS NOT NULL AND
+            status IN ('failed', 'pending')
+        ORDER BY updated_at DESC
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to retrieve failing deliveries")?;
+
+    Ok(deliveries)
+}
+
+/// `part` as a percentage of the `sent` emails.
+fn rate(part: i64, sent: i64) -> String {
+    if sent == 0 {
+        return "-".into();
+    }
+    format!(
+        "{:.1}% ({part} of {sent})",
+        part as f64 * 100.0 / sent as f64
+    )
+}
+
+/// A click counts as an open too: the pixel does not load when images are
+/// blocked, but the email has obviously been read.
+#[tracing::instrument(name = "count engagement", skip(pool))]
+async fn get_engagement_counts(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<EngagementCounts, anyhow::Error> {
+    let counts = sqlx::query_as!(
+        EngagementCounts,
+        r#"
+        SELECT
+            count(DISTINCT subscriber_id) AS "opened!",
+            count(DISTINCT subscriber_id) FILTER (WHERE kind = 'click') AS "clicked!"
+        FROM issue_engagement_events
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("failed to count engagement")?;
+
+    Ok(counts)
+}
+
+#[tracing::instrument(name = "get the most clicked links", skip(pool))]
+async fn get_top_links(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Vec<LinkClicks>, anyhow::Error> {
+    let links = sqlx::query_as!(
+        LinkClicks,
+        r#"
+        SELECT
+            url AS "url!",
+            count(*) AS "clicks!",
+            count(DISTINCT subscriber_id) AS "recipients!"
+        FROM issue_engagement_events
+        WHERE newsletter_issue_id = $1 AND kind = 'click'
+        GROUP BY url
+        ORDER BY 2 DESC, 1
+        LIMIT 10
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to retrieve the most clicked links")?;
+
+    Ok(links)
+}
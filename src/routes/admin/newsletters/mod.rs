@@ -1,5 +1,21 @@
+mod archive;
+mod drafts;
+mod failures;
 mod get;
+mod history;
+mod issue;
 mod post;
+mod schedule;
+mod tracking;
 
+pub use archive::set_archive_visibility;
+pub use drafts::{
+    create_draft, edit_draft_form, preview_draft, publish_draft, save_draft, send_test_email,
+};
+pub use failures::delivery_failures;
 pub use get::*;
+pub use history::newsletter_history;
+pub use issue::newsletter_issue;
 pub use post::*;
+pub use schedule::{cancel_newsletter, reschedule_newsletter};
+pub use tracking::set_engagement_tracking;
@@ -1,11 +1,19 @@
 use actix_web::{web, HttpResponse};
 use actix_web_flash_messages::FlashMessage;
 use anyhow::Context;
+use chrono::{DateTime, Utc};
+use secrecy::Secret;
 use sqlx::{PgPool, Postgres, Transaction};
+use std::collections::HashSet;
 use uuid::Uuid;
 
+use super::schedule::parse_send_at;
 use crate::authentication::UserId;
+use crate::domain::IssueSlug;
+use crate::erasure::email_digest;
 use crate::idempotency::{save_response, try_processing, IdempotencyKey, NextAction};
+use crate::issue_delivery_worker::notify_new_tasks;
+use crate::startup::HmacSecret;
 use crate::utils::{e400, e500, see_other};
 
 #[derive(serde::Deserialize)]
@@ -13,6 +21,12 @@ pub struct FormData {
     title: String,
     text: String,
     html: String,
+    /// When to send the issue, empty to send it straight away.
+    #[serde(default)]
+    send_at: String,
+    /// Keep the issue out of the public archive at `/issues`.
+    #[serde(default)]
+    hide_from_archive: bool,
     idempotency_key: String,
 }
 
@@ -25,15 +39,26 @@ pub async fn publish_newsletter(
     form: web::Form<FormData>,
     user_id: web::ReqData<UserId>,
     pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
 ) -> Result<HttpResponse, actix_web::Error> {
     let user_id = user_id.into_inner();
     let FormData {
         title,
         text,
         html,
+        send_at,
+        hide_from_archive,
         idempotency_key,
     } = form.0;
     let idempotency_key: IdempotencyKey = idempotency_key.try_into().map_err(e400)?;
+    let send_at = match parse_send_at(&send_at) {
+        // A time in the past is as good as no time at all
+        Ok(send_at) => send_at.filter(|send_at| *send_at > Utc::now()),
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/newsletters"));
+        }
+    };
 
     let mut transaction = match try_processing(&pool, &idempotency_key, *user_id)
         .await
@@ -46,36 +71,57 @@ pub async fn publish_newsletter(
         }
     };
 
-    let issue_id = insert_newsletter_issue(&mut transaction, &title, &text, &html)
+    let issue_id = insert_newsletter_issue(
+        &mut transaction,
+        *user_id,
+        &title,
+        &text,
+        &html,
+        !hide_from_archive,
+    )
+    .await
+    .context("failed to store newsletter issue details")
+    .map_err(e500)?;
+    publish_issue(&mut transaction, issue_id, send_at, &hmac_secret.0)
         .await
-        .context("failed to store newsletter issue details")
+        .context("failed to publish the newsletter issue")
         .map_err(e500)?;
 
-    enqueue_delivery_tasks(&mut transaction, issue_id)
-        .await
-        .context("failed to enqueue delivery tasks")
-        .map_err(e500)?;
     let response = see_other("/admin/newsletters");
     let response = save_response(transaction, &idempotency_key, *user_id, response)
         .await
         .map_err(e500)?;
-    success_message().send();
+    match send_at {
+        Some(send_at) => scheduled_message(send_at).send(),
+        None => success_message().send(),
+    }
     Ok(response)
 }
 
-fn success_message() -> FlashMessage {
+pub(super) fn success_message() -> FlashMessage {
     FlashMessage::info(
         "The newsletter issue has been accepted - \
         emails will go out shortly.",
     )
 }
 
+pub(super) fn scheduled_message(send_at: DateTime<Utc>) -> FlashMessage {
+    FlashMessage::info(format!(
+        "The newsletter issue has been scheduled - \
+        emails will go out on {}.",
+        send_at.to_rfc2822()
+    ))
+}
+
+/// Store a new issue as a draft: nobody will receive it until it is published.
 #[tracing::instrument(skip_all)]
-async fn insert_newsletter_issue(
+pub(super) async fn insert_newsletter_issue(
     transaction: &mut Transaction<'_, Postgres>,
+    author_id: Uuid,
     title: &str,
     text_content: &str,
     html_content: &str,
+    in_public_archive: bool,
 ) -> Result<Uuid, sqlx::Error> {
     let newsletter_issue_id = Uuid::new_v4();
     sqlx::query!(
@@ -85,13 +131,17 @@ async fn insert_newsletter_issue(
             title,
             text_content,
             html_content,
-            published_at
+            status,
+            author_id,
+            in_public_archive
         )
-        VALUES ($1, $2, $3, $4, now())"#,
+        VALUES ($1, $2, $3, $4, 'draft', $5, $6)"#,
         newsletter_issue_id,
         title,
         text_content,
         html_content,
+        author_id,
+        in_public_archive,
     )
     .execute(transaction)
     .await?;
@@ -99,11 +149,89 @@ async fn insert_newsletter_issue(
     Ok(newsletter_issue_id)
 }
 
+/// Publish a draft: queue its deliveries straight away or, if `send_at` is
+/// set, leave it to the scheduler in the delivery worker.
+/// Returns `false` if the issue is not a draft.
+#[tracing::instrument(skip(transaction, hmac_secret))]
+pub(super) async fn publish_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    send_at: Option<DateTime<Utc>>,
+    hmac_secret: &Secret<String>,
+) -> Result<bool, sqlx::Error> {
+    let (status, published_at) = match send_at {
+        Some(_) => ("scheduled", None),
+        None => ("published", Some(Utc::now())),
+    };
+    let draft = sqlx::query!(
+        r#"
+        SELECT title
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1 AND status = 'draft'
+        FOR UPDATE
+        "#,
+        newsletter_issue_id,
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+    let draft = match draft {
+        Some(draft) => draft,
+        None => return Ok(false),
+    };
+    // The title can't change anymore, so neither will the permalink
+    let slug = IssueSlug::new(&draft.title, newsletter_issue_id);
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = $2, send_at = $3, published_at = $4, slug = $5
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+        status,
+        send_at,
+        published_at,
+        slug.as_ref(),
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    match send_at {
+        // Wake the worker up, it might be sleeping past `send_at`
+        Some(_) => notify_new_tasks(transaction).await?,
+        None => enqueue_delivery_tasks(transaction, newsletter_issue_id, hmac_secret).await?,
+    }
+    Ok(true)
+}
+
+/// Queue a delivery of `newsletter_issue_id` for every confirmed subscriber
+/// whose address is not suppressed, erased since or not: `hmac_secret` is
+/// what the digests of erased addresses were computed with.
 #[tracing::instrument(skip_all)]
-async fn enqueue_delivery_tasks(
+pub async fn enqueue_delivery_tasks(
     transaction: &mut Transaction<'_, Postgres>,
     newsletter_issue_id: Uuid,
+    hmac_secret: &Secret<String>,
 ) -> Result<(), sqlx::Error> {
+    // Erased addresses are only known by their digest, see
+    // `erasure::email_digest`: match them against every subscriber
+    let erased: HashSet<String> = sqlx::query!("SELECT email_digest FROM erased_suppressions")
+        .fetch_all(&mut *transaction)
+        .await?
+        .into_iter()
+        .map(|r| r.email_digest)
+        .collect();
+    let erased_emails: Vec<String> = if erased.is_empty() {
+        Vec::new()
+    } else {
+        sqlx::query!("SELECT email FROM subscriptions WHERE status = 'confirmed'")
+            .fetch_all(&mut *transaction)
+            .await?
+            .into_iter()
+            .map(|r| r.email)
+            .filter(|email| erased.contains(&email_digest(email, hmac_secret)))
+            .collect()
+    };
+
     sqlx::query!(
         r#"
         INSERT INTO issue_delivery_queue (
@@ -112,12 +240,35 @@ async fn enqueue_delivery_tasks(
         )
         SELECT $1, email
         FROM subscriptions
-        WHERE status = 'confirmed'
+        WHERE
+            status = 'confirmed' AND
+            lower(email) NOT IN (SELECT email FROM suppressed_emails) AND
+            email <> ALL($2)
+        "#,
+        newsletter_issue_id,
+        &erased_emails[..]
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_deliveries (
+            newsletter_issue_id,
+            subscriber_email,
+            status,
+            updated_at
+        )
+        SELECT newsletter_issue_id, subscriber_email, 'pending', now()
+        FROM issue_delivery_queue
+        WHERE newsletter_issue_id = $1
         "#,
         newsletter_issue_id
     )
-    .execute(transaction)
+    .execute(&mut *transaction)
     .await?;
 
+    notify_new_tasks(transaction).await?;
+
     Ok(())
 }
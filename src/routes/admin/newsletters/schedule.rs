@@ -0,0 +1,168 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::issue_delivery_worker::notify_new_tasks;
+use crate::utils::{e500, see_other};
+
+/// Parse the `send_at` field of the newsletter forms.
+///
+/// An empty field means "send it now". We accept RFC 3339 timestamps as well
+/// as the zone-less values submitted by `<input type="datetime-local">`,
+/// which are taken to be in UTC.
+pub fn parse_send_at(send_at: &str) -> Result<Option<DateTime<Utc>>, String> {
+    let send_at = send_at.trim();
+    if send_at.is_empty() {
+        return Ok(None);
+    }
+    if let Ok(t) = DateTime::parse_from_rfc3339(send_at) {
+        return Ok(Some(t.with_timezone(&Utc)));
+    }
+    ["%Y-%m-%dT%H:%M", "%Y-%m-%dT%H:%M:%S"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(send_at, format).ok())
+        .map(|t| Some(Utc.from_utc_datetime(&t)))
+        .ok_or_else(|| "The send time is not a valid date and time.".to_string())
+}
+
+#[derive(serde::Deserialize)]
+pub struct ScheduleFormData {
+    send_at: String,
+}
+
+#[tracing::instrument(name = "reschedule a newsletter issue", skip(form, pool))]
+pub async fn reschedule_newsletter(
+    newsletter_issue_id: web::Path<Uuid>,
+    form: web::Form<ScheduleFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let issue_page = format!("/admin/newsletters/{}", newsletter_issue_id);
+    let send_at = match parse_send_at(&form.send_at) {
+        Ok(Some(send_at)) => send_at,
+        Ok(None) => {
+            FlashMessage::error("Pick when the issue should be sent.").send();
+            return Ok(see_other(&issue_page));
+        }
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&issue_page));
+        }
+    };
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET send_at = $2
+        WHERE newsletter_issue_id = $1 AND status = 'scheduled'
+        "#,
+        newsletter_issue_id,
+        send_at
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to reschedule the newsletter issue")
+    .map_err(e500)?
+    .rows_affected();
+    if n_updated == 0 {
+        FlashMessage::error(
+            "The issue is not scheduled anymore - it has already been sent or cancelled.",
+        )
+        .send();
+        return Ok(see_other(&issue_page));
+    }
+    // The worker might be sleeping until the old `send_at`
+    notify_new_tasks(&mut transaction)
+        .await
+        .context("failed to notify the delivery worker")
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("failed to commit the new schedule")
+        .map_err(e500)?;
+
+    FlashMessage::info(format!(
+        "The issue has been rescheduled for {}.",
+        send_at.to_rfc2822()
+    ))
+    .send();
+    Ok(see_other(&issue_page))
+}
+
+#[tracing::instrument(name = "cancel a scheduled newsletter issue", skip(pool))]
+pub async fn cancel_newsletter(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'cancelled'
+        WHERE newsletter_issue_id = $1 AND status = 'scheduled'
+        "#,
+        newsletter_issue_id
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("failed to cancel the newsletter issue")
+    .map_err(e500)?
+    .rows_affected();
+
+    if n_updated == 0 {
+        FlashMessage::error(
+            "The issue is not scheduled anymore - it has already been sent or cancelled.",
+        )
+        .send();
+    } else {
+        FlashMessage::info("The issue has been cancelled - it will not be sent.").send();
+    }
+    Ok(see_other(&format!(
+        "/admin/newsletters/{}",
+        newsletter_issue_id
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use claim::{assert_err, assert_none};
+
+    use super::parse_send_at;
+
+    #[test]
+    fn an_empty_send_at_means_send_now() {
+        assert_none!(parse_send_at("").unwrap());
+        assert_none!(parse_send_at("  ").unwrap());
+    }
+
+    #[test]
+    fn datetime_local_values_are_taken_to_be_in_utc() {
+        assert_eq!(
+            parse_send_at("2030-01-02T03:04").unwrap(),
+            Some(Utc.ymd(2030, 1, 2).and_hms(3, 4, 0))
+        );
+    }
+
+    #[test]
+    fn rfc3339_values_are_converted_to_utc() {
+        assert_eq!(
+            parse_send_at("2030-01-02T03:04:05+02:00").unwrap(),
+            Some(Utc.ymd(2030, 1, 2).and_hms(1, 4, 5))
+        );
+    }
+
+    #[test]
+    fn garbage_is_rejected() {
+        assert_err!(parse_send_at("tomorrow"));
+    }
+}
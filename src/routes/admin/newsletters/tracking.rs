@@ -0,0 +1,50 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::utils::{e500, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct TrackingFormData {
+    track_engagement: bool,
+}
+
+/// Turn open and click tracking on or off for the deliveries of an issue
+/// that have not been sent yet.
+#[tracing::instrument(name = "change the engagement tracking of an issue", skip(form, pool))]
+pub async fn set_engagement_tracking(
+    newsletter_issue_id: web::Path<Uuid>,
+    form: web::Form<TrackingFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET track_engagement = $2
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+        form.track_engagement
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("failed to update the engagement tracking of the issue")
+    .map_err(e500)?
+    .rows_affected();
+    if n_updated == 0 {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    if form.track_engagement {
+        FlashMessage::info("Opens and clicks will be tracked.").send();
+    } else {
+        FlashMessage::info("Opens and clicks will not be tracked anymore.").send();
+    }
+    Ok(see_other(&format!(
+        "/admin/newsletters/{}",
+        newsletter_issue_id
+    )))
+}
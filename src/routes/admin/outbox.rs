@@ -0,0 +1,123 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use htmlescape::{encode_attribute, encode_minimal};
+use linkify::{LinkFinder, LinkKind};
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::email_client::OutboxTransport;
+use crate::utils::e500;
+
+pub async fn dev_outbox(
+    outbox: web::Data<OutboxTransport>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let emails = outbox.emails().await.map_err(e500)?;
+
+    let mut rows_html = String::new();
+    for e in &emails {
+        writeln!(
+            rows_html,
+            "<tr><td><a href=\"/admin/dev/outbox/{}\">{}</a></td><td>{}</td><td>{}</td></tr>",
+            e.id,
+            encode_minimal(&e.subject),
+            encode_minimal(&e.to),
+            e.created_at.to_rfc2822(),
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Outbox</title>
+    </head>
+    <body>
+        <h1>Outbox</h1>
+        <p>Emails are not sent in development: here is what would have gone out.</p>
+        <table>
+            <tr>
+                <th>Subject</th>
+                <th>To</th>
+                <th>Sent at</th>
+            </tr>
+            {rows_html}
+        </table>
+        <p><a href="/admin/dashboard">&lt;- Back</a></p>
+    </body>
+</html>"#,
+        )))
+}
+
+pub async fn dev_outbox_email(
+    email_id: web::Path<Uuid>,
+    outbox: web::Data<OutboxTransport>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let email = match outbox.email(*email_id).await.map_err(e500)? {
+        Some(email) => email,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    // Make links (e.g. confirmation links) one click away
+    let mut links_html = String::new();
+    for link in LinkFinder::new()
+        .links(&email.text_content)
+        .filter(|l| *l.kind() == LinkKind::Url)
+    {
+        writeln!(
+            links_html,
+            r#"<li><a href="{}">{}</a></li>"#,
+            encode_attribute(link.as_str()),
+            encode_minimal(link.as_str()),
+        )
+        .unwrap();
+    }
+    let mut headers_html = String::new();
+    for h in &email.headers {
+        writeln!(
+            headers_html,
+            "<li>{}: {}</li>",
+            encode_minimal(&h.name),
+            encode_minimal(&h.value)
+        )
+        .unwrap();
+    }
+    let subject = encode_minimal(&email.subject);
+    let from = encode_minimal(&email.from);
+    let to = encode_minimal(&email.to);
+    let sent_at = email.created_at.to_rfc2822();
+    let html_content = encode_attribute(&email.html_content);
+    let text_content = encode_minimal(&email.text_content);
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>{subject}</title>
+    </head>
+    <body>
+        <h1>{subject}</h1>
+        <p>From {from} to {to}, on {sent_at}.</p>
+        <h2>Links</h2>
+        <ul>
+            {links_html}
+        </ul>
+        <h2>Headers</h2>
+        <ul>
+            {headers_html}
+        </ul>
+        <h2>HTML</h2>
+        <iframe sandbox srcdoc="{html_content}" width="100%" height="400"></iframe>
+        <h2>Plain text</h2>
+        <pre>{text_content}</pre>
+        <p><a href="/admin/dev/outbox">&lt;- Back</a></p>
+    </body>
+</html>"#,
+        )))
+}
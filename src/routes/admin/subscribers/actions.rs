@@ -0,0 +1,196 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::detail::get_subscriber;
+use crate::authentication::UserId;
+use crate::consent::{record_consent_event, ConsentEvent, ConsentKind};
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::erasure::{self, ErasureRequester};
+use crate::routes::{
+    confirm_subscriber, delete_tokens, generate_subscription_token,
+    mark_subscriber_as_unsubscribed, send_confirmation_email, store_token,
+};
+use crate::startup::{ApplicationBaseUrl, HmacSecret, PrivacyPolicyVersion};
+use crate::utils::{e500, see_other};
+
+fn subscriber_page(subscriber_id: Uuid) -> HttpResponse {
+    see_other(&format!("/admin/subscribers/{}", subscriber_id))
+}
+
+/// Confirm a subscriber on their behalf, e.g. when they asked by email.
+#[tracing::instrument(
+    name = "manually confirm a subscriber",
+    skip(request, pool, privacy_policy_version),
+    fields(user_id=%*user_id)
+)]
+pub async fn admin_confirm_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    user_id: web::ReqData<UserId>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    privacy_policy_version: web::Data<PrivacyPolicyVersion>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let subscriber = match get_subscriber(&pool, subscriber_id).await.map_err(e500)? {
+        Some(subscriber) => subscriber,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    if subscriber.status == "suppressed" {
+        FlashMessage::error("Suppressed addresses cannot be confirmed.").send();
+        return Ok(subscriber_page(subscriber_id));
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    confirm_subscriber(&mut transaction, subscriber_id)
+        .await
+        .context("failed to confirm subscriber")
+        .map_err(e500)?;
+    // The address and user agent are the admin's: the source says so
+    let consent = ConsentEvent::from_request(
+        ConsentKind::Confirmed,
+        &format!("admin:{}", **user_id),
+        &request,
+        &privacy_policy_version.0,
+    );
+    record_consent_event(&mut transaction, subscriber_id, &consent)
+        .await
+        .context("failed to record the confirmation of a subscriber")
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("failed to commit SQL transaction to confirm a subscriber")
+        .map_err(e500)?;
+
+    FlashMessage::info("The subscriber has been confirmed.").send();
+    Ok(subscriber_page(subscriber_id))
+}
+
+#[tracing::instrument(name = "manually unsubscribe a subscriber", skip(pool))]
+pub async fn admin_unsubscribe_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let subscriber = match get_subscriber(&pool, subscriber_id).await.map_err(e500)? {
+        Some(subscriber) => subscriber,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    if subscriber.status == "suppressed" {
+        FlashMessage::error("Suppressed addresses are not emailed anyway.").send();
+        return Ok(subscriber_page(subscriber_id));
+    }
+
+    mark_subscriber_as_unsubscribed(&pool, subscriber_id)
+        .await
+        .context("failed to unsubscribe subscriber")
+        .map_err(e500)?;
+
+    FlashMessage::info("The subscriber has been unsubscribed.").send();
+    Ok(subscriber_page(subscriber_id))
+}
+
+/// Send a new confirmation link to a subscriber who has not confirmed yet,
+/// or who went inactive.
+#[tracing::instrument(
+    name = "resend a confirmation email",
+    skip(pool, email_client, base_url)
+)]
+pub async fn resend_confirmation(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let subscriber = match get_subscriber(&pool, subscriber_id).await.map_err(e500)? {
+        Some(subscriber) => subscriber,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    if subscriber.status != "pending_confirmation" && subscriber.status != "inactive" {
+        FlashMessage::error(format!(
+            "Only pending or inactive subscribers can be sent a confirmation email, \
+            this one is {}.",
+            subscriber.status
+        ))
+        .send();
+        return Ok(subscriber_page(subscriber_id));
+    }
+    let recipient = match SubscriberEmail::parse(subscriber.email) {
+        Ok(recipient) => recipient,
+        Err(e) => {
+            FlashMessage::error(format!("The email address is not valid: {}", e)).send();
+            return Ok(subscriber_page(subscriber_id));
+        }
+    };
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    // Only the link in the latest email works
+    delete_tokens(&mut transaction, subscriber_id)
+        .await
+        .context("failed to delete the previous confirmation tokens")
+        .map_err(e500)?;
+    let subscription_token = generate_subscription_token();
+    store_token(&mut transaction, subscriber_id, &subscription_token)
+        .await
+        .context("failed to store a new confirmation token")
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("failed to commit SQL transaction to store a new confirmation token")
+        .map_err(e500)?;
+    send_confirmation_email(&email_client, &recipient, &base_url.0, &subscription_token)
+        .await
+        .context("failed to send a confirmation email")
+        .map_err(e500)?;
+
+    FlashMessage::info("A new confirmation email has been sent.").send();
+    Ok(subscriber_page(subscriber_id))
+}
+
+/// Erase a subscriber with everything we know about them, e.g. when they
+/// asked by email. See `erasure::erase_subscriber`.
+#[tracing::instrument(
+    name = "manually erase a subscriber",
+    skip(pool, hmac_secret),
+    fields(user_id=%*user_id)
+)]
+pub async fn admin_erase_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let receipt = erasure::erase_subscriber(
+        &pool,
+        subscriber_id.into_inner(),
+        ErasureRequester::Admin(**user_id),
+        &hmac_secret.0,
+    )
+    .await
+    .map_err(e500)?;
+    let receipt = match receipt {
+        Some(receipt) => receipt,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    FlashMessage::info(format!(
+        "The subscriber has been erased, see receipt {}.",
+        receipt.erasure_id
+    ))
+    .send();
+    Ok(see_other("/admin/subscribers"))
+}
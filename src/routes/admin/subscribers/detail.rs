@@ -0,0 +1,242 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::consent::get_consent_trail;
+use crate::utils::e500;
+
+pub(super) struct Subscriber {
+    pub(super) email: String,
+    pub(super) name: String,
+    pub(super) status: String,
+    subscribed_at: DateTime<Utc>,
+    consecutive_soft_bounces: i16,
+}
+
+struct StatusChange {
+    status: String,
+    changed_at: DateTime<Utc>,
+}
+
+/// Only hashes of the tokens are stored: when they were sent is all we
+/// can show.
+struct SubscriptionToken {
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+}
+
+pub async fn subscriber(
+    subscriber_id: web::Path<Uuid>,
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let subscriber = match get_subscriber(&pool, subscriber_id).await.map_err(e500)? {
+        Some(subscriber) => subscriber,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let history = get_status_history(&pool, subscriber_id)
+        .await
+        .map_err(e500)?;
+    let consent_trail = get_consent_trail(pool.get_ref(), subscriber_id)
+        .await
+        .map_err(e500)?;
+    let tokens = get_subscription_tokens(&pool, subscriber_id)
+        .await
+        .map_err(e500)?;
+
+    let msg_html: String = flash_messages
+        .iter()
+        .map(|m| format!("<p><i>{}</i></p>", m.content()))
+        .collect();
+    let email = encode_minimal(&subscriber.email);
+    let name = encode_minimal(&subscriber.name);
+    let status = &subscriber.status;
+    let subscribed_at = subscriber.subscribed_at.to_rfc2822();
+    let soft_bounces = subscriber.consecutive_soft_bounces;
+    let mut history_html = String::new();
+    for c in &history {
+        writeln!(
+            history_html,
+            "<tr><td>{}</td><td>{}</td></tr>",
+            c.status,
+            c.changed_at.to_rfc2822()
+        )
+        .unwrap();
+    }
+    let mut consent_html = String::new();
+    for e in &consent_trail {
+        let optional = |s: &Option<String>| encode_minimal(s.as_deref().unwrap_or("-"));
+        writeln!(
+            consent_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            e.kind,
+            e.occurred_at.to_rfc2822(),
+            encode_minimal(&e.source),
+            optional(&e.ip_address),
+            optional(&e.user_agent),
+            optional(&e.privacy_policy_version),
+        )
+        .unwrap();
+    }
+    let mut tokens_html = String::new();
+    let now = Utc::now();
+    for t in &tokens {
+        let expiry = if t.expires_at > now {
+            "expires"
+        } else {
+            "expired"
+        };
+        writeln!(
+            tokens_html,
+            "<li>Sent {}, {} {}</li>",
+            t.created_at.to_rfc2822(),
+            expiry,
+            t.expires_at.to_rfc2822()
+        )
+        .unwrap();
+    }
+    let action = |path: &str, label: &str| {
+        format!(
+            r#"<form action="/admin/subscribers/{subscriber_id}/{path}" method="post">
+            <button type="submit">{label}</button>
+        </form>"#
+        )
+    };
+    let mut actions_html = String::new();
+    // Suppressed addresses must not be emailed, whatever their status says
+    if status != "confirmed" && status != "suppressed" {
+        actions_html.push_str(&action("confirm", "Confirm"));
+    }
+    if status == "pending_confirmation" || status == "inactive" {
+        actions_html.push_str(&action("resend-confirmation", "Resend confirmation email"));
+    }
+    if status != "unsubscribed" && status != "suppressed" {
+        actions_html.push_str(&action("unsubscribe", "Unsubscribe"));
+    }
+    actions_html.push_str(&action("erase", "Erase all their data"));
+    let data_subject_export = format!(
+        "/admin/subscribers/data-subject-export?email={}",
+        urlencoding::encode(&subscriber.email)
+    );
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>{email}</title>
+    </head>
+    <body>
+        {msg_html}
+        <h1>{email}</h1>
+        <ul>
+            <li>Name: {name}</li>
+            <li>Status: {status}</li>
+            <li>Subscribed at: {subscribed_at}</li>
+            <li>Consecutive soft bounces: {soft_bounces}</li>
+        </ul>
+        {actions_html}
+        <p><a href="{data_subject_export}">Export everything we hold about this address</a></p>
+        <h2>Status history</h2>
+        <table>
+            <tr>
+                <th>Status</th>
+                <th>Since</th>
+            </tr>
+            {history_html}
+        </table>
+        <h2>Consent</h2>
+        <table>
+            <tr>
+                <th>Event</th>
+                <th>At</th>
+                <th>Source</th>
+                <th>IP address</th>
+                <th>User agent</th>
+                <th>Privacy policy</th>
+            </tr>
+            {consent_html}
+        </table>
+        <h2>Confirmation links</h2>
+        <ul>
+            {tokens_html}
+        </ul>
+        <p><a href="/admin/subscribers">&lt;- Back</a></p>
+    </body>
+</html>"#,
+        )))
+}
+
+#[tracing::instrument(name = "get subscriber", skip(pool))]
+pub(super) async fn get_subscriber(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<Subscriber>, anyhow::Error> {
+    let subscriber = sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT email, name, status, subscribed_at, consecutive_soft_bounces
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("failed to retrieve subscriber")?;
+
+    Ok(subscriber)
+}
+
+#[tracing::instrument(name = "get subscriber status history", skip(pool))]
+async fn get_status_history(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Vec<StatusChange>, anyhow::Error> {
+    let history = sqlx::query_as!(
+        StatusChange,
+        r#"
+        SELECT status, changed_at
+        FROM subscription_status_changes
+        WHERE subscriber_id = $1
+        ORDER BY id DESC
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to retrieve the status history of a subscriber")?;
+
+    Ok(history)
+}
+
+#[tracing::instrument(name = "get subscription tokens", skip(pool))]
+async fn get_subscription_tokens(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Vec<SubscriptionToken>, anyhow::Error> {
+    let tokens = sqlx::query_as!(
+        SubscriptionToken,
+        r#"
+        SELECT created_at, expires_at
+        FROM subscription_tokens
+        WHERE subscriber_id = $1
+        ORDER BY created_at DESC
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to retrieve the subscription tokens of a subscriber")?;
+
+    Ok(tokens)
+}
@@ -0,0 +1,98 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use htmlescape::{encode_attribute, encode_minimal};
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::erasure::{check_receipt_chain, email_digest, get_erasure_receipts, ReceiptChain};
+use crate::startup::HmacSecret;
+use crate::utils::e500;
+
+#[derive(serde::Deserialize)]
+pub struct ErasuresParameters {
+    /// Only show the receipts of this address, if not empty.
+    #[serde(default)]
+    email: String,
+}
+
+/// Every erasure receipt, and whether the chain they form is intact.
+pub async fn erasure_receipts(
+    parameters: web::Query<ErasuresParameters>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let email = parameters.into_inner().email.trim().to_owned();
+    // The whole chain is needed to check it, whatever we show
+    let receipts = get_erasure_receipts(&pool, None).await.map_err(e500)?;
+    let chain_html = match check_receipt_chain(&receipts, &hmac_secret.0) {
+        ReceiptChain::Intact { n_receipts } => {
+            format!("<p>The {} receipts are intact.</p>", n_receipts)
+        }
+        ReceiptChain::BrokenAt { erasure_id } => format!(
+            "<p><strong>The receipts have been tampered with, \
+            starting with receipt {}.</strong></p>",
+            erasure_id
+        ),
+    };
+    let digest = Some(&email)
+        .filter(|e| !e.is_empty())
+        .map(|e| email_digest(e, &hmac_secret.0));
+
+    let mut rows_html = String::new();
+    for r in receipts
+        .iter()
+        .rev()
+        .filter(|r| digest.as_ref().is_none_or(|d| *d == r.email_digest))
+    {
+        writeln!(
+            rows_html,
+            "<tr><td><code>{}</code></td><td>{}</td><td>{}</td><td><code>{}</code></td></tr>",
+            r.erasure_id,
+            r.erased_at.to_rfc2822(),
+            encode_minimal(&r.requested_by),
+            encode_minimal(&r.affected_rows),
+        )
+        .unwrap();
+    }
+    let search_html = match &digest {
+        Some(_) if rows_html.is_empty() => {
+            format!("<p>{} has never been erased.</p>", encode_minimal(&email))
+        }
+        Some(_) => format!("<p>{} has been erased.</p>", encode_minimal(&email)),
+        None => String::new(),
+    };
+    let email = encode_attribute(&email);
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Erasure receipts</title>
+    </head>
+    <body>
+        <h1>Erasure receipts</h1>
+        {chain_html}
+        <form action="/admin/subscribers/erasures" method="get">
+            <label>Was this address erased?
+                <input type="email" placeholder="Enter an email address" name="email" value="{email}">
+            </label>
+            <button type="submit">Search</button>
+        </form>
+        {search_html}
+        <table>
+            <tr>
+                <th>Receipt</th>
+                <th>Erased at</th>
+                <th>Requested by</th>
+                <th>Rows deleted or anonymised</th>
+            </tr>
+            {rows_html}
+        </table>
+        <p><a href="/admin/subscribers">&lt;- Back</a></p>
+    </body>
+</html>"#,
+        )))
+}
@@ -0,0 +1,513 @@
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::web::Bytes;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use csv_async::AsyncWriterBuilder;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::consent::{get_consent_trail, ConsentRecord};
+use crate::utils::e500;
+
+/// How many subscribers are read from the database at once while exporting.
+const EXPORT_PAGE_SIZE: i64 = 1000;
+
+#[derive(serde::Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ExportParameters {
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+#[derive(serde::Serialize)]
+struct ExportedSubscriber {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+    /// When they last became confirmed, if they ever did.
+    confirmed_at: Option<DateTime<Utc>>,
+}
+
+/// Every subscriber, as CSV or JSON. The list is read and sent one page at a
+/// time, so it never has to fit in memory.
+pub async fn export_subscribers(
+    parameters: web::Query<ExportParameters>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let format = parameters.format;
+    let pages = ExportPages {
+        pool: pool.get_ref().clone(),
+        format,
+        after: None,
+        started: false,
+        done: false,
+    };
+    let body = futures::stream::try_unfold(pages, ExportPages::next);
+    let (content_type, filename) = match format {
+        ExportFormat::Csv => ("text/csv; charset=utf-8", "subscribers.csv"),
+        ExportFormat::Json => ("application/json", "subscribers.json"),
+    };
+
+    HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header(attachment(filename.into()))
+        .streaming(body)
+}
+
+struct ExportPages {
+    pool: PgPool,
+    format: ExportFormat,
+    /// The id of the last subscriber exported so far.
+    after: Option<Uuid>,
+    started: bool,
+    done: bool,
+}
+
+impl ExportPages {
+    async fn next(mut self) -> Result<Option<(Bytes, Self)>, anyhow::Error> {
+        if self.done {
+            return Ok(None);
+        }
+        let subscribers = get_export_page(&self.pool, self.after).await?;
+        self.done = subscribers.len() < EXPORT_PAGE_SIZE as usize;
+        self.after = subscribers.last().map(|s| s.id).or(self.after);
+
+        let mut chunk = Vec::new();
+        match self.format {
+            ExportFormat::Csv => {
+                let mut writer = AsyncWriterBuilder::new()
+                    .has_headers(false)
+                    .create_writer(chunk);
+                if !self.started {
+                    writer
+                        .write_record(&[
+                            "id",
+                            "email",
+                            "name",
+                            "status",
+                            "subscribed_at",
+                            "confirmed_at",
+                        ])
+                        .await?;
+                }
+                for s in &subscribers {
+                    writer
+                        .write_record(&[
+                            s.id.to_string(),
+                            s.email.clone(),
+                            s.name.clone(),
+                            s.status.clone(),
+                            s.subscribed_at.to_rfc3339(),
+                            s.confirmed_at.map(|at| at.to_rfc3339()).unwrap_or_default(),
+                        ])
+                        .await?;
+                }
+                chunk = writer.into_inner().await?;
+            }
+            ExportFormat::Json => {
+                if !self.started {
+                    chunk.push(b'[');
+                }
+                for (i, s) in subscribers.iter().enumerate() {
+                    if self.started || i > 0 {
+                        chunk.push(b',');
+                    }
+                    serde_json::to_writer(&mut chunk, s)?;
+                }
+                if self.done {
+                    chunk.push(b']');
+                }
+            }
+        }
+        self.started = true;
+
+        Ok(Some((Bytes::from(chunk), self)))
+    }
+}
+
+#[tracing::instrument(name = "get a page of subscribers to export", skip(pool))]
+async fn get_export_page(
+    pool: &PgPool,
+    after: Option<Uuid>,
+) -> Result<Vec<ExportedSubscriber>, anyhow::Error> {
+    let subscribers = sqlx::query_as!(
+        ExportedSubscriber,
+        r#"
+        SELECT
+            id,
+            email,
+            name,
+            status,
+            subscribed_at,
+            (
+                SELECT max(changed_at)
+                FROM subscription_status_changes
+                WHERE subscriber_id = subscriptions.id AND status = 'confirmed'
+            ) AS confirmed_at
+        FROM subscriptions
+        WHERE $1::uuid IS NULL OR id > $1
+        ORDER BY id
+        LIMIT $2
+        "#,
+        after,
+        EXPORT_PAGE_SIZE
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to retrieve a page of subscribers to export")?;
+
+    Ok(subscribers)
+}
+
+#[derive(serde::Deserialize)]
+pub struct DataSubjectParameters {
+    email: String,
+}
+
+/// Everything we hold about an email address, whether it is still
+/// subscribed or not, to answer a data subject access request.
+#[derive(serde::Serialize)]
+struct DataSubjectExport {
+    email: String,
+    exported_at: DateTime<Utc>,
+    subscription: Option<SubscriptionRecord>,
+    status_history: Vec<StatusChangeRecord>,
+    consent_events: Vec<ConsentRecord>,
+    confirmation_tokens: Vec<ConfirmationTokenRecord>,
+    deliveries: Vec<DeliveryRecord>,
+    queued_deliveries: Vec<QueuedDeliveryRecord>,
+    delivery_failures: Vec<DeliveryFailureRecord>,
+    email_events: Vec<EmailEventRecord>,
+    engagement_events: Vec<EngagementEventRecord>,
+    suppression: Option<SuppressionRecord>,
+    import_rejections: Vec<ImportRejectionRecord>,
+}
+
+#[derive(serde::Serialize)]
+struct SubscriptionRecord {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+    consecutive_soft_bounces: i16,
+}
+
+#[derive(serde::Serialize)]
+struct StatusChangeRecord {
+    status: String,
+    changed_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+struct ConfirmationTokenRecord {
+    token_hash: String,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+struct DeliveryRecord {
+    newsletter_issue_id: Uuid,
+    issue_title: String,
+    status: String,
+    n_attempts: i16,
+    provider_message_id: Option<String>,
+    last_error: Option<String>,
+    updated_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+struct QueuedDeliveryRecord {
+    newsletter_issue_id: Uuid,
+    issue_title: String,
+}
+
+#[derive(serde::Serialize)]
+struct DeliveryFailureRecord {
+    newsletter_issue_id: Uuid,
+    issue_title: String,
+    n_retries: i16,
+    last_error: String,
+    failed_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+struct EmailEventRecord {
+    record_type: String,
+    event_type: String,
+    provider_message_id: Option<String>,
+    description: Option<String>,
+    occurred_at: DateTime<Utc>,
+    received_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+struct EngagementEventRecord {
+    newsletter_issue_id: Uuid,
+    issue_title: String,
+    kind: String,
+    url: Option<String>,
+    occurred_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+struct SuppressionRecord {
+    reason: String,
+    suppressed_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+struct ImportRejectionRecord {
+    import_id: Uuid,
+    line: i64,
+    name: String,
+    reason: String,
+}
+
+/// Download, as JSON, every row we hold about an email address.
+#[tracing::instrument(name = "export a data subject", skip_all)]
+pub async fn export_data_subject(
+    parameters: web::Query<DataSubjectParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let email = parameters.into_inner().email.trim().to_owned();
+    if email.is_empty() {
+        return Ok(HttpResponse::BadRequest().finish());
+    }
+    let export = get_data_subject(&pool, email).await.map_err(e500)?;
+    let body = serde_json::to_vec_pretty(&export).map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .insert_header(attachment(format!(
+            "data-subject-{}.json",
+            export.exported_at.format("%Y%m%d%H%M%S")
+        )))
+        .body(body))
+}
+
+async fn get_data_subject(
+    pool: &PgPool,
+    email: String,
+) -> Result<DataSubjectExport, anyhow::Error> {
+    // A repeatable read transaction, for the sections to agree with each other
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("failed to acquire a Postgres connection from the pool")?;
+    sqlx::query!("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ READ ONLY")
+        .execute(&mut transaction)
+        .await
+        .context("failed to set the isolation level of a data subject export")?;
+
+    let subscription = sqlx::query_as!(
+        SubscriptionRecord,
+        r#"
+        SELECT id, email, name, status, subscribed_at, consecutive_soft_bounces
+        FROM subscriptions
+        WHERE lower(email) = lower($1)
+        "#,
+        email
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("failed to retrieve the subscription of a data subject")?;
+    let subscriber_id = subscription.as_ref().map(|s| s.id);
+
+    let status_history = sqlx::query_as!(
+        StatusChangeRecord,
+        r#"
+        SELECT status, changed_at
+        FROM subscription_status_changes
+        WHERE subscriber_id = $1
+        ORDER BY id
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut transaction)
+    .await
+    .context("failed to retrieve the status history of a data subject")?;
+
+    let consent_events = match subscriber_id {
+        Some(subscriber_id) => get_consent_trail(&mut transaction, subscriber_id)
+            .await
+            .context("failed to retrieve the consent trail of a data subject")?,
+        None => Vec::new(),
+    };
+
+    let confirmation_tokens = sqlx::query_as!(
+        ConfirmationTokenRecord,
+        r#"
+        SELECT token_hash, created_at, expires_at
+        FROM subscription_tokens
+        WHERE subscriber_id = $1
+        ORDER BY created_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut transaction)
+    .await
+    .context("failed to retrieve the confirmation tokens of a data subject")?;
+
+    let deliveries = sqlx::query_as!(
+        DeliveryRecord,
+        r#"
+        SELECT
+            d.newsletter_issue_id,
+            i.title AS issue_title,
+            d.status,
+            d.n_attempts,
+            d.provider_message_id,
+            d.last_error,
+            d.updated_at
+        FROM issue_deliveries d
+        JOIN newsletter_issues i USING (newsletter_issue_id)
+        WHERE lower(d.subscriber_email) = lower($1)
+        ORDER BY d.updated_at
+        "#,
+        email
+    )
+    .fetch_all(&mut transaction)
+    .await
+    .context("failed to retrieve the deliveries of a data subject")?;
+
+    let queued_deliveries = sqlx::query_as!(
+        QueuedDeliveryRecord,
+        r#"
+        SELECT q.newsletter_issue_id, i.title AS issue_title
+        FROM issue_delivery_queue q
+        JOIN newsletter_issues i USING (newsletter_issue_id)
+        WHERE lower(q.subscriber_email) = lower($1)
+        "#,
+        email
+    )
+    .fetch_all(&mut transaction)
+    .await
+    .context("failed to retrieve the queued deliveries of a data subject")?;
+
+    let delivery_failures = sqlx::query_as!(
+        DeliveryFailureRecord,
+        r#"
+        SELECT
+            f.newsletter_issue_id,
+            i.title AS issue_title,
+            f.n_retries,
+            f.last_error,
+            f.failed_at
+        FROM issue_delivery_failures f
+        JOIN newsletter_issues i USING (newsletter_issue_id)
+        WHERE lower(f.subscriber_email) = lower($1)
+        ORDER BY f.failed_at
+        "#,
+        email
+    )
+    .fetch_all(&mut transaction)
+    .await
+    .context("failed to retrieve the delivery failures of a data subject")?;
+
+    let email_events = sqlx::query_as!(
+        EmailEventRecord,
+        r#"
+        SELECT
+            record_type,
+            event_type,
+            provider_message_id,
+            description,
+            occurred_at,
+            received_at
+        FROM email_events
+        WHERE lower(email) = lower($1)
+        ORDER BY occurred_at
+        "#,
+        email
+    )
+    .fetch_all(&mut transaction)
+    .await
+    .context("failed to retrieve the email events of a data subject")?;
+
+    let engagement_events = sqlx::query_as!(
+        EngagementEventRecord,
+        r#"
+        SELECT
+            e.newsletter_issue_id,
+            i.title AS issue_title,
+            e.kind,
+            e.url,
+            e.occurred_at
+        FROM issue_engagement_events e
+        JOIN newsletter_issues i USING (newsletter_issue_id)
+        WHERE e.subscriber_id = $1
+        ORDER BY e.occurred_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut transaction)
+    .await
+    .context("failed to retrieve the engagement events of a data subject")?;
+
+    let suppression = sqlx::query_as!(
+        SuppressionRecord,
+        r#"
+        SELECT reason, suppressed_at
+        FROM suppressed_emails
+        WHERE email = lower($1)
+        "#,
+        email
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("failed to retrieve the suppression of a data subject")?;
+
+    let import_rejections = sqlx::query_as!(
+        ImportRejectionRecord,
+        r#"
+        SELECT import_id, line, name, reason
+        FROM subscriber_import_rejections
+        WHERE lower(email) = lower($1)
+        ORDER BY import_id, line
+        "#,
+        email
+    )
+    .fetch_all(&mut transaction)
+    .await
+    .context("failed to retrieve the import rejections of a data subject")?;
+
+    transaction
+        .commit()
+        .await
+        .context("failed to commit SQL transaction to export a data subject")?;
+
+    Ok(DataSubjectExport {
+        email,
+        exported_at: Utc::now(),
+        subscription,
+        status_history,
+        consent_events,
+        confirmation_tokens,
+        deliveries,
+        queued_deliveries,
+        delivery_failures,
+        email_events,
+        engagement_events,
+        suppression,
+        import_rejections,
+    })
+}
+
+fn attachment(filename: String) -> ContentDisposition {
+    ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters: vec![DispositionParam::Filename(filename)],
+    }
+}
@@ -0,0 +1,646 @@
+use actix_multipart::{Field, Multipart};
+use actix_web::http::header::{ContentDisposition, ContentType, DispositionParam, DispositionType};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use csv_async::{AsyncReaderBuilder, AsyncWriter, ByteRecord, Trim};
+use futures::TryStreamExt;
+use htmlescape::encode_minimal;
+use secrecy::Secret;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use tokio::io::{AsyncRead, AsyncWriteExt};
+use uuid::Uuid;
+
+use crate::authentication::UserId;
+use crate::consent::ConsentKind;
+use crate::domain::{SubscriberEmail, SubscriberName};
+use crate::erasure::email_digest;
+use crate::issue_delivery_worker::notify_new_tasks;
+use crate::startup::HmacSecret;
+use crate::utils::{e400, e500, see_other};
+
+/// How many lines are written to the database at once.
+const IMPORT_BATCH_SIZE: usize = 500;
+
+pub async fn import_subscribers_form(
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let msg_html: String = flash_messages
+        .iter()
+        .map(|m| format!("<p><i>{}</i></p>", m.content()))
+        .collect();
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Import subscribers</title>
+    </head>
+    <body>
+        {msg_html}
+        <h1>Import subscribers</h1>
+        <p>The CSV file must have a header line with an <code>email</code> and a
+        <code>name</code> column. Other columns are ignored.</p>
+        <form action="/admin/subscribers/import" method="post" enctype="multipart/form-data">
+            <label>Imported subscribers are
+                <select name="status">
+                    <option value="pending_confirmation">pending: send them a confirmation email</option>
+                    <option value="confirmed">confirmed: they already opted in elsewhere</option>
+                </select>
+            </label>
+            <br>
+            <label>CSV file
+                <input type="file" name="file" accept=".csv,text/csv">
+            </label>
+            <br>
+            <button type="submit">Import</button>
+        </form>
+        <p><a href="/admin/subscribers">&lt;- Back</a></p>
+    </body>
+</html>"#,
+        )))
+}
+
+/// The multipart form of `import_subscribers_form`. The `status` field
+/// must come before the `file` one, which is imported as it is uploaded.
+#[tracing::instrument(
+    name = "import subscribers",
+    skip_all,
+    fields(user_id=%*user_id, import_id=tracing::field::Empty)
+)]
+pub async fn import_subscribers(
+    mut payload: Multipart,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut status = None;
+    while let Some(mut field) = payload.try_next().await.map_err(e400)? {
+        match field.name() {
+            "status" => {
+                let mut value = Vec::new();
+                while let Some(chunk) = field.try_next().await.map_err(e400)? {
+                    value.extend_from_slice(&chunk);
+                }
+                status = Some(String::from_utf8_lossy(&value).into_owned());
+            }
+            "file" => {
+                let status = match status.as_deref() {
+                    Some(status @ ("confirmed" | "pending_confirmation")) => status.to_owned(),
+                    _ => {
+                        FlashMessage::error("Choose the status of the imported subscribers.")
+                            .send();
+                        return Ok(see_other("/admin/subscribers/import"));
+                    }
+                };
+                let importer = Importer {
+                    import_id: Uuid::new_v4(),
+                    status,
+                    pool: &pool,
+                    hmac_secret: &hmac_secret.0,
+                    first_seen_on: HashMap::new(),
+                    batch: Vec::new(),
+                    rejections: Vec::new(),
+                };
+                return import_file(importer, field, **user_id).await;
+            }
+            // Ignore anything else
+            _ => while field.try_next().await.map_err(e400)?.is_some() {},
+        }
+    }
+
+    FlashMessage::error("Choose a CSV file to import.").send();
+    Ok(see_other("/admin/subscribers/import"))
+}
+
+/// Feed the uploaded file to the CSV reader through a pipe: the reader wants
+/// something it can send across threads, which a multipart field is not.
+async fn import_file(
+    importer: Importer<'_>,
+    mut field: Field,
+    author_id: Uuid,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (mut upload, file) = tokio::io::duplex(64 * 1024);
+    let upload = async move {
+        while let Some(chunk) = field.try_next().await? {
+            if let Err(e) = upload.write_all(&chunk).await {
+                if e.kind() != std::io::ErrorKind::BrokenPipe {
+                    return Err(e.into());
+                }
+                // The import stopped before the end of the file: the rest of
+                // it still has to be read for the response to go through.
+                while field.try_next().await?.is_some() {}
+                break;
+            }
+        }
+        Ok::<_, anyhow::Error>(())
+    };
+    let (upload_outcome, import_outcome) = futures::join!(upload, importer.run(file, author_id));
+    // The import stops as soon as the file stops, complete or not
+    upload_outcome
+        .context("failed to upload the CSV file")
+        .map_err(e400)?;
+
+    match import_outcome.map_err(e500)? {
+        ImportOutcome::Imported(summary) => {
+            FlashMessage::info(format!(
+                "{} subscribers have been imported, {} lines were rejected.",
+                summary.n_imported, summary.n_rejected
+            ))
+            .send();
+            Ok(see_other(&format!(
+                "/admin/subscribers/imports/{}",
+                summary.import_id
+            )))
+        }
+        ImportOutcome::InvalidFile(e) => {
+            FlashMessage::error(format!("The file could not be imported: {}", e)).send();
+            Ok(see_other("/admin/subscribers/import"))
+        }
+    }
+}
+
+enum ImportOutcome {
+    Imported(ImportSummary),
+    /// Nothing was imported.
+    InvalidFile(String),
+}
+
+struct ImportSummary {
+    import_id: Uuid,
+    n_imported: i32,
+    n_rejected: i32,
+}
+
+/// A line that passed validation.
+struct ValidLine {
+    line: i64,
+    email: SubscriberEmail,
+    name: SubscriberName,
+}
+
+struct Rejection {
+    line: i64,
+    email: String,
+    name: String,
+    reason: String,
+}
+
+struct Importer<'a> {
+    import_id: Uuid,
+    status: String,
+    pool: &'a PgPool,
+    /// To recognise suppressed addresses that have been erased since.
+    hmac_secret: &'a Secret<String>,
+    /// Lowercased email -> the line it first appeared on, to report duplicates.
+    first_seen_on: HashMap<String, i64>,
+    batch: Vec<ValidLine>,
+    rejections: Vec<Rejection>,
+}
+
+impl Importer<'_> {
+    async fn run(
+        mut self,
+        file: impl AsyncRead + Unpin + Send,
+        author_id: Uuid,
+    ) -> Result<ImportOutcome, anyhow::Error> {
+        let mut reader = AsyncReaderBuilder::new()
+            .flexible(true)
+            .trim(Trim::All)
+            .create_reader(file);
+        let headers = match reader.byte_headers().await {
+            Ok(headers) => headers.clone(),
+            Err(e) => return Ok(ImportOutcome::InvalidFile(e.to_string())),
+        };
+        let column = |name: &str| {
+            headers
+                .iter()
+                .position(|h| h.eq_ignore_ascii_case(name.as_bytes()))
+        };
+        let (email_column, name_column) = match (column("email"), column("name")) {
+            (Some(email_column), Some(name_column)) => (email_column, name_column),
+            _ => {
+                return Ok(ImportOutcome::InvalidFile(
+                    "the header line must have an `email` and a `name` column".into(),
+                ))
+            }
+        };
+
+        tracing::Span::current().record("import_id", &tracing::field::display(self.import_id));
+        self.start(author_id).await?;
+        let mut record = ByteRecord::new();
+        loop {
+            match reader.read_byte_record(&mut record).await {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => {
+                    // We can't tell where the next line starts: stop here, and
+                    // keep what was imported so far
+                    tracing::warn!(error.message = %e, "stopping an import on an unreadable line");
+                    let line = e.position().map(|p| p.line()).unwrap_or_default();
+                    self.rejections.push(Rejection {
+                        line: line as i64,
+                        email: String::new(),
+                        name: String::new(),
+                        reason: format!("unreadable line, the import stopped here: {}", e),
+                    });
+                    break;
+                }
+            }
+            let line = record.position().map(|p| p.line()).unwrap_or_default() as i64;
+            let field = |i: usize| record.get(i).map(String::from_utf8_lossy);
+            let email = field(email_column).unwrap_or_default().into_owned();
+            let name = field(name_column).unwrap_or_default().into_owned();
+            self.check_line(line, email, name);
+            if self.batch.len() >= IMPORT_BATCH_SIZE || self.rejections.len() >= IMPORT_BATCH_SIZE {
+                self.flush().await?;
+            }
+        }
+        self.flush().await?;
+
+        let summary = self.finish().await?;
+        Ok(ImportOutcome::Imported(summary))
+    }
+
+    /// Validate a line on its own: checks against the database happen in
+    /// batches, see `flush`.
+    fn check_line(&mut self, line: i64, email: String, name: String) {
+        let parsed = SubscriberEmail::parse(email.clone()).and_then(|parsed_email| {
+            SubscriberName::parse(name.clone()).map(|parsed_name| (parsed_email, parsed_name))
+        });
+        let (parsed_email, parsed_name) = match parsed {
+            Ok(parsed) => parsed,
+            Err(reason) => {
+                self.rejections.push(Rejection {
+                    line,
+                    email,
+                    name,
+                    reason,
+                });
+                return;
+            }
+        };
+        if let Some(first_line) = self.first_seen_on.get(&email.to_lowercase()) {
+            self.rejections.push(Rejection {
+                line,
+                reason: format!("duplicate of line {}", first_line),
+                email,
+                name,
+            });
+            return;
+        }
+        self.first_seen_on.insert(email.to_lowercase(), line);
+        self.batch.push(ValidLine {
+            line,
+            email: parsed_email,
+            name: parsed_name,
+        });
+    }
+
+    #[tracing::instrument(name = "start a subscriber import", skip(self))]
+    async fn start(&self, author_id: Uuid) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO subscriber_imports (import_id, author_id, status, started_at)
+            VALUES ($1, $2, $3, now())
+            "#,
+            self.import_id,
+            author_id,
+            self.status
+        )
+        .execute(self.pool)
+        .await
+        .context("failed to record a new subscriber import")?;
+        Ok(())
+    }
+
+    /// Store the current batch of valid lines, minus the addresses we already
+    /// know about, and the rejections so far.
+    #[tracing::instrument(
+        name = "import a batch of subscribers",
+        skip(self),
+        fields(n_lines = self.batch.len())
+    )]
+    async fn flush(&mut self) -> Result<(), anyhow::Error> {
+        let batch = std::mem::take(&mut self.batch);
+        let emails: Vec<String> = batch
+            .iter()
+            .map(|l| l.email.as_ref().to_ascii_lowercase())
+            .collect();
+        let digests: Vec<String> = batch
+            .iter()
+            .map(|l| email_digest(l.email.as_ref(), self.hmac_secret))
+            .collect();
+        let known = sqlx::query!(
+            r#"
+            SELECT lower(email) AS "email!", 'already subscribed' AS "reason!"
+            FROM subscriptions
+            WHERE lower(email) = ANY($1)
+            UNION ALL
+            SELECT email, 'on the suppression list'
+            FROM UNNEST($1::text[], $2::text[]) AS batch(email, email_digest)
+            WHERE
+                email IN (SELECT email FROM suppressed_emails) OR
+                email_digest IN (SELECT email_digest FROM erased_suppressions)
+            "#,
+            &emails[..],
+            &digests[..]
+        )
+        .fetch_all(self.pool)
+        .await
+        .context("failed to look for known addresses")?;
+        let known: HashMap<String, String> =
+            known.into_iter().map(|r| (r.email, r.reason)).collect();
+
+        let mut imported = Vec::with_capacity(batch.len());
+        for l in batch {
+            match known.get(&l.email.as_ref().to_ascii_lowercase()) {
+                Some(reason) => self.rejections.push(Rejection {
+                    line: l.line,
+                    email: l.email.as_ref().to_owned(),
+                    name: l.name.as_ref().to_owned(),
+                    reason: reason.clone(),
+                }),
+                None => imported.push((Uuid::new_v4(), l)),
+            }
+        }
+        let rejections = std::mem::take(&mut self.rejections);
+
+        let mut transaction = self
+            .pool
+            .begin()
+            .await
+            .context("failed to acquire a Postgres connection from the pool")?;
+        let ids: Vec<Uuid> = imported.iter().map(|(id, _)| *id).collect();
+        sqlx::query!(
+            r#"
+            INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+            SELECT id, email, name, now(), $4
+            FROM UNNEST($1::uuid[], $2::text[], $3::text[]) AS imported(id, email, name)
+            "#,
+            &ids[..],
+            &imported
+                .iter()
+                .map(|(_, l)| l.email.as_ref().to_owned())
+                .collect::<Vec<_>>()[..],
+            &imported
+                .iter()
+                .map(|(_, l)| l.name.as_ref().to_owned())
+                .collect::<Vec<_>>()[..],
+            self.status
+        )
+        .execute(&mut transaction)
+        .await
+        .context("failed to insert imported subscribers")?;
+        // Whatever consent they gave, they gave it elsewhere: all we can
+        // vouch for is which import brought them in.
+        sqlx::query!(
+            r#"
+            INSERT INTO consent_events (subscriber_id, kind, source, occurred_at)
+            SELECT id, $2, $3, now() FROM UNNEST($1::uuid[]) AS imported(id)
+            "#,
+            &ids[..],
+            ConsentKind::Imported.as_str(),
+            format!("import:{}", self.import_id)
+        )
+        .execute(&mut transaction)
+        .await
+        .context("failed to record the consent of imported subscribers")?;
+        // The delivery worker sends the confirmation emails, see
+        // `issue_delivery_worker::try_send_queued_confirmations`
+        if self.status == "pending_confirmation" && !ids.is_empty() {
+            sqlx::query!(
+                r#"
+                INSERT INTO confirmation_email_queue (subscriber_id, import_id)
+                SELECT id, $2 FROM UNNEST($1::uuid[]) AS imported(id)
+                "#,
+                &ids[..],
+                self.import_id
+            )
+            .execute(&mut transaction)
+            .await
+            .context("failed to queue the confirmation emails of imported subscribers")?;
+            notify_new_tasks(&mut transaction)
+                .await
+                .context("failed to notify the delivery worker")?;
+        }
+        sqlx::query!(
+            r#"
+            INSERT INTO subscriber_import_rejections (import_id, line, email, name, reason)
+            SELECT $1, * FROM UNNEST($2::bigint[], $3::text[], $4::text[], $5::text[])
+            "#,
+            self.import_id,
+            &rejections.iter().map(|r| r.line).collect::<Vec<_>>()[..],
+            &rejections
+                .iter()
+                .map(|r| r.email.clone())
+                .collect::<Vec<_>>()[..],
+            &rejections
+                .iter()
+                .map(|r| r.name.clone())
+                .collect::<Vec<_>>()[..],
+            &rejections
+                .iter()
+                .map(|r| r.reason.clone())
+                .collect::<Vec<_>>()[..],
+        )
+        .execute(&mut transaction)
+        .await
+        .context("failed to store the rejected lines of an import")?;
+        sqlx::query!(
+            r#"
+            UPDATE subscriber_imports
+            SET n_imported = n_imported + $2, n_rejected = n_rejected + $3
+            WHERE import_id = $1
+            "#,
+            self.import_id,
+            imported.len() as i32,
+            rejections.len() as i32
+        )
+        .execute(&mut transaction)
+        .await
+        .context("failed to update the counts of an import")?;
+        transaction
+            .commit()
+            .await
+            .context("failed to commit SQL transaction to import subscribers")?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "finish a subscriber import", skip(self))]
+    async fn finish(self) -> Result<ImportSummary, anyhow::Error> {
+        let r = sqlx::query!(
+            r#"
+            UPDATE subscriber_imports
+            SET finished_at = now()
+            WHERE import_id = $1
+            RETURNING n_imported, n_rejected
+            "#,
+            self.import_id
+        )
+        .fetch_one(self.pool)
+        .await
+        .context("failed to mark an import as finished")?;
+
+        Ok(ImportSummary {
+            import_id: self.import_id,
+            n_imported: r.n_imported,
+            n_rejected: r.n_rejected,
+        })
+    }
+}
+
+struct SubscriberImport {
+    status: String,
+    author: Option<String>,
+    n_imported: i32,
+    n_rejected: i32,
+    n_queued_confirmations: i64,
+    n_unsent_confirmations: i32,
+    started_at: DateTime<Utc>,
+    finished_at: Option<DateTime<Utc>>,
+}
+
+/// The outcome of an import, with a link to the report of its rejected lines.
+pub async fn subscriber_import(
+    import_id: web::Path<Uuid>,
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let import_id = import_id.into_inner();
+    let import = match get_import(&pool, import_id).await.map_err(e500)? {
+        Some(import) => import,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    let msg_html: String = flash_messages
+        .iter()
+        .map(|m| format!("<p><i>{}</i></p>", m.content()))
+        .collect();
+    let started_at = import.started_at.to_rfc2822();
+    let author = encode_minimal(import.author.as_deref().unwrap_or("unknown"));
+    let finished_html = match import.finished_at {
+        Some(finished_at) => format!("<p>Finished on {}.</p>", finished_at.to_rfc2822()),
+        None => "<p>This import did not finish.</p>".into(),
+    };
+    let SubscriberImport {
+        status,
+        n_imported,
+        n_rejected,
+        n_queued_confirmations,
+        n_unsent_confirmations,
+        ..
+    } = import;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Subscriber import</title>
+    </head>
+    <body>
+        {msg_html}
+        <h1>Subscriber import</h1>
+        <p>Started on {started_at} by {author}.</p>
+        {finished_html}
+        <ul>
+            <li>Imported as {status}: {n_imported}</li>
+            <li>Rejected lines: {n_rejected}</li>
+            <li>Confirmation emails waiting to be sent: {n_queued_confirmations}</li>
+            <li>Confirmation emails that could not be sent: {n_unsent_confirmations}</li>
+        </ul>
+        <p><a href="/admin/subscribers/imports/{import_id}/rejections.csv">Download the rejected lines</a></p>
+        <p><a href="/admin/subscribers">&lt;- Back</a></p>
+    </body>
+</html>"#,
+        )))
+}
+
+/// The rejected lines of an import, with the reason they were rejected, as CSV.
+pub async fn subscriber_import_rejections(
+    import_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let import_id = import_id.into_inner();
+    if get_import(&pool, import_id).await.map_err(e500)?.is_none() {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    let rejections = sqlx::query!(
+        r#"
+        SELECT line, email, name, reason
+        FROM subscriber_import_rejections
+        WHERE import_id = $1
+        ORDER BY line
+        "#,
+        import_id
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("failed to retrieve the rejected lines of an import")
+    .map_err(e500)?;
+
+    let mut writer = AsyncWriter::from_writer(Vec::new());
+    writer
+        .write_record(&["line", "email", "name", "reason"])
+        .await
+        .map_err(e500)?;
+    for r in &rejections {
+        writer
+            .write_record(&[&r.line.to_string(), &r.email, &r.name, &r.reason])
+            .await
+            .map_err(e500)?;
+    }
+    let csv = writer.into_inner().await.map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!(
+                "rejections-{}.csv",
+                import_id
+            ))],
+        })
+        .body(csv))
+}
+
+#[tracing::instrument(name = "get subscriber import", skip(pool))]
+async fn get_import(
+    pool: &PgPool,
+    import_id: Uuid,
+) -> Result<Option<SubscriberImport>, anyhow::Error> {
+    let import = sqlx::query_as!(
+        SubscriberImport,
+        r#"
+        SELECT
+            status,
+            users.username AS "author?",
+            n_imported,
+            n_rejected,
+            (
+                SELECT count(*) FROM confirmation_email_queue
+                WHERE confirmation_email_queue.import_id = subscriber_imports.import_id
+            ) AS "n_queued_confirmations!",
+            n_unsent_confirmations,
+            started_at,
+            finished_at
+        FROM subscriber_imports
+        LEFT JOIN users ON users.user_id = subscriber_imports.author_id
+        WHERE import_id = $1
+        "#,
+        import_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("failed to retrieve subscriber import")?;
+
+    Ok(import)
+}
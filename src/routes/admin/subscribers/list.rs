@@ -0,0 +1,218 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use htmlescape::{encode_attribute, encode_minimal};
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::utils::{contains_pattern, e500};
+
+const SUBSCRIBERS_PER_PAGE: i64 = 50;
+
+/// Every status a subscriber can be in.
+const STATUSES: [&str; 5] = [
+    "pending_confirmation",
+    "confirmed",
+    "unsubscribed",
+    "inactive",
+    "suppressed",
+];
+
+#[derive(serde::Deserialize)]
+pub struct SubscribersParameters {
+    /// Only show subscribers whose email or name contains this.
+    #[serde(default)]
+    q: String,
+    /// Only show subscribers in this status, if not empty.
+    #[serde(default)]
+    status: String,
+    #[serde(default = "first_page")]
+    page: i64,
+}
+
+fn first_page() -> i64 {
+    1
+}
+
+struct SubscriberRow {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+}
+
+pub async fn subscribers(
+    parameters: web::Query<SubscribersParameters>,
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let SubscribersParameters { q, status, page } = parameters.into_inner();
+    let pattern = contains_pattern(&q);
+    let status_filter = Some(status.as_str()).filter(|s| !s.is_empty());
+    let n_subscribers = count_subscribers(&pool, &pattern, status_filter)
+        .await
+        .map_err(e500)?;
+    let n_pages = ((n_subscribers + SUBSCRIBERS_PER_PAGE - 1) / SUBSCRIBERS_PER_PAGE).max(1);
+    let page = page.clamp(1, n_pages);
+    let subscribers = get_subscribers(&pool, &pattern, status_filter, page)
+        .await
+        .map_err(e500)?;
+
+    let msg_html: String = flash_messages
+        .iter()
+        .map(|m| format!("<p><i>{}</i></p>", m.content()))
+        .collect();
+    let mut rows_html = String::new();
+    for s in &subscribers {
+        writeln!(
+            rows_html,
+            "<tr><td><a href=\"/admin/subscribers/{}\">{}</a></td>\
+            <td>{}</td><td>{}</td><td>{}</td></tr>",
+            s.id,
+            encode_minimal(&s.email),
+            encode_minimal(&s.name),
+            s.status,
+            s.subscribed_at.to_rfc2822(),
+        )
+        .unwrap();
+    }
+    let page_link = |page: i64| {
+        format!(
+            "/admin/subscribers?q={}&status={}&page={}",
+            urlencoding::encode(&q),
+            urlencoding::encode(&status),
+            page
+        )
+    };
+    let mut pagination_html = format!("Page {} of {}", page, n_pages);
+    if page > 1 {
+        pagination_html = format!(
+            r#"<a href="{}">Previous</a> {pagination_html}"#,
+            page_link(page - 1)
+        );
+    }
+    if page < n_pages {
+        write!(
+            pagination_html,
+            r#" <a href="{}">Next</a>"#,
+            page_link(page + 1)
+        )
+        .unwrap();
+    }
+    let mut status_options_html = String::from(r#"<option value="">Any</option>"#);
+    for s in STATUSES {
+        let selected = if s == status { " selected" } else { "" };
+        write!(
+            status_options_html,
+            r#"<option value="{s}"{selected}>{s}</option>"#
+        )
+        .unwrap();
+    }
+    let q = encode_attribute(&q);
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Subscribers</title>
+    </head>
+    <body>
+        {msg_html}
+        <h1>Subscribers</h1>
+        <p><a href="/admin/subscribers/import">Import subscribers from a CSV file</a></p>
+        <p><a href="/admin/subscribers/erasures">Erasure receipts</a></p>
+        <p>Export every subscriber as
+            <a href="/admin/subscribers/export?format=csv">CSV</a> or
+            <a href="/admin/subscribers/export?format=json">JSON</a>
+        </p>
+        <p>{n_subscribers} subscribers found.</p>
+        <form action="/admin/subscribers" method="get">
+            <label>Email or name
+                <input
+                    type="search"
+                    placeholder="Search by email or name"
+                    name="q"
+                    value="{q}"
+                >
+            </label>
+            <label>Status
+                <select name="status">{status_options_html}</select>
+            </label>
+            <button type="submit">Search</button>
+        </form>
+        <table>
+            <tr>
+                <th>Email</th>
+                <th>Name</th>
+                <th>Status</th>
+                <th>Subscribed at</th>
+            </tr>
+            {rows_html}
+        </table>
+        <p>{pagination_html}</p>
+        <p><a href="/admin/dashboard">&lt;- Back</a></p>
+    </body>
+</html>"#,
+        )))
+}
+
+#[tracing::instrument(name = "count subscribers", skip(pool))]
+async fn count_subscribers(
+    pool: &PgPool,
+    pattern: &str,
+    status: Option<&str>,
+) -> Result<i64, anyhow::Error> {
+    let r = sqlx::query!(
+        r#"
+        SELECT count(*) AS "count!"
+        FROM subscriptions
+        WHERE
+            (email ILIKE $1 OR name ILIKE $1) AND
+            ($2::text IS NULL OR status = $2)
+        "#,
+        pattern,
+        status
+    )
+    .fetch_one(pool)
+    .await
+    .context("failed to count subscribers")?;
+
+    Ok(r.count)
+}
+
+#[tracing::instrument(name = "get subscribers", skip(pool))]
+async fn get_subscribers(
+    pool: &PgPool,
+    pattern: &str,
+    status: Option<&str>,
+    page: i64,
+) -> Result<Vec<SubscriberRow>, anyhow::Error> {
+    let subscribers = sqlx::query_as!(
+        SubscriberRow,
+        r#"
+        SELECT id, email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE
+            (email ILIKE $1 OR name ILIKE $1) AND
+            ($2::text IS NULL OR status = $2)
+        ORDER BY subscribed_at DESC, id
+        LIMIT $3 OFFSET $4
+        "#,
+        pattern,
+        status,
+        SUBSCRIBERS_PER_PAGE,
+        (page - 1) * SUBSCRIBERS_PER_PAGE
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to retrieve subscribers")?;
+
+    Ok(subscribers)
+}
@@ -0,0 +1,18 @@
+mod actions;
+mod detail;
+mod erasures;
+mod export;
+mod import;
+mod list;
+
+pub use actions::{
+    admin_confirm_subscriber, admin_erase_subscriber, admin_unsubscribe_subscriber,
+    resend_confirmation,
+};
+pub use detail::subscriber;
+pub use erasures::erasure_receipts;
+pub use export::{export_data_subject, export_subscribers};
+pub use import::{
+    import_subscribers, import_subscribers_form, subscriber_import, subscriber_import_rejections,
+};
+pub use list::subscribers;
@@ -0,0 +1,217 @@
+use std::fmt::Write;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use actix_web::http::header::{
+    EntityTag, Header, HttpDate, IfModifiedSince, IfNoneMatch, LastModified, CONTENT_TYPE, ETAG,
+    IF_NONE_MATCH,
+};
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, SecondsFormat, Utc};
+use htmlescape::encode_minimal;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::routes::issue_permalink;
+use crate::startup::ApplicationBaseUrl;
+use crate::utils::e500;
+
+const FEED_TITLE: &str = "Our newsletter";
+/// How many of the latest issues make it into the feeds.
+const FEED_SIZE: i64 = 20;
+
+struct FeedIssue {
+    newsletter_issue_id: Uuid,
+    slug: String,
+    title: String,
+    html_content: String,
+    published_at: DateTime<Utc>,
+}
+
+pub async fn rss_feed(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issues = get_feed_issues(&pool).await.map_err(e500)?;
+    let last_change = get_last_archive_change(&pool).await.map_err(e500)?;
+    let base_url = &base_url.0;
+
+    let mut items = String::new();
+    for i in &issues {
+        let permalink = encode_minimal(&issue_permalink(base_url, &i.slug));
+        writeln!(
+            items,
+            r#"<item>
+<title>{}</title>
+<link>{permalink}</link>
+<guid isPermaLink="true">{permalink}</guid>
+<pubDate>{}</pubDate>
+<description>{}</description>
+</item>"#,
+            encode_minimal(&i.title),
+            i.published_at.to_rfc2822(),
+            encode_minimal(&i.html_content),
+        )
+        .unwrap();
+    }
+    let body = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom">
+<channel>
+<title>{FEED_TITLE}</title>
+<link>{base_url}/issues</link>
+<description>The latest issues of {FEED_TITLE}</description>
+<atom:link href="{base_url}/feed.rss" rel="self" type="application/rss+xml"/>
+{items}</channel>
+</rss>
+"#
+    );
+
+    Ok(feed_response(
+        &request,
+        "application/rss+xml; charset=utf-8",
+        body,
+        last_change,
+    ))
+}
+
+pub async fn atom_feed(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issues = get_feed_issues(&pool).await.map_err(e500)?;
+    let last_change = get_last_archive_change(&pool).await.map_err(e500)?;
+    let base_url = &base_url.0;
+
+    let mut entries = String::new();
+    for i in &issues {
+        let published_at = i.published_at.to_rfc3339_opts(SecondsFormat::Secs, true);
+        writeln!(
+            entries,
+            r#"<entry>
+<title>{}</title>
+<id>urn:uuid:{}</id>
+<link href="{}"/>
+<published>{published_at}</published>
+<updated>{published_at}</updated>
+<content type="html">{}</content>
+</entry>"#,
+            encode_minimal(&i.title),
+            i.newsletter_issue_id,
+            encode_minimal(&issue_permalink(base_url, &i.slug)),
+            encode_minimal(&i.html_content),
+        )
+        .unwrap();
+    }
+    let updated = last_change.to_rfc3339_opts(SecondsFormat::Secs, true);
+    let body = format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+<title>{FEED_TITLE}</title>
+<id>{base_url}/issues</id>
+<link href="{base_url}/feed.atom" rel="self"/>
+<link href="{base_url}/issues"/>
+<updated>{updated}</updated>
+<author><name>{FEED_TITLE}</name></author>
+{entries}</feed>
+"#
+    );
+
+    Ok(feed_response(
+        &request,
+        "application/atom+xml; charset=utf-8",
+        body,
+        last_change,
+    ))
+}
+
+/// Attach `ETag` and `Last-Modified` to a feed, answering with a
+/// `304 Not Modified` if the client already has the latest version.
+fn feed_response(
+    request: &HttpRequest,
+    content_type: &str,
+    body: String,
+    last_change: DateTime<Utc>,
+) -> HttpResponse {
+    let etag = EntityTag::new_strong(hex::encode(&Sha256::digest(body.as_bytes())[..16]));
+    // HTTP dates have a resolution of one second
+    let last_modified = UNIX_EPOCH + Duration::from_secs(last_change.timestamp().max(0) as u64);
+
+    // `If-None-Match` takes precedence over `If-Modified-Since` (RFC 7232)
+    let not_modified = if request.headers().contains_key(IF_NONE_MATCH) {
+        match IfNoneMatch::parse(request) {
+            Ok(IfNoneMatch::Any) => true,
+            Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|t| t.weak_eq(&etag)),
+            Err(_) => false,
+        }
+    } else {
+        match IfModifiedSince::parse(request) {
+            Ok(IfModifiedSince(since)) => last_modified <= SystemTime::from(since),
+            Err(_) => false,
+        }
+    };
+
+    let mut response = if not_modified {
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+    response
+        .insert_header((ETAG, etag.to_string()))
+        .insert_header(LastModified(HttpDate::from(last_modified)));
+    if not_modified {
+        response.finish()
+    } else {
+        response
+            .insert_header((CONTENT_TYPE, content_type))
+            .body(body)
+    }
+}
+
+#[tracing::instrument(name = "get newsletter issues for the feeds", skip(pool))]
+async fn get_feed_issues(pool: &PgPool) -> Result<Vec<FeedIssue>, anyhow::Error> {
+    let issues = sqlx::query_as!(
+        FeedIssue,
+        r#"
+        SELECT
+            newsletter_issue_id,
+            slug AS "slug!",
+            title,
+            html_content,
+            published_at AS "published_at!"
+        FROM newsletter_issues
+        WHERE status = 'published' AND in_public_archive AND slug IS NOT NULL
+        ORDER BY published_at DESC
+        LIMIT $1
+        "#,
+        FEED_SIZE
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to retrieve newsletter issues for the feeds")?;
+
+    Ok(issues)
+}
+
+/// When the feeds last changed: published issues can't be edited, so it is
+/// either when the latest one was published or when an issue last entered
+/// or left the archive, hidden issues included.
+#[tracing::instrument(name = "get the last change to the public archive", skip(pool))]
+async fn get_last_archive_change(pool: &PgPool) -> Result<DateTime<Utc>, anyhow::Error> {
+    let r = sqlx::query!(
+        r#"
+        SELECT MAX(GREATEST(published_at, archive_updated_at)) AS last_change
+        FROM newsletter_issues
+        WHERE status = 'published'
+        "#
+    )
+    .fetch_one(pool)
+    .await
+    .context("failed to retrieve the last change to the public archive")?;
+
+    Ok(r.last_change
+        .unwrap_or_else(|| DateTime::<Utc>::from(UNIX_EPOCH)))
+}
@@ -0,0 +1,128 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::utils::e500;
+
+struct ArchivedIssue {
+    slug: String,
+    title: String,
+    published_at: DateTime<Utc>,
+}
+
+struct PublicIssue {
+    title: String,
+    html_content: String,
+    published_at: DateTime<Utc>,
+}
+
+/// Build the public link to a published issue, a.k.a. "view in browser".
+pub fn issue_permalink(base_url: &str, slug: &str) -> String {
+    format!("{}/issues/{}", base_url, slug)
+}
+
+pub async fn public_issues(pool: web::Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+    let issues = get_archived_issues(&pool).await.map_err(e500)?;
+
+    let mut issues_html = String::new();
+    for i in &issues {
+        writeln!(
+            issues_html,
+            r#"<li><a href="/issues/{}">{}</a> - {}</li>"#,
+            i.slug,
+            encode_minimal(&i.title),
+            i.published_at.format("%B %e, %Y"),
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Past issues</title>
+    </head>
+    <body>
+        <h1>Past issues</h1>
+        <ul>
+            {issues_html}
+        </ul>
+        <p><a href="/">Subscribe</a> to get the next ones in your inbox.</p>
+    </body>
+</html>"#,
+        )))
+}
+
+pub async fn public_issue(
+    slug: web::Path<String>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue = match get_public_issue(&pool, &slug).await.map_err(e500)? {
+        Some(issue) => issue,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    let title = encode_minimal(&issue.title);
+    let published_at = issue.published_at.format("%B %e, %Y");
+    let html_content = issue.html_content;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>{title}</title>
+    </head>
+    <body>
+        <h1>{title}</h1>
+        <p><i>{published_at}</i></p>
+        {html_content}
+        <p><a href="/issues">&lt;- All issues</a></p>
+    </body>
+</html>"#,
+        )))
+}
+
+#[tracing::instrument(name = "get archived newsletter issues", skip(pool))]
+async fn get_archived_issues(pool: &PgPool) -> Result<Vec<ArchivedIssue>, anyhow::Error> {
+    let issues = sqlx::query_as!(
+        ArchivedIssue,
+        r#"
+        SELECT slug AS "slug!", title, published_at AS "published_at!"
+        FROM newsletter_issues
+        WHERE status = 'published' AND in_public_archive AND slug IS NOT NULL
+        ORDER BY published_at DESC
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to retrieve archived newsletter issues")?;
+
+    Ok(issues)
+}
+
+#[tracing::instrument(name = "get public newsletter issue", skip(pool))]
+async fn get_public_issue(pool: &PgPool, slug: &str) -> Result<Option<PublicIssue>, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        PublicIssue,
+        r#"
+        SELECT title, html_content, published_at AS "published_at!"
+        FROM newsletter_issues
+        WHERE slug = $1 AND status = 'published' AND in_public_archive
+        "#,
+        slug
+    )
+    .fetch_optional(pool)
+    .await
+    .context("failed to retrieve newsletter issue")?;
+
+    Ok(issue)
+}
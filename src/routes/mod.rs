@@ -1,16 +1,29 @@
 mod admin;
+mod feeds;
 mod health_check;
 mod home;
+mod issues;
 mod login;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_unsubscribe;
+mod tracking;
+mod webhooks;
 
+// Both the admin and the subscription forms have a `FormData`, which
+// nobody imports from here
+#[allow(ambiguous_glob_reexports)]
 pub use admin::*;
+pub use feeds::*;
 pub use health_check::*;
 pub use home::*;
+pub use issues::*;
 pub use login::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
+pub use subscriptions_unsubscribe::*;
+pub use tracking::*;
+pub use webhooks::*;
 
 pub fn error_chain_fmt(
     e: &impl std::error::Error,
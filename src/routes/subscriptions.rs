@@ -1,21 +1,30 @@
-use actix_web::{web, HttpResponse, ResponseError};
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
 use anyhow::Context;
-use chrono::Utc;
+use chrono::{DateTime, Duration, Utc};
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use sha2::{Digest, Sha256};
 use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
+use crate::consent::{record_consent_event, ConsentEvent, ConsentKind};
 use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
 use crate::email_client::EmailClient;
 use crate::routes::error_chain_fmt;
-use crate::startup::ApplicationBaseUrl;
+use crate::startup::{ApplicationBaseUrl, HmacSecret, PrivacyPolicyVersion};
+use crate::suppression_list::is_suppressed;
 
 #[derive(serde::Deserialize)]
 pub struct FormData {
     email: String,
     name: String,
+    /// Which form was filled in, when we have several.
+    #[serde(default)]
+    source: Option<String>,
 }
 
+/// The source recorded for forms that don't say which one they are.
+const DEFAULT_SUBSCRIPTION_SOURCE: &str = "subscription_form";
+
 impl TryFrom<FormData> for NewSubscriber {
     type Error = String;
 
@@ -51,27 +60,92 @@ impl std::fmt::Debug for SubscribeError {
 
 #[tracing::instrument(
     name = "adding a new subscriber",
-    skip(form, pool, email_client, base_url),
+    skip(form, request, pool, email_client, base_url, privacy_policy_version, hmac_secret),
     fields(
         subscriber_email = %form.email,
         subscriber_name = %form.name
     )
 )]
 pub async fn subscribe(
-    form: web::Form<FormData>,
+    mut form: web::Form<FormData>,
+    request: HttpRequest,
     pool: web::Data<PgPool>,
     email_client: web::Data<EmailClient>,
     base_url: web::Data<ApplicationBaseUrl>,
+    privacy_policy_version: web::Data<PrivacyPolicyVersion>,
+    hmac_secret: web::Data<HmacSecret>,
 ) -> Result<HttpResponse, SubscribeError> {
-    let new_subscriber = form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    let source = form
+        .source
+        .take()
+        .filter(|s| !s.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_SUBSCRIPTION_SOURCE.into());
+    let new_subscriber: NewSubscriber =
+        form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    // Addresses that bounced or complained are not emailed again. We don't
+    // tell the caller though: whether an address is on the suppression
+    // list is none of their business.
+    if is_suppressed(&pool, new_subscriber.email.as_ref(), &hmac_secret.0)
+        .await
+        .context("failed to check the suppression list")?
+    {
+        tracing::info!("ignoring a subscription request for a suppressed email address");
+        return Ok(HttpResponse::Ok().finish());
+    }
 
     let mut transaction = pool
         .begin()
         .await
         .context("failed to acquire a Postgres connection from the pool")?;
-    let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
+    let (subscriber_id, status) = match get_subscriber_by_email(&mut transaction, &new_subscriber)
+        .await
+        .context("failed to look for an existing subscriber")?
+    {
+        Some(subscriber) => subscriber,
+        None => {
+            insert_subscriber(&mut transaction, &new_subscriber)
+                .await
+                .context("failed to insert a new subscriber in the database")?;
+            // Someone else may have subscribed the same address meanwhile
+            get_subscriber_by_email(&mut transaction, &new_subscriber)
+                .await
+                .context("failed to look for an existing subscriber")?
+                .context("the new subscriber could not be found")?
+        }
+    };
+    // Signing up again changes nothing for those who already confirmed,
+    // and they get the same answer as everyone else: whether an address
+    // is subscribed is none of the caller's business either.
+    if status == "confirmed" || status == "suppressed" {
+        tracing::info!("ignoring a subscription request for an existing subscriber");
+        return Ok(HttpResponse::Ok().finish());
+    }
+    // Pending subscribers who were just sent a link get it in a moment
+    if status == "pending_confirmation"
+        && last_token_created_at(&mut transaction, subscriber_id)
+            .await
+            .context("failed to look for the last confirmation token")?
+            .is_some_and(|created_at| {
+                Utc::now() - created_at < Duration::minutes(CONFIRMATION_RESEND_INTERVAL_MINUTES)
+            })
+    {
+        tracing::info!("ignoring a subscription request sent right after a previous one");
+        return Ok(HttpResponse::Ok().finish());
+    }
+    // Everyone else, pending, inactive or unsubscribed, gets a new
+    // confirmation link: they are only subscribed again once they follow it.
+    delete_tokens(&mut transaction, subscriber_id)
         .await
-        .context("failed to insert a new subscriber in the database")?;
+        .context("failed to delete the previous confirmation tokens")?;
+    let consent = ConsentEvent::from_request(
+        ConsentKind::Subscribed,
+        source.trim(),
+        &request,
+        &privacy_policy_version.0,
+    );
+    record_consent_event(&mut transaction, subscriber_id, &consent)
+        .await
+        .context("failed to record the consent of a new subscriber")?;
     let subscription_token = generate_subscription_token();
     store_token(&mut transaction, subscriber_id, &subscription_token)
         .await
@@ -82,7 +156,7 @@ pub async fn subscribe(
         .context("failed to commit SQL transaction to store a new subscriber")?;
     send_confirmation_email(
         &email_client,
-        new_subscriber,
+        &new_subscriber.email,
         &base_url.0,
         &subscription_token,
     )
@@ -94,14 +168,30 @@ pub async fn subscribe(
 
 #[tracing::instrument(
     name = "send a confirmation email to a new subscriber",
-    skip(email_client, new_subscriber, base_url, subscription_token)
+    skip(email_client, recipient, base_url, subscription_token)
 )]
 pub async fn send_confirmation_email(
     email_client: &EmailClient,
-    new_subscriber: NewSubscriber,
+    recipient: &SubscriberEmail,
     base_url: &str,
     subscription_token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<(), anyhow::Error> {
+    let (html_body, plain_body) = confirmation_email_bodies(base_url, subscription_token);
+    email_client
+        .send_email(
+            recipient,
+            CONFIRMATION_EMAIL_SUBJECT,
+            &html_body,
+            &plain_body,
+        )
+        .await
+}
+
+pub const CONFIRMATION_EMAIL_SUBJECT: &str = "Welcome!";
+
+/// The HTML and plain text bodies of the email asking a subscriber to
+/// confirm their subscription with `subscription_token`.
+pub fn confirmation_email_bodies(base_url: &str, subscription_token: &str) -> (String, String) {
     let confirmation_link = format!(
         "{}/subscriptions/confirm?subscription_token={}",
         base_url, subscription_token
@@ -114,25 +204,23 @@ pub async fn send_confirmation_email(
         "Welcome to our newsletter!\nVisit {} to confirm your subscription.",
         confirmation_link
     );
-    email_client
-        .send_email(&new_subscriber.email, "Welcome!", &html_body, &plain_body)
-        .await
+    (html_body, plain_body)
 }
 
+/// Does nothing if the address is already taken, see `get_subscriber_by_email`.
 #[tracing::instrument(
     name = "saving new subscriber details in the database",
     skip(transaction, new_subscriber)
 )]
-pub async fn insert_subscriber(
+async fn insert_subscriber(
     transaction: &mut Transaction<'_, Postgres>,
     new_subscriber: &NewSubscriber,
-) -> Result<Uuid, sqlx::Error> {
-    let subscriber_id = Uuid::new_v4();
-
+) -> Result<(), sqlx::Error> {
     sqlx::query!(
         r#"INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-        VALUES ($1, $2, $3, $4, 'pending_confirmation')"#,
-        subscriber_id,
+        VALUES ($1, $2, $3, $4, 'pending_confirmation')
+        ON CONFLICT (email) DO NOTHING"#,
+        Uuid::new_v4(),
         new_subscriber.email.as_ref(),
         new_subscriber.name.as_ref(),
         Utc::now()
@@ -140,10 +228,73 @@ pub async fn insert_subscriber(
     .execute(transaction)
     .await?;
 
-    Ok(subscriber_id)
+    Ok(())
+}
+
+/// The id and status of the subscriber with this email address, if any.
+#[tracing::instrument(
+    name = "look for an existing subscriber",
+    skip(transaction, new_subscriber)
+)]
+async fn get_subscriber_by_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    new_subscriber: &NewSubscriber,
+) -> Result<Option<(Uuid, String)>, sqlx::Error> {
+    // The exact address first, should there be several spellings of it
+    let r = sqlx::query!(
+        r#"SELECT id, status FROM subscriptions
+        WHERE lower(email) = lower($1)
+        ORDER BY email = $1 DESC
+        LIMIT 1
+        FOR UPDATE"#,
+        new_subscriber.email.as_ref(),
+    )
+    .fetch_optional(transaction)
+    .await?;
+
+    Ok(r.map(|r| (r.id, r.status)))
+}
+
+/// When the latest confirmation link of a subscriber was sent, if any.
+#[tracing::instrument(name = "get the last subscription token", skip(transaction))]
+async fn last_token_created_at(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    let r = sqlx::query!(
+        r#"SELECT max(created_at) AS created_at FROM subscription_tokens
+        WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .fetch_one(transaction)
+    .await?;
+
+    Ok(r.created_at)
+}
+
+#[tracing::instrument(name = "delete subscription tokens", skip(transaction))]
+pub async fn delete_tokens(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"DELETE FROM subscription_tokens WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(transaction)
+    .await?;
+
+    Ok(())
 }
 
-fn generate_subscription_token() -> String {
+/// How long a confirmation link can be followed for.
+pub const SUBSCRIPTION_TOKEN_LIFETIME_HOURS: i64 = 7 * 24;
+
+/// How long pending subscribers wait before signing up again sends them
+/// another confirmation link, so that the form can't be used to flood them.
+pub const CONFIRMATION_RESEND_INTERVAL_MINUTES: i64 = 10;
+
+pub fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
         .map(char::from)
@@ -151,6 +302,12 @@ fn generate_subscription_token() -> String {
         .collect()
 }
 
+/// What we store instead of the token itself. Tokens are random enough
+/// for a plain SHA-256 to do: there is nothing to brute force.
+pub fn hash_subscription_token(subscription_token: &str) -> String {
+    hex::encode(Sha256::digest(subscription_token.as_bytes()))
+}
+
 pub struct StoreTokenError(sqlx::Error);
 
 impl std::fmt::Display for StoreTokenError {
@@ -177,16 +334,19 @@ impl std::error::Error for StoreTokenError {
     name = "store subscription token in the database"
     skip(transaction, subscription_token)
 )]
-async fn store_token(
+pub async fn store_token(
     transaction: &mut Transaction<'_, Postgres>,
     subscriber_id: Uuid,
     subscription_token: &str,
 ) -> Result<(), StoreTokenError> {
+    let now = Utc::now();
     sqlx::query!(
-        r#"INSERT INTO subscription_tokens (subscription_token, subscriber_id)
-        VALUES ($1, $2)"#,
-        subscription_token,
-        subscriber_id
+        r#"INSERT INTO subscription_tokens (token_hash, subscriber_id, created_at, expires_at)
+        VALUES ($1, $2, $3, $4)"#,
+        hash_subscription_token(subscription_token),
+        subscriber_id,
+        now,
+        now + Duration::hours(SUBSCRIPTION_TOKEN_LIFETIME_HOURS)
     )
     .execute(transaction)
     .await
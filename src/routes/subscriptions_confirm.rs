@@ -1,9 +1,18 @@
-use actix_web::{web, HttpResponse, ResponseError};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
 use anyhow::Context;
-use sqlx::PgPool;
+use htmlescape::encode_attribute;
+use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
-use crate::routes::error_chain_fmt;
+use crate::consent::{record_consent_event, ConsentEvent, ConsentKind};
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::routes::{
+    delete_tokens, error_chain_fmt, generate_subscription_token, hash_subscription_token,
+    send_confirmation_email, store_token,
+};
+use crate::startup::{ApplicationBaseUrl, PrivacyPolicyVersion};
 
 #[derive(serde::Deserialize)]
 pub struct Parameters {
@@ -31,50 +40,236 @@ impl ResponseError for ConfirmError {
             ConfirmError::UnknownToken => reqwest::StatusCode::UNAUTHORIZED,
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            ConfirmError::UnexpectedError(_) => HttpResponse::new(self.status_code()),
+            ConfirmError::UnknownToken => HttpResponse::build(self.status_code())
+                .content_type(ContentType::html())
+                .body(
+                    r#"<!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Invalid link</title>
+    </head>
+    <body>
+        <p>This confirmation link is not valid. Links can only be used once:
+        if you already followed it, your subscription is confirmed.</p>
+    </body>
+</html>"#,
+                ),
+        }
+    }
 }
 
-#[tracing::instrument(name = "confirm a pending subscriber", skip(parameters, pool))]
+#[tracing::instrument(
+    name = "confirm a pending subscriber",
+    skip(parameters, request, pool, privacy_policy_version)
+)]
 pub async fn confirm(
     parameters: web::Query<Parameters>,
+    request: HttpRequest,
     pool: web::Data<PgPool>,
+    privacy_policy_version: web::Data<PrivacyPolicyVersion>,
 ) -> Result<HttpResponse, ConfirmError> {
-    let id = get_subscriber_id_from_token(&pool, &parameters.subscription_token)
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("failed to acquire a Postgres connection from the pool")?;
+    let id = get_subscriber_id_from_token(&mut transaction, &parameters.subscription_token)
         .await
         .context("failed to get subscriber id from token")?;
-
-    match id {
-        None => Err(ConfirmError::UnknownToken),
-        Some(subscriber_id) => {
-            confirm_subscriber(&pool, subscriber_id)
-                .await
-                .context("failed to confirm subscriber")?;
-            Ok(HttpResponse::Ok().finish())
+    let subscriber_id = match id {
+        Some(subscriber_id) => subscriber_id,
+        None => {
+            let expired = get_subscriber_id_from_expired_token(
+                &mut transaction,
+                &parameters.subscription_token,
+            )
+            .await
+            .context("failed to look for an expired token")?;
+            return match expired {
+                Some(_) => Ok(link_expired_page(&parameters.subscription_token)),
+                None => Err(ConfirmError::UnknownToken),
+            };
         }
+    };
+
+    confirm_subscriber(&mut transaction, subscriber_id)
+        .await
+        .context("failed to confirm subscriber")?;
+    let consent = ConsentEvent::from_request(
+        ConsentKind::Confirmed,
+        "confirmation_link",
+        &request,
+        &privacy_policy_version.0,
+    );
+    record_consent_event(&mut transaction, subscriber_id, &consent)
+        .await
+        .context("failed to record the confirmation of a subscriber")?;
+    transaction
+        .commit()
+        .await
+        .context("failed to commit SQL transaction to confirm a subscriber")?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+fn link_expired_page(subscription_token: &str) -> HttpResponse {
+    let subscription_token = encode_attribute(subscription_token);
+    HttpResponse::Gone()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Link expired</title>
+    </head>
+    <body>
+        <p>This confirmation link has expired.</p>
+        <form action="/subscriptions/confirm/resend" method="post">
+            <input type="hidden" name="subscription_token" value="{subscription_token}">
+            <button type="submit">Send me a new link</button>
+        </form>
+    </body>
+</html>"#,
+        ))
+}
+
+/// The form of the page shown for expired links. The expired token stands
+/// for the address to send the new link to, and is replaced by it: each
+/// expired link can only ask for one new link.
+#[tracing::instrument(
+    name = "resend an expired confirmation link",
+    skip(form, pool, email_client, base_url)
+)]
+pub async fn resend_confirmation_link(
+    form: web::Form<Parameters>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, ConfirmError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("failed to acquire a Postgres connection from the pool")?;
+    let subscriber_id =
+        get_subscriber_id_from_expired_token(&mut transaction, &form.subscription_token)
+            .await
+            .context("failed to look for an expired token")?
+            .ok_or(ConfirmError::UnknownToken)?;
+    let subscriber = sqlx::query!(
+        r#"SELECT email, status FROM subscriptions WHERE id = $1 FOR UPDATE"#,
+        subscriber_id
+    )
+    .fetch_one(&mut transaction)
+    .await
+    .context("failed to retrieve the subscriber of an expired token")?;
+    delete_tokens(&mut transaction, subscriber_id)
+        .await
+        .context("failed to delete the expired confirmation tokens")?;
+    // Confirmed subscribers have nothing left to confirm, and suppressed
+    // addresses are not emailed: the page doesn't tell, like `subscribe`.
+    let new_token = if subscriber.status != "confirmed" && subscriber.status != "suppressed" {
+        let subscription_token = generate_subscription_token();
+        store_token(&mut transaction, subscriber_id, &subscription_token)
+            .await
+            .context("failed to store a new confirmation token")?;
+        Some(subscription_token)
+    } else {
+        None
+    };
+    transaction
+        .commit()
+        .await
+        .context("failed to commit SQL transaction to store a new confirmation token")?;
+    if let Some(subscription_token) = new_token {
+        let recipient = SubscriberEmail::parse(subscriber.email)
+            .map_err(|e| anyhow::anyhow!(e))
+            .context("the subscriber's email address is not valid")?;
+        send_confirmation_email(&email_client, &recipient, &base_url.0, &subscription_token)
+            .await
+            .context("failed to send a confirmation email")?;
     }
+
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(
+        r#"<!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>New link sent</title>
+    </head>
+    <body>
+        <p>If your subscription still needs confirming, a new link is on its
+        way to your inbox.</p>
+    </body>
+</html>"#,
+    ))
 }
 
-#[tracing::instrument(name = "mark subscriber as confirmed", skip(subscriber_id, pool))]
-pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
+/// Also deletes every confirmation token of the subscriber: links are
+/// single-use, and there is nothing left to confirm anyway.
+#[tracing::instrument(
+    name = "mark subscriber as confirmed",
+    skip(subscriber_id, transaction)
+)]
+pub async fn confirm_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
     sqlx::query!(
-        r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
+        r#"UPDATE subscriptions
+        SET status = 'confirmed', consecutive_soft_bounces = 0
+        WHERE id = $1"#,
         subscriber_id
     )
-    .execute(pool)
+    .execute(&mut *transaction)
     .await?;
+    delete_tokens(transaction, subscriber_id).await?;
 
     Ok(())
 }
 
-#[tracing::instrument(name = "get subscriber id from token", skip(pool, subscription_token))]
+/// The subscriber a confirmation token was sent to, as long as it has not
+/// expired. The token is locked until the end of the transaction, for it
+/// to be used only once.
+#[tracing::instrument(
+    name = "get subscriber id from token",
+    skip(transaction, subscription_token)
+)]
 pub async fn get_subscriber_id_from_token(
-    pool: &PgPool,
+    transaction: &mut Transaction<'_, Postgres>,
+    subscription_token: &str,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"SELECT subscriber_id FROM subscription_tokens
+        WHERE token_hash = $1 AND expires_at > now()
+        FOR UPDATE"#,
+        hash_subscription_token(subscription_token),
+    )
+    .fetch_optional(transaction)
+    .await?;
+
+    Ok(result.map(|r| r.subscriber_id))
+}
+
+#[tracing::instrument(
+    name = "get subscriber id from expired token",
+    skip(transaction, subscription_token)
+)]
+async fn get_subscriber_id_from_expired_token(
+    transaction: &mut Transaction<'_, Postgres>,
     subscription_token: &str,
 ) -> Result<Option<Uuid>, sqlx::Error> {
     let result = sqlx::query!(
-        r#"SELECT subscriber_id FROM subscription_tokens WHERE subscription_token = $1"#,
-        subscription_token,
+        r#"SELECT subscriber_id FROM subscription_tokens
+        WHERE token_hash = $1 AND expires_at <= now()
+        FOR UPDATE"#,
+        hash_subscription_token(subscription_token),
     )
-    .fetch_optional(pool)
+    .fetch_optional(transaction)
     .await?;
 
     Ok(result.map(|r| r.subscriber_id))
@@ -0,0 +1,174 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use secrecy::Secret;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::domain::UnsubscribeToken;
+use crate::erasure::{erase_subscriber, ErasureRequester};
+use crate::routes::error_chain_fmt;
+use crate::startup::HmacSecret;
+
+#[derive(serde::Deserialize)]
+pub struct UnsubscribeParameters {
+    subscriber_id: Uuid,
+    token: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum UnsubscribeError {
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+    #[error("the unsubscribe link is not valid")]
+    InvalidLink(#[source] anyhow::Error),
+}
+
+impl std::fmt::Debug for UnsubscribeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for UnsubscribeError {
+    fn status_code(&self) -> reqwest::StatusCode {
+        match self {
+            UnsubscribeError::UnexpectedError(_) => reqwest::StatusCode::INTERNAL_SERVER_ERROR,
+            UnsubscribeError::InvalidLink(_) => reqwest::StatusCode::UNAUTHORIZED,
+        }
+    }
+}
+
+/// Build the link a subscriber can follow to stop receiving our emails.
+pub fn unsubscribe_link(base_url: &str, subscriber_id: Uuid, secret: &Secret<String>) -> String {
+    let token = UnsubscribeToken::generate(subscriber_id, secret);
+    format!(
+        "{}/subscriptions/unsubscribe?subscriber_id={}&token={}",
+        base_url,
+        subscriber_id,
+        token.as_ref()
+    )
+}
+
+/// Ask for confirmation instead of unsubscribing straight away: link scanners
+/// and prefetchers follow every `GET` they find in an email.
+#[tracing::instrument(name = "show the unsubscribe form", skip(parameters, hmac_secret))]
+pub async fn unsubscribe_form(
+    parameters: web::Query<UnsubscribeParameters>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    UnsubscribeToken::verify(&parameters.token, parameters.subscriber_id, &hmac_secret.0)
+        .map_err(UnsubscribeError::InvalidLink)?;
+
+    let subscriber_id = parameters.subscriber_id;
+    let token = &parameters.token;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Unsubscribe</title>
+    </head>
+    <body>
+        <form action="/subscriptions/unsubscribe?subscriber_id={subscriber_id}&token={token}" method="post">
+            <p>Do you want to stop receiving our newsletter?</p>
+            <button type="submit">Unsubscribe</button>
+        </form>
+        <form action="/subscriptions/erase?subscriber_id={subscriber_id}&token={token}" method="post">
+            <p>You can also ask us to erase everything we hold about you.
+            This can't be undone.</p>
+            <button type="submit">Unsubscribe and erase my data</button>
+        </form>
+    </body>
+</html>"#,
+        )))
+}
+
+/// Handles both the form shown by `unsubscribe_form` and the one-click
+/// `List-Unsubscribe=One-Click` requests sent by mail clients (RFC 8058):
+/// everything we need is in the signed query string, so the body is ignored.
+#[tracing::instrument(name = "unsubscribe a subscriber", skip(parameters, pool, hmac_secret))]
+pub async fn unsubscribe(
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    UnsubscribeToken::verify(&parameters.token, parameters.subscriber_id, &hmac_secret.0)
+        .map_err(UnsubscribeError::InvalidLink)?;
+
+    mark_subscriber_as_unsubscribed(&pool, parameters.subscriber_id)
+        .await
+        .context("failed to unsubscribe subscriber")?;
+
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(
+        r#"<!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Unsubscribed</title>
+    </head>
+    <body>
+        <p>You have been unsubscribed. You will not receive any more emails from us.</p>
+    </body>
+</html>"#,
+    ))
+}
+
+/// The self-service side of `erasure::erase_subscriber`, from the form of
+/// `unsubscribe_form`. Erasing twice is not an error: the first request may
+/// have gone through without its answer making it back.
+#[tracing::instrument(
+    name = "erase a subscriber on their request",
+    skip(parameters, pool, hmac_secret)
+)]
+pub async fn erase_subscriber_data(
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    UnsubscribeToken::verify(&parameters.token, parameters.subscriber_id, &hmac_secret.0)
+        .map_err(UnsubscribeError::InvalidLink)?;
+
+    erase_subscriber(
+        &pool,
+        parameters.subscriber_id,
+        ErasureRequester::Subscriber,
+        &hmac_secret.0,
+    )
+    .await
+    .context("failed to erase subscriber")?;
+
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(
+        r#"<!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Erased</title>
+    </head>
+    <body>
+        <p>Everything we held about you has been erased.
+        You will not receive any more emails from us.</p>
+    </body>
+</html>"#,
+    ))
+}
+
+#[tracing::instrument(name = "mark subscriber as unsubscribed", skip(pool))]
+pub async fn mark_subscriber_as_unsubscribed(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = 'unsubscribed'
+        WHERE id = $1 AND status <> 'suppressed'
+        "#,
+        subscriber_id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
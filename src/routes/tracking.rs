@@ -0,0 +1,278 @@
+use actix_web::http::header::{CacheControl, CacheDirective, LOCATION};
+use actix_web::{web, HttpResponse, ResponseError};
+use chrono::Utc;
+use secrecy::Secret;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::domain::TrackingToken;
+use crate::routes::error_chain_fmt;
+use crate::startup::HmacSecret;
+
+/// A transparent 1x1 GIF.
+const TRACKING_PIXEL: &[u8] = &[
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+#[derive(serde::Deserialize)]
+pub struct OpenParameters {
+    issue_id: Uuid,
+    subscriber_id: Uuid,
+    token: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ClickParameters {
+    issue_id: Uuid,
+    subscriber_id: Uuid,
+    url: String,
+    token: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum TrackingError {
+    #[error("the tracking link is not valid")]
+    InvalidLink(#[source] anyhow::Error),
+}
+
+impl std::fmt::Debug for TrackingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for TrackingError {
+    fn status_code(&self) -> reqwest::StatusCode {
+        match self {
+            TrackingError::InvalidLink(_) => reqwest::StatusCode::UNAUTHORIZED,
+        }
+    }
+}
+
+/// The URL of the image that tells us `subscriber_id` opened an issue.
+pub fn open_tracking_pixel_url(
+    base_url: &str,
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+    secret: &Secret<String>,
+) -> String {
+    let token = TrackingToken::generate(newsletter_issue_id, subscriber_id, None, secret);
+    format!(
+        "{}/tracking/open?issue_id={}&subscriber_id={}&token={}",
+        base_url,
+        newsletter_issue_id,
+        subscriber_id,
+        token.as_ref()
+    )
+}
+
+/// A link that records the click of `subscriber_id` before sending them on
+/// to `url`.
+pub fn click_tracking_link(
+    base_url: &str,
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+    url: &str,
+    secret: &Secret<String>,
+) -> String {
+    let token = TrackingToken::generate(newsletter_issue_id, subscriber_id, Some(url), secret);
+    format!(
+        "{}/tracking/click?issue_id={}&subscriber_id={}&url={}&token={}",
+        base_url,
+        newsletter_issue_id,
+        subscriber_id,
+        urlencoding::encode(url),
+        token.as_ref()
+    )
+}
+
+/// Replace the target of every `http(s)` link in `html` with what
+/// `tracking_link` returns for it. Other links (`mailto:`, anchors, ...)
+/// are left alone.
+pub fn track_links(html: &str, mut tracking_link: impl FnMut(&str) -> String) -> String {
+    // ASCII lowercasing keeps byte offsets unchanged
+    let lowercase = html.to_ascii_lowercase();
+    let mut output = String::with_capacity(html.len());
+    let mut copied_up_to = 0;
+    let mut search_from = 0;
+    while let Some(i) = lowercase[search_from..].find("href=") {
+        let attribute_start = search_from + i;
+        let value_start = attribute_start + "href=".len();
+        search_from = value_start;
+        if !lowercase[..attribute_start].ends_with(|c: char| c.is_ascii_whitespace()) {
+            // e.g. `data-href=`
+            continue;
+        }
+        let quote = match lowercase[value_start..].chars().next() {
+            Some(quote @ ('"' | '\'')) => quote,
+            _ => continue,
+        };
+        let url_start = value_start + 1;
+        let url_end = match lowercase[url_start..].find(quote) {
+            Some(j) => url_start + j,
+            None => break,
+        };
+        search_from = url_end;
+        let raw_url = &html[url_start..url_end];
+        let url = htmlescape::decode_html(raw_url).unwrap_or_else(|_| raw_url.to_owned());
+        let lowercase_url = url.to_ascii_lowercase();
+        if lowercase_url.starts_with("http://") || lowercase_url.starts_with("https://") {
+            output.push_str(&html[copied_up_to..url_start]);
+            output.push_str(&tracking_link(&url).replace('&', "&amp;"));
+            copied_up_to = url_end;
+        }
+    }
+    output.push_str(&html[copied_up_to..]);
+    output
+}
+
+/// Loaded by mail clients that display images. Tracking is best effort:
+/// the pixel is served even if the open could not be recorded.
+#[tracing::instrument(name = "track an open", skip(parameters, pool, hmac_secret))]
+pub async fn track_open(
+    parameters: web::Query<OpenParameters>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, TrackingError> {
+    TrackingToken::verify(
+        &parameters.token,
+        parameters.issue_id,
+        parameters.subscriber_id,
+        None,
+        &hmac_secret.0,
+    )
+    .map_err(TrackingError::InvalidLink)?;
+
+    if let Err(e) =
+        record_engagement(&pool, parameters.issue_id, parameters.subscriber_id, None).await
+    {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "failed to record an open"
+        );
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("image/gif")
+        // Every load counts: do not let proxies answer in our place
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .body(TRACKING_PIXEL))
+}
+
+/// Record a click and redirect to the destination of the link. The
+/// subscriber gets where they wanted to go even if the click could not be
+/// recorded.
+#[tracing::instrument(name = "track a click", skip(parameters, pool, hmac_secret))]
+pub async fn track_click(
+    parameters: web::Query<ClickParameters>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, TrackingError> {
+    TrackingToken::verify(
+        &parameters.token,
+        parameters.issue_id,
+        parameters.subscriber_id,
+        Some(&parameters.url),
+        &hmac_secret.0,
+    )
+    .map_err(TrackingError::InvalidLink)?;
+
+    if let Err(e) = record_engagement(
+        &pool,
+        parameters.issue_id,
+        parameters.subscriber_id,
+        Some(&parameters.url),
+    )
+    .await
+    {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "failed to record a click"
+        );
+    }
+
+    Ok(HttpResponse::Found()
+        .insert_header((LOCATION, parameters.url.as_str()))
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .finish())
+}
+
+/// A click if `url` is set, an open otherwise.
+#[tracing::instrument(name = "record an engagement event", skip(pool))]
+async fn record_engagement(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+    url: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    let kind = if url.is_some() { "click" } else { "open" };
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_engagement_events (
+            id,
+            newsletter_issue_id,
+            subscriber_id,
+            kind,
+            url,
+            occurred_at
+        )
+        -- Nothing to record for subscribers who have been erased since
+        SELECT $1, $2, id, $4, $5, $6
+        FROM subscriptions
+        WHERE id = $3
+        "#,
+        Uuid::new_v4(),
+        newsletter_issue_id,
+        subscriber_id,
+        kind,
+        url,
+        Utc::now()
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::track_links;
+
+    fn tracked(html: &str) -> String {
+        track_links(html, |url| format!("https://t.example/?u={url}&x=1"))
+    }
+
+    #[test]
+    fn http_links_are_rewritten() {
+        assert_eq!(
+            tracked(
+                r#"<a href="https://example.com/a">a</a> <a HREF='http://example.com/b'>b</a>"#
+            ),
+            r#"<a href="https://t.example/?u=https://example.com/a&amp;x=1">a</a> <a HREF='https://t.example/?u=http://example.com/b&amp;x=1'>b</a>"#
+        );
+    }
+
+    #[test]
+    fn entities_in_links_are_decoded() {
+        assert_eq!(
+            tracked(r#"<a href="https://example.com/?a=1&amp;b=2">a</a>"#),
+            r#"<a href="https://t.example/?u=https://example.com/?a=1&amp;b=2&amp;x=1">a</a>"#
+        );
+    }
+
+    #[test]
+    fn other_links_are_left_alone() {
+        let html = r##"<a href="mailto:ursula@example.com">a</a><a href="#top">b</a><div data-href="https://example.com">c</div><a href=https://example.com>d</a>"##;
+        assert_eq!(tracked(html), html);
+    }
+
+    #[test]
+    fn an_unterminated_attribute_is_left_alone() {
+        let html = r#"<a href="https://example.com>a</a>"#;
+        assert_eq!(tracked(html), html);
+    }
+}
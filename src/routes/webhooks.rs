@@ -0,0 +1,238 @@
+use actix_web::http::header::{HeaderMap, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::Credentials;
+use crate::configuration::PostmarkWebhookSettings;
+use crate::routes::error_chain_fmt;
+use crate::suppression_list::{
+    record_soft_bounce, reset_soft_bounces, suppress_email, SuppressionReason,
+};
+
+#[derive(thiserror::Error)]
+pub enum WebhookError {
+    #[error("authentication failed")]
+    AuthError(#[source] anyhow::Error),
+    #[error("{0}")]
+    InvalidPayload(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for WebhookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for WebhookError {
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            WebhookError::AuthError(_) => {
+                let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
+                let header_value = HeaderValue::from_str(r#"Basic realm="webhooks""#).unwrap();
+                response
+                    .headers_mut()
+                    .insert(actix_web::http::header::WWW_AUTHENTICATE, header_value);
+                response
+            }
+            WebhookError::InvalidPayload(_) => HttpResponse::new(StatusCode::BAD_REQUEST),
+            WebhookError::UnexpectedError(_) => {
+                HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
+    }
+}
+
+/// The fields we use out of Postmark's Bounce, SpamComplaint and Delivery
+/// webhooks.
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct PostmarkEvent {
+    record_type: String,
+    #[serde(rename = "ID")]
+    id: Option<i64>,
+    #[serde(rename = "Type")]
+    event_type: Option<String>,
+    email: Option<String>,
+    /// Delivery webhooks name the address `Recipient` rather than `Email`.
+    recipient: Option<String>,
+    #[serde(rename = "MessageID")]
+    message_id: Option<String>,
+    description: Option<String>,
+    bounced_at: Option<DateTime<Utc>>,
+}
+
+impl PostmarkEvent {
+    /// Whether the address must not be emailed anymore, and why.
+    fn suppression_reason(&self) -> Option<SuppressionReason> {
+        match (self.record_type.as_str(), self.event_type.as_deref()) {
+            ("SpamComplaint", _) | (_, Some("SpamComplaint")) => {
+                Some(SuppressionReason::SpamComplaint)
+            }
+            ("Bounce", Some("HardBounce" | "BadEmailAddress")) => {
+                Some(SuppressionReason::HardBounce)
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the email bounced for reasons that may go away by themselves
+    /// (a full mailbox, a server being down, ...).
+    fn is_soft_bounce(&self) -> bool {
+        self.record_type == "Bounce"
+            && matches!(
+                self.event_type.as_deref(),
+                Some("SoftBounce" | "Transient" | "DnsError")
+            )
+    }
+}
+
+/// Postmark calls this for every bounce, spam complaint and delivery,
+/// authenticating with the basic auth credentials set in its webhook URL.
+#[tracing::instrument(
+    name = "receive a Postmark webhook",
+    skip(request, body, pool, webhook_settings),
+    fields(record_type = tracing::field::Empty, email = tracing::field::Empty)
+)]
+pub async fn postmark_webhook(
+    request: HttpRequest,
+    body: web::Bytes,
+    pool: web::Data<PgPool>,
+    webhook_settings: web::Data<PostmarkWebhookSettings>,
+) -> Result<HttpResponse, WebhookError> {
+    let credentials = basic_authentication(request.headers()).map_err(WebhookError::AuthError)?;
+    if !credentials_match(&credentials, &webhook_settings) {
+        return Err(WebhookError::AuthError(anyhow::anyhow!(
+            "invalid webhook credentials"
+        )));
+    }
+
+    let event: PostmarkEvent = serde_json::from_slice(&body)
+        .map_err(|e| WebhookError::InvalidPayload(format!("invalid webhook payload: {}", e)))?;
+    tracing::Span::current().record("record_type", &tracing::field::display(&event.record_type));
+    if event.record_type == "Delivery" {
+        let recipient = event.recipient.as_deref().ok_or_else(|| {
+            WebhookError::InvalidPayload("the webhook payload has no recipient".into())
+        })?;
+        tracing::Span::current().record("email", &tracing::field::display(recipient));
+        reset_soft_bounces(pool.get_ref(), recipient)
+            .await
+            .context("failed to reset the soft bounces of a subscriber")?;
+        return Ok(HttpResponse::Ok().finish());
+    }
+    if !matches!(event.record_type.as_str(), "Bounce" | "SpamComplaint") {
+        // Postmark keeps retrying webhooks that fail: acknowledge the rest
+        return Ok(HttpResponse::Ok().finish());
+    }
+    let email = event
+        .email
+        .as_deref()
+        .ok_or_else(|| WebhookError::InvalidPayload("the webhook payload has no email".into()))?;
+    tracing::Span::current().record("email", &tracing::field::display(email));
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("failed to acquire a Postgres connection from the pool")?;
+    let is_new = record_event(&mut transaction, &event, email)
+        .await
+        .context("failed to record an email event")?;
+    if let (true, Some(reason)) = (is_new, event.suppression_reason()) {
+        suppress_email(&mut transaction, email, reason)
+            .await
+            .context("failed to suppress an email address")?;
+    }
+    if is_new && event.is_soft_bounce() {
+        record_soft_bounce(&mut transaction, email)
+            .await
+            .context("failed to record a soft bounce")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("failed to commit SQL transaction to record an email event")?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
+    let header_value = headers
+        .get("Authorization")
+        .context("the 'Authorization' header was missing")?
+        .to_str()
+        .context("the 'Authorization' header was not a valid UTF8 string")?;
+    let base64encoded_segment = header_value
+        .strip_prefix("Basic ")
+        .context("the authorization scheme was not 'Basic'")?;
+    let decoded_bytes = base64::decode_config(base64encoded_segment, base64::STANDARD)
+        .context("failed to base64-decode 'Basic' credentials")?;
+    let decoded_credentials = String::from_utf8(decoded_bytes)
+        .context("the decoded credential string is not valid UTF8")?;
+
+    let (username, password) = decoded_credentials
+        .split_once(':')
+        .context("a password must be provided in 'Basic' auth")?;
+    Ok(Credentials {
+        username: username.to_string(),
+        password: Secret::new(password.to_string()),
+    })
+}
+
+/// Compare digests rather than the credentials themselves, so that how long
+/// the comparison takes says nothing about the expected values.
+fn credentials_match(credentials: &Credentials, expected: &PostmarkWebhookSettings) -> bool {
+    let digest = |username: &str, password: &str| {
+        Sha256::new()
+            .chain_update(username)
+            .chain_update([0])
+            .chain_update(password)
+            .finalize()
+    };
+    digest(&credentials.username, credentials.password.expose_secret())
+        == digest(&expected.username, expected.password.expose_secret())
+}
+
+/// Returns `false` if Postmark had already told us about this event.
+#[tracing::instrument(name = "record an email event", skip(transaction, event))]
+async fn record_event(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    event: &PostmarkEvent,
+    email: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO email_events (
+            id,
+            provider_event_id,
+            record_type,
+            event_type,
+            email,
+            provider_message_id,
+            description,
+            occurred_at,
+            received_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, now())
+        ON CONFLICT (provider_event_id) DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        event.id,
+        event.record_type,
+        event.event_type.as_deref().unwrap_or(&event.record_type),
+        email,
+        event.message_id,
+        event.description,
+        event.bounced_at.unwrap_or_else(Utc::now),
+    )
+    .execute(transaction)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
@@ -1,4 +1,5 @@
-use std::net::TcpListener;
+use std::net::{IpAddr, TcpListener};
+use std::time::Duration;
 
 use actix_session::storage::RedisSessionStore;
 use actix_session::SessionMiddleware;
@@ -11,14 +12,24 @@ use actix_web_lab::middleware::from_fn;
 use secrecy::{ExposeSecret, Secret};
 use sqlx::postgres::PgPoolOptions;
 use sqlx::PgPool;
+use tokio_util::sync::CancellationToken;
 use tracing_actix_web::TracingLogger;
 
 use crate::authentication::reject_anonymous_users;
-use crate::configuration::{DatabaseSettings, Settings};
-use crate::email_client::EmailClient;
+use crate::configuration::{DatabaseSettings, Environment, PostmarkWebhookSettings, Settings};
+use crate::email_client::{EmailClient, OutboxTransport};
 use crate::routes::{
-    admin_dashboard, change_password, change_password_form, confirm, health_check, home, log_out,
-    login, login_form, publish_newsletter, send_newsletter_form, subscribe,
+    admin_confirm_subscriber, admin_dashboard, admin_erase_subscriber,
+    admin_unsubscribe_subscriber, atom_feed, cancel_newsletter, change_password,
+    change_password_form, confirm, create_draft, delivery_failures, dev_outbox, dev_outbox_email,
+    edit_draft_form, erase_subscriber_data, erasure_receipts, export_data_subject,
+    export_subscribers, health_check, home, import_subscribers, import_subscribers_form, log_out,
+    login, login_form, newsletter_history, newsletter_issue, postmark_webhook, preview_draft,
+    public_issue, public_issues, publish_draft, publish_newsletter, reschedule_newsletter,
+    resend_confirmation, resend_confirmation_link, rss_feed, save_draft, send_newsletter_form,
+    send_test_email, set_archive_visibility, set_engagement_tracking, subscribe, subscriber,
+    subscriber_import, subscriber_import_rejections, subscribers, track_click, track_open,
+    unsubscribe, unsubscribe_form,
 };
 
 pub struct Application {
@@ -29,7 +40,13 @@ pub struct Application {
 impl Application {
     pub async fn build(configuration: Settings) -> Result<Self, anyhow::Error> {
         let connection_pool = get_connection_pool(&configuration.database);
-        let email_client = configuration.email_client.client();
+        // The outbox can only be browsed when developing locally
+        let outbox = match configuration.environment {
+            Environment::Local => configuration.email_client.outbox(),
+            Environment::Production => None,
+        };
+        let email_client = configuration.email_client();
+        let shutdown_timeout = configuration.application.shutdown_timeout();
         let address = format!(
             "{}:{}",
             configuration.application.host, configuration.application.port
@@ -41,9 +58,14 @@ impl Application {
             listener,
             connection_pool,
             email_client,
+            outbox,
             configuration.application.base_url,
             configuration.application.hmac_secret,
+            configuration.application.privacy_policy_version,
+            configuration.application.trusted_proxies,
             configuration.redis_uri,
+            configuration.postmark_webhook,
+            shutdown_timeout,
         )
         .await?;
         Ok(Self { port, server })
@@ -53,7 +75,17 @@ impl Application {
         self.port
     }
 
-    pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
+    /// Serve requests until `shutdown` is cancelled, then stop accepting new
+    /// connections and let the in-flight requests complete.
+    pub async fn run_until_stopped(
+        self,
+        shutdown: CancellationToken,
+    ) -> Result<(), std::io::Error> {
+        let handle = self.server.handle();
+        tokio::spawn(async move {
+            shutdown.cancelled().await;
+            handle.stop(true).await;
+        });
         self.server.await
     }
 }
@@ -67,14 +99,22 @@ pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
 pub struct ApplicationBaseUrl(pub String);
 #[derive(Clone)]
 pub struct HmacSecret(pub Secret<String>);
+pub struct PrivacyPolicyVersion(pub String);
+pub struct TrustedProxies(pub Vec<IpAddr>);
 
+#[allow(clippy::too_many_arguments)]
 pub async fn run(
     listener: TcpListener,
     db_pool: PgPool,
     email_client: EmailClient,
+    outbox: Option<OutboxTransport>,
     base_url: String,
     hmac_secret: Secret<String>,
+    privacy_policy_version: String,
+    trusted_proxies: Vec<IpAddr>,
     redis_uri: Secret<String>,
+    postmark_webhook_settings: PostmarkWebhookSettings,
+    shutdown_timeout: Duration,
 ) -> Result<Server, anyhow::Error> {
     let secret_key = Key::from(hmac_secret.expose_secret().as_bytes());
     let message_store = CookieMessageStore::builder(secret_key.clone()).build();
@@ -82,7 +122,12 @@ pub async fn run(
 
     let db_pool = web::Data::new(db_pool);
     let email_client = web::Data::new(email_client);
+    let outbox = outbox.map(web::Data::new);
     let base_url = web::Data::new(ApplicationBaseUrl(base_url));
+    let hmac_secret = web::Data::new(HmacSecret(hmac_secret));
+    let privacy_policy_version = web::Data::new(PrivacyPolicyVersion(privacy_policy_version));
+    let trusted_proxies = web::Data::new(TrustedProxies(trusted_proxies));
+    let postmark_webhook_settings = web::Data::new(postmark_webhook_settings);
 
     let redis_store = RedisSessionStore::new(redis_uri.expose_secret()).await?;
 
@@ -100,6 +145,26 @@ pub async fn run(
             .route("/health_check", web::get().to(health_check))
             .route("/subscriptions", web::post().to(subscribe))
             .route("/subscriptions/confirm", web::get().to(confirm))
+            .route(
+                "/subscriptions/confirm/resend",
+                web::post().to(resend_confirmation_link),
+            )
+            .route(
+                "/subscriptions/unsubscribe",
+                web::get().to(unsubscribe_form),
+            )
+            .route("/subscriptions/unsubscribe", web::post().to(unsubscribe))
+            .route(
+                "/subscriptions/erase",
+                web::post().to(erase_subscriber_data),
+            )
+            .route("/issues", web::get().to(public_issues))
+            .route("/issues/{slug}", web::get().to(public_issue))
+            .route("/feed.rss", web::get().to(rss_feed))
+            .route("/feed.atom", web::get().to(atom_feed))
+            .route("/tracking/open", web::get().to(track_open))
+            .route("/tracking/click", web::get().to(track_click))
+            .route("/webhooks/postmark", web::post().to(postmark_webhook))
             .service(
                 web::scope("/admin")
                     .wrap(from_fn(reject_anonymous_users))
@@ -108,12 +173,106 @@ pub async fn run(
                     .route("/password", web::post().to(change_password))
                     .route("/newsletters", web::get().to(send_newsletter_form))
                     .route("/newsletters", web::post().to(publish_newsletter))
-                    .route("/logout", web::post().to(log_out)),
+                    .route("/newsletters/failures", web::get().to(delivery_failures))
+                    .route("/newsletters/history", web::get().to(newsletter_history))
+                    .route("/newsletters/drafts", web::post().to(create_draft))
+                    .route(
+                        "/newsletters/drafts/{newsletter_issue_id}",
+                        web::get().to(edit_draft_form),
+                    )
+                    .route(
+                        "/newsletters/drafts/{newsletter_issue_id}",
+                        web::post().to(save_draft),
+                    )
+                    .route(
+                        "/newsletters/drafts/{newsletter_issue_id}/preview",
+                        web::get().to(preview_draft),
+                    )
+                    .route(
+                        "/newsletters/drafts/{newsletter_issue_id}/test",
+                        web::post().to(send_test_email),
+                    )
+                    .route(
+                        "/newsletters/drafts/{newsletter_issue_id}/publish",
+                        web::post().to(publish_draft),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}",
+                        web::get().to(newsletter_issue),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}/schedule",
+                        web::post().to(reschedule_newsletter),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}/cancel",
+                        web::post().to(cancel_newsletter),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}/archive",
+                        web::post().to(set_archive_visibility),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}/tracking",
+                        web::post().to(set_engagement_tracking),
+                    )
+                    .route("/subscribers", web::get().to(subscribers))
+                    .route("/subscribers/export", web::get().to(export_subscribers))
+                    .route("/subscribers/erasures", web::get().to(erasure_receipts))
+                    .route(
+                        "/subscribers/data-subject-export",
+                        web::get().to(export_data_subject),
+                    )
+                    .route(
+                        "/subscribers/import",
+                        web::get().to(import_subscribers_form),
+                    )
+                    .route("/subscribers/import", web::post().to(import_subscribers))
+                    .route(
+                        "/subscribers/imports/{import_id}",
+                        web::get().to(subscriber_import),
+                    )
+                    .route(
+                        "/subscribers/imports/{import_id}/rejections.csv",
+                        web::get().to(subscriber_import_rejections),
+                    )
+                    .route("/subscribers/{subscriber_id}", web::get().to(subscriber))
+                    .route(
+                        "/subscribers/{subscriber_id}/confirm",
+                        web::post().to(admin_confirm_subscriber),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/unsubscribe",
+                        web::post().to(admin_unsubscribe_subscriber),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/resend-confirmation",
+                        web::post().to(resend_confirmation),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/erase",
+                        web::post().to(admin_erase_subscriber),
+                    )
+                    .route("/logout", web::post().to(log_out))
+                    .configure(|cfg| {
+                        if let Some(outbox) = &outbox {
+                            cfg.app_data(outbox.clone())
+                                .route("/dev/outbox", web::get().to(dev_outbox))
+                                .route("/dev/outbox/{email_id}", web::get().to(dev_outbox_email));
+                        }
+                    }),
             )
             .app_data(db_pool.clone())
             .app_data(email_client.clone())
             .app_data(base_url.clone())
+            .app_data(hmac_secret.clone())
+            .app_data(privacy_policy_version.clone())
+            .app_data(trusted_proxies.clone())
+            .app_data(postmark_webhook_settings.clone())
     })
+    // Signals are handled by the caller, which shuts the worker down too
+    .disable_signals()
+    .shutdown_timeout(shutdown_timeout.as_secs())
     .listen(listener)?
     .run();
 
@@ -0,0 +1,139 @@
+use secrecy::Secret;
+use sqlx::{PgExecutor, PgPool, Postgres, Transaction};
+
+use crate::erasure::email_digest;
+
+/// Why an address ended up on the suppression list.
+#[derive(Debug, Clone, Copy)]
+pub enum SuppressionReason {
+    HardBounce,
+    SpamComplaint,
+}
+
+impl SuppressionReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SuppressionReason::HardBounce => "hard_bounce",
+            SuppressionReason::SpamComplaint => "spam_complaint",
+        }
+    }
+}
+
+/// Stop emailing `email` for good: add it to the suppression list and move
+/// the matching subscriber, if any, to the `suppressed` status.
+#[tracing::instrument(name = "suppress an email address", skip(transaction))]
+pub async fn suppress_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &str,
+    reason: SuppressionReason,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO suppressed_emails (email, reason, suppressed_at)
+        VALUES (lower($1), $2, now())
+        ON CONFLICT (email) DO NOTHING
+        "#,
+        email,
+        reason.as_str()
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET status = 'suppressed'
+        WHERE lower(email) = lower($1)
+        "#,
+        email
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    Ok(())
+}
+
+/// Whether `email` is on the suppression list, including if it has been
+/// erased since: `hmac_secret` is what its digest was computed with.
+#[tracing::instrument(name = "check the suppression list", skip(pool, hmac_secret))]
+pub async fn is_suppressed(
+    pool: &PgPool,
+    email: &str,
+    hmac_secret: &Secret<String>,
+) -> Result<bool, sqlx::Error> {
+    let r = sqlx::query!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM suppressed_emails WHERE email = lower($1)
+            UNION ALL
+            SELECT 1 FROM erased_suppressions WHERE email_digest = $2
+        ) AS "suppressed!"
+        "#,
+        email,
+        email_digest(email, hmac_secret)
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(r.suppressed)
+}
+
+/// An email to `email` bounced without the address being definitely dead.
+/// Too many of those in a row and the subscriber is marked `inactive`, see
+/// `mark_inactive_subscribers`.
+#[tracing::instrument(name = "record a soft bounce", skip(executor))]
+pub async fn record_soft_bounce(
+    executor: impl PgExecutor<'_>,
+    email: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET consecutive_soft_bounces = consecutive_soft_bounces + 1
+        WHERE lower(email) = lower($1)
+        "#,
+        email
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// An email reached `email`: its soft bounces were only a bad patch.
+#[tracing::instrument(name = "reset soft bounces", skip(executor))]
+pub async fn reset_soft_bounces(
+    executor: impl PgExecutor<'_>,
+    email: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET consecutive_soft_bounces = 0
+        WHERE lower(email) = lower($1) AND consecutive_soft_bounces > 0
+        "#,
+        email
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// Stop emailing the confirmed subscribers that soft-bounced at least
+/// `threshold` times in a row, until they confirm their subscription again.
+/// Returns how many subscribers were marked `inactive`.
+#[tracing::instrument(name = "mark inactive subscribers", skip(pool))]
+pub async fn mark_inactive_subscribers(pool: &PgPool, threshold: i16) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET status = 'inactive'
+        WHERE status = 'confirmed' AND consecutive_soft_bounces >= $1
+        "#,
+        threshold
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
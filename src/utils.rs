@@ -20,3 +20,28 @@ where
 {
     actix_web::error::ErrorBadRequest(e)
 }
+
+/// A `LIKE` pattern matching the values that contain `q`, taken literally.
+pub fn contains_pattern(q: &str) -> String {
+    let escaped = q
+        .trim()
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::contains_pattern;
+
+    #[test]
+    fn an_empty_search_matches_everything() {
+        assert_eq!(contains_pattern(""), "%%");
+    }
+
+    #[test]
+    fn like_wildcards_in_the_search_are_matched_literally() {
+        assert_eq!(contains_pattern("100%_off\\"), "%100\\%\\_off\\\\%");
+    }
+}
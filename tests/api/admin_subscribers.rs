@@ -0,0 +1,236 @@
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+async fn subscribe(app: &TestApp, name: &str, email: &str) {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let body = serde_urlencoded::to_string(serde_json::json!({
+        "name": name,
+        "email": email,
+    }))
+    .unwrap();
+    app.post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+}
+
+async fn subscriber_id(app: &TestApp, email: &str) -> Uuid {
+    sqlx::query!("SELECT id FROM subscriptions WHERE email = $1", email)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id
+}
+
+async fn subscriber_status(app: &TestApp, subscriber_id: Uuid) -> String {
+    sqlx::query!(
+        "SELECT status FROM subscriptions WHERE id = $1",
+        subscriber_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .status
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_subscribers() {
+    let app = spawn_app().await;
+    subscribe(&app, "le guin", "ursula@example.com").await;
+    let subscriber_id = subscriber_id(&app, "ursula@example.com").await;
+
+    let response = app.get_subscribers("").await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app.get_subscriber(&subscriber_id).await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app.post_subscriber_action(&subscriber_id, "erase").await;
+    assert_is_redirect_to(&response, "/login");
+
+    assert_eq!(
+        subscriber_status(&app, subscriber_id).await,
+        "pending_confirmation"
+    );
+}
+
+#[tokio::test]
+async fn subscribers_can_be_searched_by_email_name_and_status() {
+    let app = spawn_app().await;
+    subscribe(&app, "le guin", "ursula@example.com").await;
+    subscribe(&app, "butler", "octavia@example.com").await;
+    let octavia_id = subscriber_id(&app, "octavia@example.com").await;
+    app.test_user.login(&app).await;
+    app.post_subscriber_action(&octavia_id, "confirm").await;
+
+    let html_page = app.get_subscribers_html("").await;
+    assert!(html_page.contains("ursula@example.com"));
+    assert!(html_page.contains("octavia@example.com"));
+
+    let html_page = app.get_subscribers_html("q=URSULA").await;
+    assert!(html_page.contains("ursula@example.com"));
+    assert!(!html_page.contains("octavia@example.com"));
+
+    let html_page = app.get_subscribers_html("q=butler").await;
+    assert!(!html_page.contains("ursula@example.com"));
+    assert!(html_page.contains("octavia@example.com"));
+
+    let html_page = app.get_subscribers_html("status=confirmed").await;
+    assert!(!html_page.contains("ursula@example.com"));
+    assert!(html_page.contains("octavia@example.com"));
+    assert!(html_page.contains("<p>1 subscribers found.</p>"));
+}
+
+#[tokio::test]
+async fn the_subscriber_page_shows_the_status_history_and_confirmation_links() {
+    let app = spawn_app().await;
+    let confirmation_links = app.create_unconfirmed_subscriber().await;
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+    app.test_user.login(&app).await;
+
+    let html_page = app.get_subscriber_html(&subscriber_id).await;
+    assert!(html_page.contains("<li>Sent "));
+    let token = confirmation_links
+        .html
+        .query_pairs()
+        .find(|(k, _)| k == "subscription_token")
+        .unwrap()
+        .1
+        .into_owned();
+    assert!(!html_page.contains(&token), "only token hashes are stored");
+
+    reqwest::get(confirmation_links.html.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let html_page = app.get_subscriber_html(&subscriber_id).await;
+
+    assert!(html_page.contains("<li>Status: confirmed</li>"));
+    let confirmed_at = html_page.find("<tr><td>confirmed</td>").unwrap();
+    let pending_at = html_page.find("<tr><td>pending_confirmation</td>").unwrap();
+    assert!(confirmed_at < pending_at, "the latest status comes first");
+    assert!(!html_page.contains("<li>Sent "), "links are used up");
+}
+
+#[tokio::test]
+async fn unknown_subscribers_are_not_found() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app.get_subscriber(&Uuid::new_v4()).await;
+    assert_eq!(response.status().as_u16(), 404);
+    let response = app.post_subscriber_action(&Uuid::new_v4(), "confirm").await;
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn subscribers_can_be_confirmed_and_unsubscribed_manually() {
+    let app = spawn_app().await;
+    subscribe(&app, "le guin", "ursula@example.com").await;
+    let subscriber_id = subscriber_id(&app, "ursula@example.com").await;
+    app.test_user.login(&app).await;
+
+    let response = app.post_subscriber_action(&subscriber_id, "confirm").await;
+    assert_is_redirect_to(&response, &format!("/admin/subscribers/{}", subscriber_id));
+    assert_eq!(subscriber_status(&app, subscriber_id).await, "confirmed");
+    let html_page = app.get_subscriber_html(&subscriber_id).await;
+    assert!(html_page.contains("<p><i>The subscriber has been confirmed.</i></p>"));
+
+    let response = app
+        .post_subscriber_action(&subscriber_id, "unsubscribe")
+        .await;
+    assert_is_redirect_to(&response, &format!("/admin/subscribers/{}", subscriber_id));
+    assert_eq!(subscriber_status(&app, subscriber_id).await, "unsubscribed");
+}
+
+#[tokio::test]
+async fn a_new_confirmation_email_can_be_sent() {
+    let app = spawn_app().await;
+    subscribe(&app, "le guin", "ursula@example.com").await;
+    let subscriber_id = subscriber_id(&app, "ursula@example.com").await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_subscriber_action(&subscriber_id, "resend-confirmation")
+        .await;
+    assert_is_redirect_to(&response, &format!("/admin/subscribers/{}", subscriber_id));
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let confirmation_links = app.get_confirmation_links(&email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    assert_eq!(subscriber_status(&app, subscriber_id).await, "confirmed");
+}
+
+#[tokio::test]
+async fn confirmed_subscribers_are_not_sent_a_confirmation_email() {
+    let app = spawn_app().await;
+    subscribe(&app, "le guin", "ursula@example.com").await;
+    let subscriber_id = subscriber_id(&app, "ursula@example.com").await;
+    app.test_user.login(&app).await;
+    app.post_subscriber_action(&subscriber_id, "confirm").await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_subscriber_action(&subscriber_id, "resend-confirmation")
+        .await;
+    assert_is_redirect_to(&response, &format!("/admin/subscribers/{}", subscriber_id));
+}
+
+#[tokio::test]
+async fn subscribers_can_be_erased() {
+    let app = spawn_app().await;
+    subscribe(&app, "le guin", "ursula@example.com").await;
+    let subscriber_id = subscriber_id(&app, "ursula@example.com").await;
+    app.test_user.login(&app).await;
+
+    let response = app.post_subscriber_action(&subscriber_id, "erase").await;
+    assert_is_redirect_to(&response, "/admin/subscribers");
+
+    let n_subscribers = sqlx::query!(r#"SELECT count(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_subscribers, 0);
+    let n_tokens = sqlx::query!(r#"SELECT count(*) AS "count!" FROM subscription_tokens"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_tokens, 0);
+    let html_page = app.get_subscribers_html("").await;
+    assert!(html_page.contains("<p><i>The subscriber has been erased, see receipt "));
+    let response = app.post_subscriber_action(&subscriber_id, "erase").await;
+    assert_eq!(response.status().as_u16(), 404);
+}
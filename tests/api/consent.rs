@@ -0,0 +1,162 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+async fn consent_trail(app: &TestApp) -> Vec<(String, String, Option<String>, Option<String>)> {
+    sqlx::query!(
+        r#"
+        SELECT kind, source, user_agent, privacy_policy_version
+        FROM consent_events
+        ORDER BY id
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|r| (r.kind, r.source, r.user_agent, r.privacy_policy_version))
+    .collect()
+}
+
+#[tokio::test]
+async fn subscribing_records_who_consented_from_where() {
+    let app = spawn_app_with(|c| c.application.privacy_policy_version = "v42".into()).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.api_client
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("User-Agent", "Subscriber's browser")
+        .body("name=le%20guin&email=ursula_le_guin%40gmail.com&source=footer")
+        .send()
+        .await
+        .unwrap();
+
+    let trail = consent_trail(&app).await;
+    assert_eq!(
+        trail,
+        vec![(
+            "subscribed".into(),
+            "footer".into(),
+            Some("Subscriber's browser".into()),
+            Some("v42".into())
+        )]
+    );
+    let ip_address = sqlx::query!("SELECT ip_address FROM consent_events")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .ip_address;
+    assert_eq!(ip_address.as_deref(), Some("127.0.0.1"));
+}
+
+#[tokio::test]
+async fn subscription_forms_that_do_not_tell_their_name_are_recorded_as_such() {
+    let app = spawn_app().await;
+
+    app.create_unconfirmed_subscriber().await;
+
+    let trail = consent_trail(&app).await;
+    assert_eq!(trail.len(), 1);
+    assert_eq!(trail[0].1, "subscription_form");
+}
+
+#[tokio::test]
+async fn confirming_is_recorded_after_subscribing() {
+    let app = spawn_app().await;
+
+    app.create_confirmed_subscriber().await;
+
+    let trail = consent_trail(&app).await;
+    let steps: Vec<_> = trail
+        .iter()
+        .map(|(kind, source, _, _)| (kind.as_str(), source.as_str()))
+        .collect();
+    assert_eq!(
+        steps,
+        vec![
+            ("subscribed", "subscription_form"),
+            ("confirmed", "confirmation_link")
+        ]
+    );
+}
+
+#[tokio::test]
+async fn the_consent_trail_is_shown_on_the_subscriber_page() {
+    let app = spawn_app().await;
+    app.create_unconfirmed_subscriber().await;
+    app.test_user.login(&app).await;
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+
+    app.post_subscriber_action(&subscriber_id, "confirm").await;
+
+    let html_page = app.get_subscriber_html(&subscriber_id).await;
+    assert!(html_page.contains("<h2>Consent</h2>"));
+    assert!(html_page.contains("<td>subscribed</td>"));
+    assert!(html_page.contains(&format!("<td>admin:{}</td>", app.test_user.user_id)));
+}
+
+#[tokio::test]
+async fn forwarding_headers_do_not_change_the_recorded_address() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.api_client
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("X-Forwarded-For", "203.0.113.7")
+        .header("Forwarded", "for=203.0.113.7")
+        .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .send()
+        .await
+        .unwrap();
+
+    let ip_address = sqlx::query!("SELECT ip_address FROM consent_events")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .ip_address;
+    assert_eq!(ip_address.as_deref(), Some("127.0.0.1"));
+}
+
+#[tokio::test]
+async fn forwarding_headers_from_a_trusted_proxy_give_the_recorded_address() {
+    let app = spawn_app_with(|c| {
+        c.application.trusted_proxies = vec!["127.0.0.1".parse().unwrap()];
+    })
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.api_client
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("Forwarded", "for=\"203.0.113.7:41234\"")
+        .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .send()
+        .await
+        .unwrap();
+
+    let ip_address = sqlx::query!("SELECT ip_address FROM consent_events")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .ip_address;
+    assert_eq!(ip_address.as_deref(), Some("203.0.113.7"));
+}
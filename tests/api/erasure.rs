@@ -0,0 +1,371 @@
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+/// Deliver an issue to the (only) confirmed subscriber, who then opens it.
+/// Returns the link to erase their data, next to the unsubscribe one.
+async fn deliver_and_open_newsletter(app: &TestApp) -> reqwest::Url {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_newsletters(&serde_json::json!({
+        "title": "Newsletter title",
+        "text": "Newsletter body as plain text",
+        "html": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": Uuid::new_v4().to_string(),
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_engagement_events (id, newsletter_issue_id, subscriber_id, kind, occurred_at)
+        SELECT gen_random_uuid(), newsletter_issue_id, subscriptions.id, 'open', now()
+        FROM newsletter_issues, subscriptions
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let mut erase_link = app.get_unsubscribe_link(&email_request);
+    erase_link.set_path("/subscriptions/erase");
+    erase_link
+}
+
+async fn subscriber(app: &TestApp) -> (Uuid, String) {
+    let r = sqlx::query!("SELECT id, email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    (r.id, r.email)
+}
+
+async fn n_rows_mentioning(app: &TestApp, email: &str) -> i64 {
+    sqlx::query!(
+        r#"
+        SELECT (
+            (SELECT count(*) FROM subscriptions WHERE lower(email) = lower($1)) +
+            (SELECT count(*) FROM issue_deliveries WHERE lower(subscriber_email) = lower($1)) +
+            (SELECT count(*) FROM issue_delivery_queue WHERE lower(subscriber_email) = lower($1)) +
+            (SELECT count(*) FROM email_events WHERE lower(email) = lower($1)) +
+            (SELECT count(*) FROM suppressed_emails WHERE email = lower($1))
+        ) AS "count!"
+        "#,
+        email
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .count
+}
+
+#[tokio::test]
+async fn erasing_a_subscriber_removes_them_but_keeps_the_statistics() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(&app).await;
+    deliver_and_open_newsletter(&app).await;
+    let (subscriber_id, email) = subscriber(&app).await;
+    assert!(n_rows_mentioning(&app, &email).await > 0);
+
+    let response = app.post_subscriber_action(&subscriber_id, "erase").await;
+    assert_is_redirect_to(&response, "/admin/subscribers");
+
+    assert_eq!(n_rows_mentioning(&app, &email).await, 0);
+    let deliveries = sqlx::query!("SELECT subscriber_email, status FROM issue_deliveries")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(deliveries.len(), 1);
+    assert_eq!(deliveries[0].status, "sent");
+    assert!(deliveries[0].subscriber_email.starts_with("erased:"));
+    let opens = sqlx::query!(
+        r#"SELECT count(DISTINCT subscriber_id) AS "count!" FROM issue_engagement_events"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .count;
+    assert_eq!(opens, 1);
+    let n_status_changes = sqlx::query!(
+        r#"SELECT count(*) AS "count!" FROM subscription_status_changes WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .count;
+    assert_eq!(n_status_changes, 0);
+}
+
+#[tokio::test]
+async fn every_erasure_leaves_a_receipt_that_tells_an_address_was_erased() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(&app).await;
+    let (subscriber_id, email) = subscriber(&app).await;
+
+    app.post_subscriber_action(&subscriber_id, "erase").await;
+
+    let receipt =
+        sqlx::query!("SELECT email_digest, requested_by, affected_rows FROM erasure_receipts")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert!(!receipt.email_digest.contains(&email));
+    assert!(receipt.requested_by.starts_with("admin:"));
+    assert!(receipt.affected_rows.contains(r#""subscriptions":1"#));
+    let html_page = app.get_erasure_receipts_html(&email.to_uppercase()).await;
+    assert!(html_page.contains("<p>The 1 receipts are intact.</p>"));
+    assert!(html_page.contains("has been erased.</p>"));
+    let html_page = app.get_erasure_receipts_html("someone@example.com").await;
+    assert!(html_page.contains("<p>someone@example.com has never been erased.</p>"));
+}
+
+#[tokio::test]
+async fn tampering_with_a_receipt_is_detected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    for email in [
+        "ursula@example.com",
+        "octavia@example.com",
+        "ted@example.com",
+    ] {
+        sqlx::query!(
+            r#"
+            INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+            VALUES (gen_random_uuid(), $1, 'someone', now(), 'confirmed')
+            "#,
+            email
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+        let (subscriber_id, _) = subscriber(&app).await;
+        app.post_subscriber_action(&subscriber_id, "erase").await;
+    }
+    let html_page = app.get_erasure_receipts_html("").await;
+    assert!(html_page.contains("<p>The 3 receipts are intact.</p>"));
+
+    // Removing a receipt in the middle of the chain
+    let second =
+        sqlx::query!("SELECT erasure_id FROM erasure_receipts ORDER BY id OFFSET 1 LIMIT 1")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .erasure_id;
+    sqlx::query!("DELETE FROM erasure_receipts WHERE erasure_id = $1", second)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let html_page = app.get_erasure_receipts_html("").await;
+    assert!(html_page.contains("The receipts have been tampered with"));
+}
+
+#[tokio::test]
+async fn subscribers_can_erase_their_data_from_the_unsubscribe_page() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(&app).await;
+    let erase_link = deliver_and_open_newsletter(&app).await;
+    let (_, email) = subscriber(&app).await;
+
+    let response = reqwest::Client::new()
+        .post(erase_link.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(n_rows_mentioning(&app, &email).await, 0);
+
+    // Asking again is fine, and does not leave a second receipt
+    let response = reqwest::Client::new()
+        .post(erase_link)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let receipts = sqlx::query!("SELECT requested_by FROM erasure_receipts")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(receipts.len(), 1);
+    assert_eq!(receipts[0].requested_by, "subscriber");
+}
+
+#[tokio::test]
+async fn erasing_with_a_tampered_link_is_rejected() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(&app).await;
+    let mut erase_link = deliver_and_open_newsletter(&app).await;
+    let query: Vec<(String, String)> = erase_link
+        .query_pairs()
+        .map(|(k, v)| {
+            let v = if k == "subscriber_id" {
+                Uuid::new_v4().to_string()
+            } else {
+                v.into_owned()
+            };
+            (k.into_owned(), v)
+        })
+        .collect();
+    erase_link.query_pairs_mut().clear().extend_pairs(query);
+
+    let response = reqwest::Client::new()
+        .post(erase_link)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+    let n_subscribers = sqlx::query!(r#"SELECT count(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_subscribers, 1);
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_erasure_receipts() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_client
+        .get(format!("{}/admin/subscribers/erasures", &app.address))
+        .send()
+        .await
+        .unwrap();
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+/// Make the (only) subscriber hard-bounce, then erase them.
+/// Returns their address.
+async fn erase_a_suppressed_subscriber(app: &TestApp) -> String {
+    app.create_confirmed_subscriber().await;
+    let (subscriber_id, email) = subscriber(app).await;
+    app.post_postmark_webhook(&serde_json::json!({
+        "RecordType": "Bounce",
+        "ID": 1,
+        "Type": "HardBounce",
+        "TypeCode": 1,
+        "MessageID": Uuid::new_v4().to_string(),
+        "Email": email,
+        "BouncedAt": "2026-10-18T10:00:00Z",
+    }))
+    .await;
+    app.test_user.login(app).await;
+    let response = app.post_subscriber_action(&subscriber_id, "erase").await;
+    assert_is_redirect_to(&response, "/admin/subscribers");
+    email
+}
+
+#[tokio::test]
+async fn erased_addresses_stay_suppressed() {
+    let app = spawn_app().await;
+    let email = erase_a_suppressed_subscriber(&app).await;
+    assert_eq!(n_rows_mentioning(&app, &email).await, 0);
+
+    let body = serde_urlencoded::to_string([("name", "le guin"), ("email", &email.to_uppercase())])
+        .unwrap();
+    let response = app.post_subscriptions(body).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(n_rows_mentioning(&app, &email).await, 0);
+
+    let response = app
+        .post_subscriber_import("confirmed", &format!("email,name\n{},le guin\n", email))
+        .await;
+    assert_eq!(response.status().as_u16(), 303);
+    assert_eq!(n_rows_mentioning(&app, &email).await, 0);
+    let n_rejected = sqlx::query!(
+        r#"
+        SELECT count(*) AS "count!" FROM subscriber_import_rejections
+        WHERE reason = 'on the suppression list'
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .count;
+    assert_eq!(n_rejected, 1);
+}
+
+#[tokio::test]
+async fn erased_suppressed_addresses_do_not_get_new_issues() {
+    let app = spawn_app().await;
+    let email = erase_a_suppressed_subscriber(&app).await;
+    // Sneaked back in without going through the suppression checks
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, $2, 'le guin', now(), 'confirmed')
+        "#,
+        Uuid::new_v4(),
+        email.to_uppercase()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    app.publish_newsletter().await;
+
+    assert_eq!(app.n_queued_deliveries().await, 0);
+}
+
+#[tokio::test]
+async fn erased_suppressed_non_ascii_addresses_do_not_get_new_issues() {
+    let app = spawn_app().await;
+    let subscriber_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, 'Ünïcödé@example.com', 'le guin', now(), 'confirmed')
+        "#,
+        subscriber_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.post_postmark_webhook(&serde_json::json!({
+        "RecordType": "Bounce",
+        "ID": 1,
+        "Type": "HardBounce",
+        "TypeCode": 1,
+        "MessageID": Uuid::new_v4().to_string(),
+        "Email": "Ünïcödé@example.com",
+        "BouncedAt": "2026-10-18T10:00:00Z",
+    }))
+    .await;
+    app.test_user.login(&app).await;
+    app.post_subscriber_action(&subscriber_id, "erase").await;
+    // Sneaked back in without going through the suppression checks
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, 'Ünïcödé@EXAMPLE.COM', 'le guin', now(), 'confirmed')
+        "#,
+        Uuid::new_v4()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    app.publish_newsletter().await;
+
+    assert_eq!(app.n_queued_deliveries().await, 0);
+}
@@ -0,0 +1,169 @@
+use reqwest::header::{CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn get_feed(app: &TestApp, feed: &str) -> reqwest::Response {
+    reqwest::get(format!("{}/{}", app.address, feed))
+        .await
+        .expect("failed to execute request")
+}
+
+#[tokio::test]
+async fn feeds_contain_the_published_issues() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.publish_newsletter_with(
+        serde_json::json!({"title": "Spring & summer news", "hide_from_archive": false}),
+    )
+    .await;
+    let slug = sqlx::query!(r#"SELECT slug AS "slug!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .slug;
+
+    for (feed, content_type) in [
+        ("feed.rss", "application/rss+xml; charset=utf-8"),
+        ("feed.atom", "application/atom+xml; charset=utf-8"),
+    ] {
+        let response = get_feed(&app, feed).await;
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(response.headers()[CONTENT_TYPE], content_type);
+
+        let body = response.text().await.unwrap();
+        assert!(body.contains("<title>Spring &amp; summer news</title>"));
+        assert!(body.contains(&format!("/issues/{}", slug)));
+        // The HTML content is escaped
+        assert!(body.contains("&lt;p&gt;Newsletter body as HTML&lt;"));
+    }
+}
+
+#[tokio::test]
+async fn feeds_leave_out_issues_that_are_not_in_the_public_archive() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.publish_newsletter_with(
+        serde_json::json!({"title": "Members only", "hide_from_archive": true}),
+    )
+    .await;
+
+    for feed in ["feed.rss", "feed.atom"] {
+        let body = get_feed(&app, feed).await.text().await.unwrap();
+        assert!(!body.contains("Members only"));
+    }
+}
+
+#[tokio::test]
+async fn feeds_can_be_revalidated_with_their_etag() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.publish_newsletter_with(
+        serde_json::json!({"title": "Spring news", "hide_from_archive": false}),
+    )
+    .await;
+
+    for feed in ["feed.rss", "feed.atom"] {
+        let response = get_feed(&app, feed).await;
+        let etag = response.headers()[ETAG].clone();
+
+        let response = reqwest::Client::new()
+            .get(format!("{}/{}", app.address, feed))
+            .header(IF_NONE_MATCH, etag.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 304);
+        assert_eq!(response.headers()[ETAG], etag);
+    }
+}
+
+#[tokio::test]
+async fn the_etag_changes_when_a_new_issue_is_published() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.publish_newsletter_with(
+        serde_json::json!({"title": "Spring news", "hide_from_archive": false}),
+    )
+    .await;
+    let etag = get_feed(&app, "feed.rss").await.headers()[ETAG].clone();
+
+    app.publish_newsletter_with(
+        serde_json::json!({"title": "Summer news", "hide_from_archive": false}),
+    )
+    .await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/feed.rss", app.address))
+        .header(IF_NONE_MATCH, etag)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains("Summer news"));
+}
+
+#[tokio::test]
+async fn feeds_can_be_revalidated_with_their_last_modified_date() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.publish_newsletter_with(
+        serde_json::json!({"title": "Spring news", "hide_from_archive": false}),
+    )
+    .await;
+
+    let response = get_feed(&app, "feed.atom").await;
+    let last_modified = response.headers()[LAST_MODIFIED].clone();
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/feed.atom", app.address))
+        .header(IF_MODIFIED_SINCE, last_modified)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 304);
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/feed.atom", app.address))
+        .header(IF_MODIFIED_SINCE, "Mon, 01 Jan 2001 00:00:00 GMT")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn hiding_an_issue_from_the_archive_changes_the_last_modified_date() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.publish_newsletter_with(serde_json::json!({"title": "Spring news"}))
+        .await;
+    app.publish_newsletter_with(serde_json::json!({"title": "Summer news"}))
+        .await;
+    // HTTP dates are to the second: publish them well before hiding one
+    sqlx::query!("UPDATE newsletter_issues SET published_at = published_at - interval '1 hour'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let last_modified = get_feed(&app, "feed.rss").await.headers()[LAST_MODIFIED].clone();
+    let summer_news = sqlx::query!(
+        "SELECT newsletter_issue_id FROM newsletter_issues WHERE title = 'Summer news'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .newsletter_issue_id;
+
+    app.post_archive_visibility(&summer_news, false).await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/feed.rss", app.address))
+        .header(IF_MODIFIED_SINCE, last_modified.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_ne!(response.headers()[LAST_MODIFIED], last_modified);
+    let feed = response.text().await.unwrap();
+    assert!(feed.contains("Spring news"));
+    assert!(!feed.contains("Summer news"));
+}
@@ -5,14 +5,19 @@ use fake::faker::internet::en::SafeEmail;
 use fake::faker::name::en::Name;
 use fake::Fake;
 use once_cell::sync::Lazy;
+use secrecy::{ExposeSecret, Secret};
 use sqlx::{Connection, Executor, PgConnection, PgPool};
+use tokio::sync::Semaphore;
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 use wiremock::matchers::{method, path};
 use wiremock::{Mock, MockServer, ResponseTemplate};
 
-use zero2prod::configuration::{get_configuration, DatabaseSettings};
+use zero2prod::configuration::{get_configuration, DatabaseSettings, EmailTransportKind, Settings};
 use zero2prod::email_client::EmailClient;
-use zero2prod::issue_delivery_worker::{try_execute_task, ExecutionOutcome};
+use zero2prod::issue_delivery_worker::{
+    try_execute_task, try_send_queued_confirmations, ExecutionOutcome,
+};
 use zero2prod::startup::{get_connection_pool, Application};
 use zero2prod::telemetry::{get_subscriber, init_subscriber};
 
@@ -28,6 +33,39 @@ static TRACING: Lazy<()> = Lazy::new(|| {
     }
 });
 
+/// Answers like Postmark's batch API: every email is accepted, except the
+/// first one if `reject_first` is set.
+pub struct PostmarkBatchResponder {
+    pub reject_first: bool,
+}
+
+impl wiremock::Respond for PostmarkBatchResponder {
+    fn respond(&self, request: &wiremock::Request) -> ResponseTemplate {
+        let emails: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+        let results: Vec<_> = emails
+            .iter()
+            .enumerate()
+            .map(|(i, email)| {
+                if i == 0 && self.reject_first {
+                    serde_json::json!({
+                        "ErrorCode": 406,
+                        "Message": "You tried to send to a recipient that has been marked as inactive.",
+                        "To": email["To"],
+                    })
+                } else {
+                    serde_json::json!({
+                        "ErrorCode": 0,
+                        "Message": "OK",
+                        "MessageID": Uuid::new_v4().to_string(),
+                        "To": email["To"],
+                    })
+                }
+            })
+            .collect();
+        ResponseTemplate::new(200).set_body_json(results)
+    }
+}
+
 pub struct ConfirmationLinks {
     pub html: reqwest::Url,
     pub plain_text: reqwest::Url,
@@ -40,17 +78,35 @@ pub struct TestApp {
     pub db_pool: PgPool,
     pub email_client: EmailClient,
     pub email_server: MockServer,
+    pub base_url: String,
+    pub hmac_secret: Secret<String>,
     pub test_user: TestUser,
     pub api_client: reqwest::Client,
+    pub configuration: Settings,
+    pub shutdown: CancellationToken,
 }
 
 impl TestApp {
     pub async fn dispatch_all_pending_emails(&self) {
+        while let ExecutionOutcome::TaskCompleted = try_send_queued_confirmations(
+            &self.db_pool,
+            &self.email_client,
+            &self.base_url,
+            10,
+            &Semaphore::new(1),
+        )
+        .await
+        .unwrap()
+        {}
         loop {
-            if let ExecutionOutcome::EmptyQueue =
-                try_execute_task(&self.db_pool, &self.email_client)
-                    .await
-                    .unwrap()
+            if let ExecutionOutcome::EmptyQueue = try_execute_task(
+                &self.db_pool,
+                &self.email_client,
+                &self.base_url,
+                &self.hmac_secret,
+            )
+            .await
+            .unwrap()
             {
                 break;
             }
@@ -91,6 +147,38 @@ impl TestApp {
         ConfirmationLinks { html, plain_text }
     }
 
+    /// Extract the unsubscribe link appended to a newsletter issue.
+    pub fn get_unsubscribe_link(&self, email_request: &wiremock::Request) -> reqwest::Url {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+
+        let links: Vec<_> = linkify::LinkFinder::new()
+            .links(body["TextBody"].as_str().unwrap())
+            .filter(|l| *l.kind() == linkify::LinkKind::Url)
+            .filter(|l| l.as_str().contains("/subscriptions/unsubscribe"))
+            .collect();
+        assert_eq!(links.len(), 1);
+        let mut unsubscribe_link = reqwest::Url::parse(links[0].as_str()).unwrap();
+
+        assert_eq!(unsubscribe_link.host_str().unwrap(), "127.0.0.1");
+        unsubscribe_link.set_port(Some(self.port)).unwrap();
+        unsubscribe_link
+    }
+
+    /// Extract the "view in browser" link prepended to a newsletter issue.
+    pub fn get_permalink(&self, email_request: &wiremock::Request) -> Option<reqwest::Url> {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+
+        let link = linkify::LinkFinder::new()
+            .links(body["TextBody"].as_str().unwrap())
+            .filter(|l| *l.kind() == linkify::LinkKind::Url)
+            .find(|l| l.as_str().contains("/issues/"))?;
+        let mut permalink = reqwest::Url::parse(link.as_str()).unwrap();
+
+        assert_eq!(permalink.host_str().unwrap(), "127.0.0.1");
+        permalink.set_port(Some(self.port)).unwrap();
+        Some(permalink)
+    }
+
     /// Use the public API of the application under test to create
     /// an unconfirmed subscriber.
     pub async fn create_unconfirmed_subscriber(&self) -> ConfirmationLinks {
@@ -135,6 +223,53 @@ impl TestApp {
             .unwrap();
     }
 
+    /// The email address of the only subscriber.
+    pub async fn subscriber_email(&self) -> String {
+        sqlx::query!("SELECT email FROM subscriptions")
+            .fetch_one(&self.db_pool)
+            .await
+            .unwrap()
+            .email
+    }
+
+    /// The status of the only subscriber.
+    pub async fn subscriber_status(&self) -> String {
+        sqlx::query!("SELECT status FROM subscriptions")
+            .fetch_one(&self.db_pool)
+            .await
+            .unwrap()
+            .status
+    }
+
+    /// Publish an issue through the admin area, as the logged in test user.
+    pub async fn publish_newsletter(&self) {
+        self.publish_newsletter_with(serde_json::json!({})).await
+    }
+
+    /// Same as `publish_newsletter`, with `fields` overriding the defaults
+    /// of the form, e.g. the title.
+    pub async fn publish_newsletter_with(&self, fields: serde_json::Value) {
+        let mut body = serde_json::json!({
+            "title": "Newsletter title",
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": Uuid::new_v4().to_string(),
+        });
+        for (field, value) in fields.as_object().unwrap() {
+            body[field] = value.clone();
+        }
+        let response = self.post_newsletters(&body).await;
+        assert_is_redirect_to(&response, "/admin/newsletters");
+    }
+
+    pub async fn n_queued_deliveries(&self) -> i64 {
+        sqlx::query!("SELECT count(*) AS \"count!\" FROM issue_delivery_queue")
+            .fetch_one(&self.db_pool)
+            .await
+            .unwrap()
+            .count
+    }
+
     pub async fn post_login<Body>(&self, body: &Body) -> reqwest::Response
     where
         Body: serde::Serialize,
@@ -170,6 +305,173 @@ impl TestApp {
         self.get_admin_dashboard().await.text().await.unwrap()
     }
 
+    pub async fn get_subscribers(&self, query: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/subscribers?{}", &self.address, query))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_subscribers_html(&self, query: &str) -> String {
+        self.get_subscribers(query).await.text().await.unwrap()
+    }
+
+    pub async fn get_subscriber(&self, subscriber_id: &Uuid) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/subscribers/{}",
+                &self.address, subscriber_id
+            ))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_subscriber_html(&self, subscriber_id: &Uuid) -> String {
+        self.get_subscriber(subscriber_id)
+            .await
+            .text()
+            .await
+            .unwrap()
+    }
+
+    /// `action` is one of the buttons of the subscriber page, e.g. `confirm`.
+    pub async fn get_subscribers_export(&self, format: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/subscribers/export?format={}",
+                &self.address, format
+            ))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_data_subject_export(&self, email: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/subscribers/data-subject-export",
+                &self.address
+            ))
+            .query(&[("email", email)])
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_erasure_receipts_html(&self, email: &str) -> String {
+        self.api_client
+            .get(format!("{}/admin/subscribers/erasures", &self.address))
+            .query(&[("email", email)])
+            .send()
+            .await
+            .expect("failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_import_subscribers_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/subscribers/import", &self.address))
+            .send()
+            .await
+            .expect("failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    /// Upload `csv` as a file to import, as the admin form does.
+    pub async fn post_subscriber_import(&self, status: &str, csv: &str) -> reqwest::Response {
+        let form = reqwest::multipart::Form::new()
+            .text("status", status.to_owned())
+            .part(
+                "file",
+                reqwest::multipart::Part::text(csv.to_owned())
+                    .file_name("subscribers.csv")
+                    .mime_str("text/csv")
+                    .unwrap(),
+            );
+        self.api_client
+            .post(format!("{}/admin/subscribers/import", &self.address))
+            .multipart(form)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_subscriber_import_html(&self, import_id: &str) -> String {
+        self.api_client
+            .get(format!(
+                "{}/admin/subscribers/imports/{}",
+                &self.address, import_id
+            ))
+            .send()
+            .await
+            .expect("failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_subscriber_import_rejections(&self, import_id: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/subscribers/imports/{}/rejections.csv",
+                &self.address, import_id
+            ))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn post_subscriber_action(
+        &self,
+        subscriber_id: &Uuid,
+        action: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/subscribers/{}/{}",
+                &self.address, subscriber_id, action
+            ))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn post_postmark_webhook(&self, body: &serde_json::Value) -> reqwest::Response {
+        let credentials = &self.configuration.postmark_webhook;
+        self.api_client
+            .post(format!("{}/webhooks/postmark", &self.address))
+            .basic_auth(
+                &credentials.username,
+                Some(credentials.password.expose_secret()),
+            )
+            .json(body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_outbox(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/dev/outbox", &self.address))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_outbox_email(&self, email_id: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/dev/outbox/{}", &self.address, email_id))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
     pub async fn get_change_password(&self) -> reqwest::Response {
         self.api_client
             .get(&format!("{}/admin/password", &self.address))
@@ -222,6 +524,47 @@ impl TestApp {
             .expect("failed to get request text")
     }
 
+    pub async fn get_newsletter_issue(&self, newsletter_issue_id: &Uuid) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/newsletters/{}",
+                &self.address, newsletter_issue_id
+            ))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_newsletter_history(&self, query: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/newsletters/history?{}",
+                &self.address, query
+            ))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_newsletter_history_html(&self, query: &str) -> String {
+        self.get_newsletter_history(query)
+            .await
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_delivery_failures_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/newsletters/failures", &self.address))
+            .send()
+            .await
+            .expect("failed to execute request")
+            .text()
+            .await
+            .expect("failed to get request text")
+    }
+
     pub async fn post_newsletters<Body>(&self, body: &Body) -> reqwest::Response
     where
         Body: serde::Serialize,
@@ -233,6 +576,177 @@ impl TestApp {
             .await
             .expect("failed to execute request")
     }
+
+    pub async fn post_drafts<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/newsletters/drafts", &self.address))
+            .form(&body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_draft_html(&self, newsletter_issue_id: &Uuid) -> String {
+        self.api_client
+            .get(format!(
+                "{}/admin/newsletters/drafts/{}",
+                &self.address, newsletter_issue_id
+            ))
+            .send()
+            .await
+            .expect("failed to execute request")
+            .text()
+            .await
+            .expect("failed to get request text")
+    }
+
+    pub async fn post_draft<Body>(
+        &self,
+        newsletter_issue_id: &Uuid,
+        body: &Body,
+    ) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/drafts/{}",
+                &self.address, newsletter_issue_id
+            ))
+            .form(&body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_draft_preview(&self, newsletter_issue_id: &Uuid) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/newsletters/drafts/{}/preview",
+                &self.address, newsletter_issue_id
+            ))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn post_draft_test_email<Body>(
+        &self,
+        newsletter_issue_id: &Uuid,
+        body: &Body,
+    ) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/drafts/{}/test",
+                &self.address, newsletter_issue_id
+            ))
+            .form(&body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn post_publish_draft<Body>(
+        &self,
+        newsletter_issue_id: &Uuid,
+        body: &Body,
+    ) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/drafts/{}/publish",
+                &self.address, newsletter_issue_id
+            ))
+            .form(&body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_public_issues_html(&self) -> String {
+        reqwest::get(format!("{}/issues", &self.address))
+            .await
+            .expect("failed to execute request")
+            .text()
+            .await
+            .expect("failed to get request text")
+    }
+
+    pub async fn get_public_issue(&self, slug: &str) -> reqwest::Response {
+        reqwest::get(format!("{}/issues/{}", &self.address, slug))
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn post_archive_visibility(
+        &self,
+        newsletter_issue_id: &Uuid,
+        in_public_archive: bool,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/{}/archive",
+                &self.address, newsletter_issue_id
+            ))
+            .form(&serde_json::json!({ "in_public_archive": in_public_archive }))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn post_engagement_tracking(
+        &self,
+        newsletter_issue_id: &Uuid,
+        track_engagement: bool,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/{}/tracking",
+                &self.address, newsletter_issue_id
+            ))
+            .form(&serde_json::json!({ "track_engagement": track_engagement }))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn post_reschedule_newsletter<Body>(
+        &self,
+        newsletter_issue_id: &Uuid,
+        body: &Body,
+    ) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/{}/schedule",
+                &self.address, newsletter_issue_id
+            ))
+            .form(&body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn post_cancel_newsletter(&self, newsletter_issue_id: &Uuid) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/{}/cancel",
+                &self.address, newsletter_issue_id
+            ))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
 }
 
 impl Drop for TestApp {
@@ -272,6 +786,11 @@ impl Drop for TestApp {
 }
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Spawn the application, tweaking its configuration with `customize` first.
+pub async fn spawn_app_with(customize: impl FnOnce(&mut Settings)) -> TestApp {
     Lazy::force(&TRACING);
 
     // Launch the mock server to stand in for Postmark's API
@@ -286,7 +805,9 @@ pub async fn spawn_app() -> TestApp {
         // Use a random OS port
         c.application.port = 0;
         // Use the mock server as email API
+        c.email_client.transport = EmailTransportKind::Postmark;
         c.email_client.base_url = email_server.uri();
+        customize(&mut c);
         c
     };
 
@@ -305,17 +826,22 @@ pub async fn spawn_app() -> TestApp {
         .build()
         .unwrap();
 
-    let _ = tokio::spawn(application.run_until_stopped());
+    let shutdown = CancellationToken::new();
+    let _ = tokio::spawn(application.run_until_stopped(shutdown.clone()));
 
     let test_app = TestApp {
         address,
         port,
         db_name,
         db_pool: get_connection_pool(&configuration.database),
-        email_client: configuration.email_client.client(),
+        email_client: configuration.email_client(),
         email_server,
+        base_url: configuration.application.base_url.clone(),
+        hmac_secret: configuration.application.hmac_secret.clone(),
         test_user: TestUser::generate(),
         api_client: client,
+        configuration,
+        shutdown,
     };
 
     test_app.test_user.store(&test_app.db_pool).await;
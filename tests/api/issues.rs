@@ -0,0 +1,148 @@
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+/// Publish an issue and return its id and slug.
+async fn publish_newsletter(app: &TestApp, hide_from_archive: bool) -> (Uuid, String) {
+    app.publish_newsletter_with(serde_json::json!({
+        "title": "Spring news",
+        "hide_from_archive": hide_from_archive,
+    }))
+    .await;
+
+    let r = sqlx::query!(r#"SELECT newsletter_issue_id, slug AS "slug!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    (r.newsletter_issue_id, r.slug)
+}
+
+#[tokio::test]
+async fn published_issues_are_in_the_public_archive() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let (_, slug) = publish_newsletter(&app, false).await;
+    assert!(slug.starts_with("spring-news-"));
+
+    let html_page = app.get_public_issues_html().await;
+    assert!(html_page.contains(&format!(r#"<a href="/issues/{}">Spring news</a>"#, slug)));
+
+    let response = app.get_public_issue(&slug).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("<h1>Spring news</h1>"));
+    assert!(html_page.contains("<p>Newsletter body as HTML</p>"));
+}
+
+#[tokio::test]
+async fn issues_can_be_kept_out_of_the_public_archive() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let (_, slug) = publish_newsletter(&app, true).await;
+
+    let html_page = app.get_public_issues_html().await;
+    assert!(!html_page.contains("Spring news"));
+
+    let response = app.get_public_issue(&slug).await;
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn drafts_are_not_in_the_public_archive() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.post_drafts(&serde_json::json!({
+        "title": "A draft",
+        "text": "Draft body as plain text",
+        "html": "<p>Draft body as HTML</p>",
+    }))
+    .await;
+
+    let html_page = app.get_public_issues_html().await;
+
+    assert!(!html_page.contains("A draft"));
+}
+
+#[tokio::test]
+async fn unknown_issues_are_a_404() {
+    let app = spawn_app().await;
+
+    let response = app.get_public_issue("not-an-issue").await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn an_issue_can_be_removed_from_the_public_archive_after_publishing() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let (issue_id, slug) = publish_newsletter(&app, false).await;
+
+    let response = app.post_archive_visibility(&issue_id, false).await;
+    assert_is_redirect_to(&response, &format!("/admin/newsletters/{}", issue_id));
+    assert_eq!(app.get_public_issue(&slug).await.status().as_u16(), 404);
+
+    app.post_archive_visibility(&issue_id, true).await;
+    assert_eq!(app.get_public_issue(&slug).await.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn emails_link_to_the_public_archive() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let (_, slug) = publish_newsletter(&app, false).await;
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let permalink = app.get_permalink(&email_request).unwrap();
+    assert_eq!(permalink.path(), format!("/issues/{}", slug));
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert!(body["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("<p><a href=\"http://127.0.0.1/issues/"));
+
+    let response = reqwest::get(permalink).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn emails_of_issues_outside_the_public_archive_have_no_permalink() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    publish_newsletter(&app, true).await;
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    assert!(app.get_permalink(&email_request).is_none());
+}
@@ -0,0 +1,239 @@
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::domain::SubscriberEmail;
+use zero2prod::email_client::{EmailClient, OutboxTransport};
+use zero2prod::issue_delivery_worker::try_execute_task;
+use zero2prod::suppression_list::mark_inactive_subscribers;
+
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+async fn set_consecutive_soft_bounces(app: &TestApp, n: i16) {
+    sqlx::query!("UPDATE subscriptions SET consecutive_soft_bounces = $1", n)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+}
+
+async fn run_list_hygiene(app: &TestApp) -> u64 {
+    mark_inactive_subscribers(
+        &app.db_pool,
+        app.configuration.delivery_worker.soft_bounce_threshold,
+    )
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn subscribers_below_the_soft_bounce_threshold_stay_confirmed() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    let threshold = app.configuration.delivery_worker.soft_bounce_threshold;
+    set_consecutive_soft_bounces(&app, threshold - 1).await;
+
+    assert_eq!(run_list_hygiene(&app).await, 0);
+    assert_eq!(app.subscriber_status().await, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribers_reaching_the_soft_bounce_threshold_become_inactive() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    let threshold = app.configuration.delivery_worker.soft_bounce_threshold;
+    set_consecutive_soft_bounces(&app, threshold).await;
+
+    assert_eq!(run_list_hygiene(&app).await, 1);
+    assert_eq!(app.subscriber_status().await, "inactive");
+}
+
+#[tokio::test]
+async fn inactive_subscribers_do_not_get_new_issues() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    set_consecutive_soft_bounces(
+        &app,
+        app.configuration.delivery_worker.soft_bounce_threshold,
+    )
+    .await;
+    run_list_hygiene(&app).await;
+    app.test_user.login(&app).await;
+
+    app.publish_newsletter().await;
+
+    let n_queued = sqlx::query!(r#"SELECT count(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_queued, 0);
+}
+
+#[tokio::test]
+async fn inactive_subscribers_come_back_by_confirming_again() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    set_consecutive_soft_bounces(
+        &app,
+        app.configuration.delivery_worker.soft_bounce_threshold,
+    )
+    .await;
+    run_list_hygiene(&app).await;
+    let subscriber = sqlx::query!("SELECT name, email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let body = serde_urlencoded::to_string(serde_json::json!({
+        "name": subscriber.name,
+        "email": subscriber.email,
+    }))
+    .unwrap();
+    app.post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+    assert_eq!(app.subscriber_status().await, "inactive");
+
+    // The first email asked them to confirm when they first subscribed
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let confirmation_links = app.get_confirmation_links(&email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let subscriber = sqlx::query!("SELECT status, consecutive_soft_bounces FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscriber.status, "confirmed");
+    assert_eq!(subscriber.consecutive_soft_bounces, 0);
+}
+
+/// Deliver an issue to the (only) subscriber with Postmark answering
+/// `response` every time, until the delivery is given up on.
+/// Returns their soft bounce count.
+async fn soft_bounces_after_giving_up(app: &TestApp, response: ResponseTemplate) -> i16 {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(response)
+        .mount(&app.email_server)
+        .await;
+
+    app.publish_newsletter().await;
+    loop {
+        app.dispatch_all_pending_emails().await;
+        if app.n_queued_deliveries().await == 0 {
+            break;
+        }
+        sqlx::query!("UPDATE issue_delivery_queue SET execute_after = now()")
+            .execute(&app.db_pool)
+            .await
+            .unwrap();
+    }
+
+    sqlx::query!("SELECT consecutive_soft_bounces FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .consecutive_soft_bounces
+}
+
+#[tokio::test]
+async fn recipients_rejected_until_the_last_retry_count_as_a_soft_bounce() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(&app).await;
+
+    let rejection = ResponseTemplate::new(422).set_body_json(serde_json::json!({
+        "ErrorCode": 406,
+        "Message": "You tried to send to a recipient that has been marked as inactive."
+    }));
+    let n = soft_bounces_after_giving_up(&app, rejection).await;
+
+    assert_eq!(n, 1);
+}
+
+#[tokio::test]
+async fn provider_failures_until_the_last_retry_are_not_a_soft_bounce() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(&app).await;
+    set_consecutive_soft_bounces(&app, 2).await;
+
+    let n = soft_bounces_after_giving_up(&app, ResponseTemplate::new(500)).await;
+
+    assert_eq!(n, 2);
+}
+
+#[tokio::test]
+async fn deliveries_through_a_transport_without_delivery_webhooks_reset_soft_bounces() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(&app).await;
+    set_consecutive_soft_bounces(&app, 2).await;
+    let sender = SubscriberEmail::parse(app.email_client.sender().as_ref().to_owned()).unwrap();
+    let outbox = OutboxTransport::new(std::env::temp_dir().join(Uuid::new_v4().to_string()));
+    let email_client = EmailClient::new(sender, outbox);
+
+    app.publish_newsletter().await;
+    try_execute_task(&app.db_pool, &email_client, &app.base_url, &app.hmac_secret)
+        .await
+        .unwrap();
+
+    let n = sqlx::query!("SELECT consecutive_soft_bounces FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .consecutive_soft_bounces;
+    assert_eq!(n, 0);
+}
+
+/// Deliver an issue through Postmark to the (only) subscriber, who had
+/// soft-bounced twice. Returns their soft bounce count afterwards.
+async fn soft_bounces_after_a_delivery(app: &TestApp) -> i16 {
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(app).await;
+    set_consecutive_soft_bounces(app, 2).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.publish_newsletter().await;
+    app.dispatch_all_pending_emails().await;
+
+    sqlx::query!("SELECT consecutive_soft_bounces FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .consecutive_soft_bounces
+}
+
+#[tokio::test]
+async fn deliveries_accepted_by_postmark_reset_soft_bounces_without_its_delivery_webhook() {
+    let app = spawn_app().await;
+
+    assert_eq!(soft_bounces_after_a_delivery(&app).await, 0);
+}
+
+#[tokio::test]
+async fn deliveries_accepted_by_postmark_leave_soft_bounces_to_its_delivery_webhook() {
+    let app = spawn_app_with(|c| c.postmark_webhook.delivery_events_enabled = true).await;
+
+    assert_eq!(soft_bounces_after_a_delivery(&app).await, 2);
+}
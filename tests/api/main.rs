@@ -1,8 +1,30 @@
+// Lints newer than the code they would flag
+#![allow(
+    clippy::let_underscore_future,
+    clippy::needless_borrows_for_generic_args
+)]
+
 mod admin_dashboard;
+mod admin_subscribers;
 mod change_password;
+mod consent;
+mod erasure;
+mod feeds;
 mod health_check;
 mod helpers;
+mod issues;
+mod list_hygiene;
 mod login;
 mod newsletter;
+mod newsletter_drafts;
+mod newsletter_history;
+mod newsletter_schedule;
+mod outbox;
+mod shutdown;
+mod subscriber_exports;
+mod subscriber_imports;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_unsubscribe;
+mod tracking;
+mod webhooks;
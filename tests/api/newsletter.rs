@@ -1,15 +1,25 @@
 use std::time::Duration;
 
-use crate::helpers::{assert_is_redirect_to, spawn_app};
+use crate::helpers::{assert_is_redirect_to, spawn_app, PostmarkBatchResponder, TestApp};
 
+use tokio::sync::Semaphore;
 use uuid::Uuid;
 use wiremock::matchers::{any, method, path};
 use wiremock::{Mock, MockBuilder, ResponseTemplate};
+use zero2prod::domain::SubscriberEmail;
+use zero2prod::email_client::{Email, EmailClient, EmailTransport};
+use zero2prod::issue_delivery_worker::{
+    run_worker_until_stopped, time_until_next_task, try_execute_tasks, ExecutionOutcome,
+};
 
 fn when_sending_an_email() -> MockBuilder {
     Mock::given(path("/email")).and(method("POST"))
 }
 
+fn when_sending_a_batch() -> MockBuilder {
+    Mock::given(path("/email/batch")).and(method("POST"))
+}
+
 #[tokio::test]
 async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
     let app = spawn_app().await;
@@ -170,3 +180,461 @@ async fn concurrent_form_submission_is_handled_gracefully() {
 
     app.dispatch_all_pending_emails().await;
 }
+
+/// Pretend the retry delay of every queued delivery has elapsed.
+async fn make_queued_deliveries_due(app: &TestApp) {
+    sqlx::query!("UPDATE issue_delivery_queue SET execute_after = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn transient_delivery_failures_are_retried_later() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(&app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.publish_newsletter().await;
+    app.dispatch_all_pending_emails().await;
+
+    // The failed delivery has been rescheduled, not dropped
+    let queued = sqlx::query!(
+        "SELECT n_retries, execute_after > now() AS \"in_the_future!\" FROM issue_delivery_queue"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(queued.n_retries, 1);
+    assert!(queued.in_the_future);
+
+    make_queued_deliveries_due(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(app.n_queued_deliveries().await, 0);
+}
+
+#[tokio::test]
+async fn deliveries_that_exhaust_their_retries_are_moved_to_the_failures_table() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(&app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    app.publish_newsletter().await;
+    let mut n_attempts = 0;
+    loop {
+        app.dispatch_all_pending_emails().await;
+        n_attempts += 1;
+        if app.n_queued_deliveries().await == 0 {
+            break;
+        }
+        assert!(n_attempts < 100, "the delivery is retried forever");
+        make_queued_deliveries_due(&app).await;
+    }
+
+    let failure = sqlx::query!("SELECT subscriber_email, n_retries FROM issue_delivery_failures")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(failure.n_retries as i32 + 1, n_attempts);
+
+    let html_page = app.get_delivery_failures_html().await;
+    assert!(html_page.contains(&failure.subscriber_email));
+}
+
+async fn get_newsletter_issue_id(app: &TestApp) -> Uuid {
+    sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id
+}
+
+#[tokio::test]
+async fn successful_deliveries_are_logged_with_the_provider_message_id() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(&app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "MessageID": "b7bc2f4a-e38e-4336-af7d-e6c392c2f817",
+            "ErrorCode": 0,
+            "Message": "OK"
+        })))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.publish_newsletter().await;
+    let delivery = sqlx::query!("SELECT status, n_attempts FROM issue_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(delivery.status, "pending");
+    assert_eq!(delivery.n_attempts, 0);
+
+    app.dispatch_all_pending_emails().await;
+
+    let delivery =
+        sqlx::query!("SELECT status, n_attempts, provider_message_id FROM issue_deliveries")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(delivery.status, "sent");
+    assert_eq!(delivery.n_attempts, 1);
+    assert_eq!(
+        delivery.provider_message_id.as_deref(),
+        Some("b7bc2f4a-e38e-4336-af7d-e6c392c2f817")
+    );
+}
+
+#[tokio::test]
+async fn the_issue_page_shows_delivery_progress_and_failing_recipients() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(&app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    app.publish_newsletter().await;
+    app.dispatch_all_pending_emails().await;
+
+    let subscriber_email = app.subscriber_email().await;
+    let newsletter_issue_id = get_newsletter_issue_id(&app).await;
+    let response = app.get_newsletter_issue(&newsletter_issue_id).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Sent: 0"));
+    assert!(html_page.contains("Pending: 1"));
+    assert!(html_page.contains(&subscriber_email));
+    assert!(html_page.contains("500 Internal Server Error"));
+}
+
+#[tokio::test]
+async fn the_issue_page_returns_a_404_for_unknown_issues() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app.get_newsletter_issue(&Uuid::new_v4()).await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_an_issue_page() {
+    let app = spawn_app().await;
+
+    let response = app.get_newsletter_issue(&Uuid::new_v4()).await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn the_delivery_worker_is_woken_up_as_soon_as_an_issue_is_published() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(&app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    tokio::spawn(run_worker_until_stopped(
+        app.configuration.clone(),
+        app.shutdown.clone(),
+    ));
+    // Let the worker find the queue empty and go to sleep
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    app.publish_newsletter().await;
+
+    let mut waited = Duration::ZERO;
+    while app.n_queued_deliveries().await > 0 {
+        assert!(
+            waited < Duration::from_secs(5),
+            "the worker did not pick up the new issue"
+        );
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        waited += Duration::from_millis(100);
+    }
+}
+
+#[tokio::test]
+async fn tasks_another_worker_is_executing_do_not_wake_the_worker_up() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(&app).await;
+    app.publish_newsletter().await;
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue SET execute_after = now() + interval '1 hour'
+        WHERE subscriber_email = (SELECT MIN(subscriber_email) FROM issue_delivery_queue)
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    // Another worker is busy with the task that is due
+    let mut transaction = app.db_pool.begin().await.unwrap();
+    sqlx::query!(
+        r#"
+        SELECT subscriber_email FROM issue_delivery_queue
+        WHERE execute_after <= now()
+        FOR UPDATE SKIP LOCKED
+        "#
+    )
+    .fetch_one(&mut transaction)
+    .await
+    .unwrap();
+
+    let delay = time_until_next_task(&app.db_pool).await.unwrap().unwrap();
+
+    assert!(delay > Duration::from_secs(59 * 60));
+    assert!(delay <= Duration::from_secs(60 * 60));
+    transaction.rollback().await.unwrap();
+}
+
+#[tokio::test]
+async fn deliveries_of_different_issues_are_sent_in_parallel() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(&app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(1)))
+        .expect(4)
+        .mount(&app.email_server)
+        .await;
+
+    for _ in 0..4 {
+        app.publish_newsletter().await;
+    }
+    let start = std::time::Instant::now();
+    let outcome = try_execute_tasks(
+        &app.db_pool,
+        &app.email_client,
+        &app.base_url,
+        &app.hmac_secret,
+        10,
+        &Semaphore::new(4),
+    )
+    .await
+    .unwrap();
+    let elapsed = start.elapsed();
+
+    assert!(matches!(outcome, ExecutionOutcome::TaskCompleted));
+    assert!(
+        elapsed < Duration::from_secs(3),
+        "the emails were sent sequentially ({elapsed:?})"
+    );
+    assert_eq!(app.n_queued_deliveries().await, 0);
+}
+
+/// A transport without a batch API, like SMTP, that takes its time.
+struct SlowTransport;
+
+#[async_trait::async_trait]
+impl EmailTransport for SlowTransport {
+    async fn send(&self, _email: &Email<'_>) -> Result<Option<String>, anyhow::Error> {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        Ok(None)
+    }
+}
+
+#[tokio::test]
+async fn deliveries_of_the_same_issue_are_sent_in_parallel_without_a_batch_api() {
+    let app = spawn_app().await;
+    for _ in 0..4 {
+        app.create_confirmed_subscriber().await;
+    }
+    app.test_user.login(&app).await;
+    app.publish_newsletter().await;
+    let sender = SubscriberEmail::parse(app.email_client.sender().as_ref().to_owned()).unwrap();
+    let email_client = EmailClient::new(sender, SlowTransport);
+
+    let start = std::time::Instant::now();
+    let outcome = try_execute_tasks(
+        &app.db_pool,
+        &email_client,
+        &app.base_url,
+        &app.hmac_secret,
+        10,
+        &Semaphore::new(4),
+    )
+    .await
+    .unwrap();
+    let elapsed = start.elapsed();
+
+    assert!(matches!(outcome, ExecutionOutcome::TaskCompleted));
+    assert!(
+        elapsed < Duration::from_secs(3),
+        "the emails were sent sequentially ({elapsed:?})"
+    );
+    assert_eq!(app.n_queued_deliveries().await, 0);
+}
+
+#[tokio::test]
+async fn deliveries_of_the_same_issue_are_sent_in_a_single_batch() {
+    let app = spawn_app().await;
+    for _ in 0..3 {
+        app.create_confirmed_subscriber().await;
+    }
+    app.test_user.login(&app).await;
+
+    when_sending_a_batch()
+        .respond_with(PostmarkBatchResponder {
+            reject_first: false,
+        })
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    app.publish_newsletter().await;
+    try_execute_tasks(
+        &app.db_pool,
+        &app.email_client,
+        &app.base_url,
+        &app.hmac_secret,
+        10,
+        &Semaphore::new(1),
+    )
+    .await
+    .unwrap();
+
+    let batch = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let emails: Vec<serde_json::Value> = serde_json::from_slice(&batch.body).unwrap();
+    assert_eq!(emails.len(), 3);
+    // Every subscriber gets their own unsubscribe link
+    let unsubscribe_links: std::collections::HashSet<_> = emails
+        .iter()
+        .map(|e| e["Headers"][0]["Value"].as_str().unwrap())
+        .collect();
+    assert_eq!(unsubscribe_links.len(), 3);
+
+    assert_eq!(app.n_queued_deliveries().await, 0);
+    let deliveries = sqlx::query!("SELECT status, provider_message_id FROM issue_deliveries")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(deliveries.len(), 3);
+    for delivery in deliveries {
+        assert_eq!(delivery.status, "sent");
+        assert!(delivery.provider_message_id.is_some());
+    }
+}
+
+#[tokio::test]
+async fn a_failure_within_a_batch_only_affects_its_own_delivery() {
+    let app = spawn_app().await;
+    for _ in 0..3 {
+        app.create_confirmed_subscriber().await;
+    }
+    app.test_user.login(&app).await;
+
+    when_sending_a_batch()
+        .respond_with(PostmarkBatchResponder { reject_first: true })
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.publish_newsletter().await;
+    try_execute_tasks(
+        &app.db_pool,
+        &app.email_client,
+        &app.base_url,
+        &app.hmac_secret,
+        10,
+        &Semaphore::new(1),
+    )
+    .await
+    .unwrap();
+
+    // The rejected delivery is retried later, the other ones are done
+    let retried = sqlx::query!("SELECT n_retries FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(retried.n_retries, 1);
+    let deliveries = sqlx::query!(
+        "SELECT status, count(*) AS \"count!\" FROM issue_deliveries GROUP BY status ORDER BY status"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(deliveries.len(), 2);
+    assert_eq!(
+        (deliveries[0].status.as_str(), deliveries[0].count),
+        ("pending", 1)
+    );
+    assert_eq!(
+        (deliveries[1].status.as_str(), deliveries[1].count),
+        ("sent", 2)
+    );
+}
+
+#[tokio::test]
+async fn a_batch_only_claims_up_to_batch_size_deliveries() {
+    let app = spawn_app().await;
+    for _ in 0..3 {
+        app.create_confirmed_subscriber().await;
+    }
+    app.test_user.login(&app).await;
+
+    when_sending_a_batch()
+        .respond_with(PostmarkBatchResponder {
+            reject_first: false,
+        })
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.publish_newsletter().await;
+    try_execute_tasks(
+        &app.db_pool,
+        &app.email_client,
+        &app.base_url,
+        &app.hmac_secret,
+        2,
+        &Semaphore::new(2),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(app.n_queued_deliveries().await, 1);
+}
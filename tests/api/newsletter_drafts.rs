@@ -0,0 +1,325 @@
+use htmlescape::{encode_attribute, encode_minimal};
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+/// Save a draft and return its id.
+async fn create_draft(app: &TestApp) -> Uuid {
+    let response = app
+        .post_drafts(&serde_json::json!({
+            "title": "Draft title",
+            "text": "Draft body as plain text",
+            "html": "<p>Draft body as HTML</p>",
+        }))
+        .await;
+    let newsletter_issue_id = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/newsletters/drafts/{}", newsletter_issue_id),
+    );
+    newsletter_issue_id
+}
+
+async fn get_issue_status(app: &TestApp) -> String {
+    sqlx::query!("SELECT status FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_save_a_draft() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_drafts(&serde_json::json!({
+            "title": "Draft title",
+            "text": "Draft body as plain text",
+            "html": "<p>Draft body as HTML</p>",
+        }))
+        .await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn drafts_are_not_delivered() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let issue_id = create_draft(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(get_issue_status(&app).await, "draft");
+    let html_page = app.get_newsletters_html().await;
+    assert!(html_page.contains(&format!(
+        r#"<a href="/admin/newsletters/drafts/{}">Draft title</a>"#,
+        issue_id
+    )));
+}
+
+#[tokio::test]
+async fn drafts_can_be_edited() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = create_draft(&app).await;
+
+    let response = app
+        .post_draft(
+            &issue_id,
+            &serde_json::json!({
+                "title": "A better title",
+                "text": "A better body",
+                "html": "<p>A better body</p>",
+            }),
+        )
+        .await;
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/newsletters/drafts/{}", issue_id),
+    );
+
+    let html_page = app.get_draft_html(&issue_id).await;
+    assert!(html_page.contains("<p><i>The draft has been saved.</i></p>"));
+    assert!(html_page.contains(&format!(
+        r#"value="{}""#,
+        encode_attribute("A better title")
+    )));
+    assert!(html_page.contains(&format!(
+        "{}</textarea>",
+        encode_minimal("<p>A better body</p>")
+    )));
+}
+
+#[tokio::test]
+async fn the_preview_shows_both_bodies() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = create_draft(&app).await;
+
+    let response = app.get_draft_preview(&issue_id).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let html_page = response.text().await.unwrap();
+    // The HTML body is rendered in a sandboxed frame, not inlined in the page
+    assert!(html_page.contains(&format!(
+        r#"<iframe sandbox srcdoc="{}""#,
+        encode_attribute("<p>Draft body as HTML</p>")
+    )));
+    assert!(html_page.contains("<pre>Draft body as plain text</pre>"));
+}
+
+#[tokio::test]
+async fn the_preview_of_a_missing_issue_is_a_404() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app.get_draft_preview(&Uuid::new_v4()).await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn a_test_email_is_sent_only_to_the_given_address() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(&app).await;
+    let issue_id = create_draft(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_draft_test_email(
+            &issue_id,
+            &serde_json::json!({ "email": "editor@example.com" }),
+        )
+        .await;
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/newsletters/drafts/{}", issue_id),
+    );
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], "editor@example.com");
+    assert_eq!(body["Subject"], "[Test] Draft title");
+    // Subscribers are left alone
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(get_issue_status(&app).await, "draft");
+    let html_page = app.get_draft_html(&issue_id).await;
+    assert!(html_page.contains("A test email has been sent to editor@example.com."));
+}
+
+#[tokio::test]
+async fn a_test_email_looks_like_what_subscribers_will_get() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.post_drafts(&serde_json::json!({
+        "title": "Draft title",
+        "text": "Draft body as plain text",
+        "html": r#"<p>Read <a href="https://example.com/">this</a></p>"#,
+    }))
+    .await;
+    let issue_id = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_draft_test_email(
+        &issue_id,
+        &serde_json::json!({ "email": "editor@example.com" }),
+    )
+    .await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let html_body = body["HtmlBody"].as_str().unwrap();
+    assert!(html_body.contains("View this email in your browser"));
+    assert!(html_body.contains("/tracking/click?"));
+    assert!(!html_body.contains(r#"href="https://example.com/""#));
+    assert!(body["TextBody"].as_str().unwrap().contains("Unsubscribe: "));
+    assert!(html_body.contains("/subscriptions/unsubscribe?"));
+    let headers = body["Headers"].as_array().unwrap();
+    assert!(headers.iter().any(|h| h["Name"] == "List-Unsubscribe"));
+}
+
+#[tokio::test]
+async fn a_test_email_to_an_invalid_address_is_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = create_draft(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_draft_test_email(&issue_id, &serde_json::json!({ "email": "not-an-email" }))
+        .await;
+
+    let html_page = app.get_draft_html(&issue_id).await;
+    assert!(html_page.contains("<p><i>The test email address is not valid.</i></p>"));
+}
+
+#[tokio::test]
+async fn publishing_a_draft_delivers_it_to_subscribers() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(&app).await;
+    let issue_id = create_draft(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_publish_draft(
+            &issue_id,
+            &serde_json::json!({ "idempotency_key": Uuid::new_v4().to_string() }),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    let html_page = app.get_newsletters_html().await;
+    assert!(html_page.contains("The newsletter issue has been accepted"));
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(get_issue_status(&app).await, "published");
+}
+
+#[tokio::test]
+async fn publishing_a_draft_is_idempotent() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(&app).await;
+    let issue_id = create_draft(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let body = serde_json::json!({ "idempotency_key": Uuid::new_v4().to_string() });
+    let response = app.post_publish_draft(&issue_id, &body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    let response = app.post_publish_draft(&issue_id, &body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn a_published_draft_can_no_longer_be_edited_or_published() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = create_draft(&app).await;
+    app.post_publish_draft(
+        &issue_id,
+        &serde_json::json!({ "idempotency_key": Uuid::new_v4().to_string() }),
+    )
+    .await;
+
+    let response = app
+        .post_draft(
+            &issue_id,
+            &serde_json::json!({
+                "title": "A better title",
+                "text": "A better body",
+                "html": "<p>A better body</p>",
+            }),
+        )
+        .await;
+    assert_is_redirect_to(&response, &format!("/admin/newsletters/{}", issue_id));
+
+    let response = app
+        .post_publish_draft(
+            &issue_id,
+            &serde_json::json!({ "idempotency_key": Uuid::new_v4().to_string() }),
+        )
+        .await;
+    assert_is_redirect_to(&response, &format!("/admin/newsletters/{}", issue_id));
+
+    let title = sqlx::query!("SELECT title FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .title;
+    assert_eq!(title, "Draft title");
+}
@@ -0,0 +1,148 @@
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+/// Insert `n` published issues straight into the database.
+async fn insert_published_issues(app: &TestApp, n: usize) {
+    for i in 0..n {
+        sqlx::query!(
+            r#"
+            INSERT INTO newsletter_issues (
+                newsletter_issue_id,
+                title,
+                text_content,
+                html_content,
+                status,
+                published_at
+            )
+            VALUES ($1, $2, 'text', '<p>html</p>', 'published', now() - make_interval(mins => $3))
+            "#,
+            Uuid::new_v4(),
+            format!("Issue #{}", i),
+            i as i32
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    }
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_past_issues() {
+    let app = spawn_app().await;
+
+    let response = app.get_newsletter_history("").await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn past_issues_are_listed_with_their_author_and_deliveries() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.publish_newsletter_with(serde_json::json!({"title": "Our first issue"}))
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let html_page = app.get_newsletter_history_html("").await;
+    assert!(html_page.contains("Our first issue"));
+    assert!(html_page.contains(&format!("<td>{}</td>", app.test_user.username)));
+    // Sent, pending, failed
+    assert!(html_page.contains("<td>1</td><td>0</td><td>0</td>"));
+}
+
+#[tokio::test]
+async fn drafts_and_scheduled_issues_are_not_past_issues() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    app.post_drafts(&serde_json::json!({
+        "title": "A draft",
+        "text": "Draft body as plain text",
+        "html": "<p>Draft body as HTML</p>",
+    }))
+    .await;
+    app.post_newsletters(&serde_json::json!({
+        "title": "A scheduled issue",
+        "text": "Newsletter body as plain text",
+        "html": "<p>Newsletter body as HTML</p>",
+        "send_at": "2099-01-01T00:00",
+        "idempotency_key": Uuid::new_v4().to_string(),
+    }))
+    .await;
+
+    let html_page = app.get_newsletter_history_html("").await;
+    assert!(!html_page.contains("A draft"));
+    assert!(!html_page.contains("A scheduled issue"));
+}
+
+#[tokio::test]
+async fn past_issues_can_be_searched_by_title() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.publish_newsletter_with(serde_json::json!({"title": "Spring news"}))
+        .await;
+    app.publish_newsletter_with(serde_json::json!({"title": "Summer news"}))
+        .await;
+
+    let html_page = app.get_newsletter_history_html("q=SPRING").await;
+
+    assert!(html_page.contains("Spring news"));
+    assert!(!html_page.contains("Summer news"));
+}
+
+#[tokio::test]
+async fn past_issues_are_paginated() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    insert_published_issues(&app, 25).await;
+
+    let html_page = app.get_newsletter_history_html("").await;
+    assert!(html_page.contains("Page 1 of 2"));
+    assert!(html_page.contains("Issue #0<"));
+    assert!(html_page.contains("Issue #19<"));
+    assert!(!html_page.contains("Issue #20<"));
+    assert!(html_page.contains(r#"<a href="/admin/newsletters/history?q=&page=2">Next</a>"#));
+
+    let html_page = app.get_newsletter_history_html("page=2").await;
+    assert!(html_page.contains("Page 2 of 2"));
+    assert!(!html_page.contains("Issue #19<"));
+    assert!(html_page.contains("Issue #24<"));
+}
+
+#[tokio::test]
+async fn the_issue_page_renders_the_stored_content() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.publish_newsletter_with(serde_json::json!({"title": "Our first issue"}))
+        .await;
+    let issue_id = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+
+    let html_page = app
+        .get_newsletter_issue(&issue_id)
+        .await
+        .text()
+        .await
+        .unwrap();
+
+    assert!(html_page.contains(&format!("by {}.", app.test_user.username)));
+    assert!(html_page.contains(&format!(
+        r#"<iframe sandbox srcdoc="{}""#,
+        htmlescape::encode_attribute("<p>Newsletter body as HTML</p>")
+    )));
+    assert!(html_page.contains("<pre>Newsletter body as plain text</pre>"));
+}
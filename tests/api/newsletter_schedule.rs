@@ -0,0 +1,273 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::issue_delivery_worker::{publish_due_issues, run_worker_until_stopped};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+async fn schedule_newsletter(app: &TestApp, send_at: DateTime<Utc>) -> Uuid {
+    let response = app
+        .post_newsletters(&serde_json::json!({
+            "title": "Newsletter title",
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+            "send_at": send_at.to_rfc3339(),
+            "idempotency_key": Uuid::new_v4().to_string(),
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id
+}
+
+fn in_one_hour() -> DateTime<Utc> {
+    Utc::now() + chrono::Duration::hours(1)
+}
+
+/// Pretend the `send_at` of every scheduled issue has come.
+async fn make_scheduled_issues_due(app: &TestApp) {
+    sqlx::query!("UPDATE newsletter_issues SET send_at = now() WHERE status = 'scheduled'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+}
+
+async fn get_issue_status(app: &TestApp) -> (String, Option<DateTime<Utc>>) {
+    let r = sqlx::query!("SELECT status, send_at FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    (r.status, r.send_at)
+}
+
+#[tokio::test]
+async fn scheduled_issues_are_not_delivered_before_send_at() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    schedule_newsletter(&app, in_one_hour()).await;
+    let html_page = app.get_newsletters_html().await;
+    assert!(html_page.contains("The newsletter issue has been scheduled"));
+
+    assert_eq!(
+        publish_due_issues(&app.db_pool, &app.hmac_secret)
+            .await
+            .unwrap(),
+        0
+    );
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(get_issue_status(&app).await.0, "scheduled");
+}
+
+#[tokio::test]
+async fn scheduled_issues_are_delivered_once_send_at_has_come() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    schedule_newsletter(&app, in_one_hour()).await;
+    make_scheduled_issues_due(&app).await;
+
+    assert_eq!(
+        publish_due_issues(&app.db_pool, &app.hmac_secret)
+            .await
+            .unwrap(),
+        1
+    );
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(get_issue_status(&app).await.0, "published");
+    // Publishing happens only once
+    assert_eq!(
+        publish_due_issues(&app.db_pool, &app.hmac_secret)
+            .await
+            .unwrap(),
+        0
+    );
+}
+
+#[tokio::test]
+async fn issues_scheduled_in_the_past_are_sent_straight_away() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    schedule_newsletter(&app, Utc::now() - chrono::Duration::hours(1)).await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(get_issue_status(&app).await.0, "published");
+}
+
+#[tokio::test]
+async fn an_invalid_send_at_is_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_newsletters(&serde_json::json!({
+            "title": "Newsletter title",
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+            "send_at": "next tuesday",
+            "idempotency_key": Uuid::new_v4().to_string(),
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    let html_page = app.get_newsletters_html().await;
+    assert!(html_page.contains("<p><i>The send time is not a valid date and time.</i></p>"));
+    let n_issues = sqlx::query!("SELECT count(*) AS \"count!\" FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_issues, 0);
+}
+
+#[tokio::test]
+async fn a_scheduled_issue_can_be_rescheduled() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = schedule_newsletter(&app, in_one_hour()).await;
+
+    let response = app
+        .post_reschedule_newsletter(
+            &issue_id,
+            &serde_json::json!({ "send_at": "2099-01-02T03:04" }),
+        )
+        .await;
+    assert_is_redirect_to(&response, &format!("/admin/newsletters/{}", issue_id));
+
+    let (status, send_at) = get_issue_status(&app).await;
+    assert_eq!(status, "scheduled");
+    assert_eq!(send_at.unwrap().to_rfc3339(), "2099-01-02T03:04:00+00:00");
+    let html_page = app
+        .get_newsletter_issue(&issue_id)
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("The issue has been rescheduled"));
+}
+
+#[tokio::test]
+async fn a_cancelled_issue_is_never_delivered() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let issue_id = schedule_newsletter(&app, in_one_hour()).await;
+    let response = app.post_cancel_newsletter(&issue_id).await;
+    assert_is_redirect_to(&response, &format!("/admin/newsletters/{}", issue_id));
+
+    make_scheduled_issues_due(&app).await;
+    assert_eq!(
+        publish_due_issues(&app.db_pool, &app.hmac_secret)
+            .await
+            .unwrap(),
+        0
+    );
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(get_issue_status(&app).await.0, "cancelled");
+}
+
+#[tokio::test]
+async fn issues_that_went_out_cannot_be_rescheduled_or_cancelled() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = schedule_newsletter(&app, in_one_hour()).await;
+    make_scheduled_issues_due(&app).await;
+    publish_due_issues(&app.db_pool, &app.hmac_secret)
+        .await
+        .unwrap();
+
+    app.post_cancel_newsletter(&issue_id).await;
+    let html_page = app
+        .get_newsletter_issue(&issue_id)
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("The issue is not scheduled anymore"));
+
+    app.post_reschedule_newsletter(
+        &issue_id,
+        &serde_json::json!({ "send_at": "2099-01-02T03:04" }),
+    )
+    .await;
+    let html_page = app
+        .get_newsletter_issue(&issue_id)
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("The issue is not scheduled anymore"));
+    assert_eq!(get_issue_status(&app).await.0, "published");
+}
+
+#[tokio::test]
+async fn the_worker_wakes_up_when_a_scheduled_issue_is_due() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    tokio::spawn(run_worker_until_stopped(
+        app.configuration.clone(),
+        app.shutdown.clone(),
+    ));
+    // Let the worker find the queue empty and go to sleep
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    schedule_newsletter(&app, Utc::now() + chrono::Duration::seconds(2)).await;
+
+    // Published, then delivered: the email mock checks it went out
+    let mut waited = Duration::ZERO;
+    while get_issue_status(&app).await.0 != "published" || app.n_queued_deliveries().await > 0 {
+        assert!(
+            waited < Duration::from_secs(10),
+            "the scheduled issue was not delivered"
+        );
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        waited += Duration::from_millis(100);
+    }
+}
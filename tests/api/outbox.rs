@@ -0,0 +1,85 @@
+use std::path::PathBuf;
+
+use uuid::Uuid;
+use zero2prod::configuration::{EmailTransportKind, Environment, OutboxSettings};
+
+use crate::helpers::{spawn_app_with, TestApp};
+
+async fn spawn_app_with_outbox(environment: Environment) -> (TestApp, PathBuf) {
+    let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+    let outbox_directory = directory.to_str().unwrap().to_owned();
+    let app = spawn_app_with(|c| {
+        c.environment = environment;
+        c.email_client.transport = EmailTransportKind::Outbox;
+        c.email_client.outbox = Some(OutboxSettings {
+            directory: outbox_directory,
+        });
+    })
+    .await;
+    (app, directory)
+}
+
+#[tokio::test]
+async fn emails_are_written_to_the_outbox_and_can_be_browsed() {
+    let (app, directory) = spawn_app_with_outbox(Environment::Local).await;
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let entry = std::fs::read_dir(&directory)
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap();
+    let email: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(entry.path()).unwrap()).unwrap();
+    assert_eq!(email["to"], "ursula_le_guin@gmail.com");
+    assert_eq!(email["subject"], "Welcome!");
+
+    app.test_user.login(&app).await;
+    assert!(app
+        .get_admin_dashboard_html()
+        .await
+        .contains(r#"<a href="/admin/dev/outbox">Outbox</a>"#));
+    let html_page = app.get_outbox().await.text().await.unwrap();
+    let email_id = email["id"].as_str().unwrap();
+    assert!(html_page.contains(&format!(
+        r#"<a href="/admin/dev/outbox/{}">Welcome!</a>"#,
+        email_id
+    )));
+
+    let html_page = app.get_outbox_email(email_id).await.text().await.unwrap();
+    let confirmation_link = linkify::LinkFinder::new()
+        .links(email["text_content"].as_str().unwrap())
+        .next()
+        .unwrap();
+    assert!(html_page.contains(&format!(
+        r#"<a href="{}">"#,
+        htmlescape::encode_attribute(confirmation_link.as_str())
+    )));
+
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[tokio::test]
+async fn unknown_outbox_emails_are_a_404() {
+    let (app, _) = spawn_app_with_outbox(Environment::Local).await;
+    app.test_user.login(&app).await;
+
+    let response = app.get_outbox_email(&Uuid::new_v4().to_string()).await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn the_outbox_can_only_be_browsed_in_the_local_environment() {
+    let (app, _) = spawn_app_with_outbox(Environment::Production).await;
+    app.test_user.login(&app).await;
+
+    let response = app.get_outbox().await;
+
+    assert_eq!(response.status().as_u16(), 404);
+    assert!(!app.get_admin_dashboard_html().await.contains("Outbox"));
+}
@@ -0,0 +1,120 @@
+use std::time::Duration;
+
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::issue_delivery_worker::run_worker_until_stopped;
+
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn the_server_stops_accepting_connections_once_shutdown_is_requested() {
+    let app = spawn_app().await;
+    let health_check = format!("{}/health_check", app.address);
+    reqwest::get(&health_check)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    app.shutdown.cancel();
+
+    let mut waited = Duration::ZERO;
+    while reqwest::get(&health_check).await.is_ok() {
+        assert!(
+            waited < Duration::from_secs(5),
+            "the server kept accepting connections"
+        );
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        waited += Duration::from_millis(100);
+    }
+}
+
+#[tokio::test]
+async fn the_delivery_worker_exits_once_shutdown_is_requested() {
+    let app = spawn_app().await;
+    let worker = tokio::spawn(run_worker_until_stopped(
+        app.configuration.clone(),
+        app.shutdown.clone(),
+    ));
+    // Let the worker find the queue empty and go to sleep
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    app.shutdown.cancel();
+
+    let outcome = tokio::time::timeout(Duration::from_secs(5), worker)
+        .await
+        .expect("the worker did not exit");
+    assert!(outcome.unwrap().is_ok());
+}
+
+#[tokio::test]
+async fn the_delivery_worker_completes_the_batch_in_progress_before_exiting() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(1)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.test_user.login(&app).await;
+    app.publish_newsletter().await;
+
+    let worker = tokio::spawn(run_worker_until_stopped(
+        app.configuration.clone(),
+        app.shutdown.clone(),
+    ));
+    // Shut down while the email is being sent
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    app.shutdown.cancel();
+
+    tokio::time::timeout(Duration::from_secs(5), worker)
+        .await
+        .expect("the worker did not exit")
+        .unwrap()
+        .unwrap();
+    assert_eq!(app.n_queued_deliveries().await, 0);
+    let delivery = sqlx::query!("SELECT status FROM issue_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(delivery.status, "sent");
+}
+
+#[tokio::test]
+async fn a_batch_that_outlives_the_shutdown_deadline_is_rolled_back() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(10)))
+        .mount(&app.email_server)
+        .await;
+    app.test_user.login(&app).await;
+    app.publish_newsletter().await;
+
+    let mut configuration = app.configuration.clone();
+    configuration.application.shutdown_timeout_seconds = 0;
+    let worker = tokio::spawn(run_worker_until_stopped(
+        configuration,
+        app.shutdown.clone(),
+    ));
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    app.shutdown.cancel();
+
+    tokio::time::timeout(Duration::from_secs(5), worker)
+        .await
+        .expect("the worker did not give up on the batch")
+        .unwrap()
+        .unwrap();
+    // The task is back in the queue, untouched
+    assert_eq!(app.n_queued_deliveries().await, 1);
+    let delivery = sqlx::query!("SELECT status, n_attempts FROM issue_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(delivery.status, "pending");
+    assert_eq!(delivery.n_attempts, 0);
+}
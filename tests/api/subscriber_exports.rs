@@ -0,0 +1,179 @@
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+async fn insert_confirmed_subscribers(app: &TestApp, n: i32) {
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        SELECT gen_random_uuid(), 'subscriber' || i || '@example.com', 'subscriber ' || i, now(), 'confirmed'
+        FROM generate_series(1, $1) AS i
+        "#,
+        n
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_export_subscribers() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    let email = app.subscriber_email().await;
+
+    let response = app.get_subscribers_export("csv").await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app.get_data_subject_export(&email).await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn subscribers_are_exported_as_csv() {
+    let app = spawn_app().await;
+    app.create_unconfirmed_subscriber().await;
+    let pending_email = app.subscriber_email().await;
+    insert_confirmed_subscribers(&app, 2500).await;
+    app.test_user.login(&app).await;
+
+    let response = app.get_subscribers_export("csv").await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/csv; charset=utf-8"
+    );
+    assert!(response.headers()["Content-Disposition"]
+        .to_str()
+        .unwrap()
+        .starts_with("attachment"));
+    let csv = response.text().await.unwrap();
+    let lines: Vec<_> = csv.lines().collect();
+    assert_eq!(lines[0], "id,email,name,status,subscribed_at,confirmed_at");
+    assert_eq!(
+        lines.len(),
+        1 + 2501,
+        "one header and every subscriber once"
+    );
+    let pending = lines.iter().find(|l| l.contains(&pending_email)).unwrap();
+    assert!(pending.contains(",pending_confirmation,"));
+    assert!(
+        pending.ends_with(','),
+        "pending subscribers were never confirmed"
+    );
+    let confirmed = lines
+        .iter()
+        .find(|l| l.contains(",subscriber42@example.com,"))
+        .unwrap();
+    assert!(confirmed.contains(",confirmed,"));
+    assert!(!confirmed.ends_with(','));
+}
+
+#[tokio::test]
+async fn subscribers_are_exported_as_json() {
+    let app = spawn_app().await;
+    insert_confirmed_subscribers(&app, 1000).await;
+    app.test_user.login(&app).await;
+
+    let response = app.get_subscribers_export("json").await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let subscribers: Vec<serde_json::Value> = response.json().await.unwrap();
+    assert_eq!(subscribers.len(), 1000);
+    assert_eq!(subscribers[0]["status"], "confirmed");
+    assert!(subscribers[0]["confirmed_at"].is_string());
+    assert!(subscribers[0]["id"]
+        .as_str()
+        .unwrap()
+        .parse::<Uuid>()
+        .is_ok());
+}
+
+#[tokio::test]
+async fn an_empty_list_is_a_valid_export() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app.get_subscribers_export("json").await;
+    let subscribers: Vec<serde_json::Value> = response.json().await.unwrap();
+    assert!(subscribers.is_empty());
+}
+
+#[tokio::test]
+async fn the_data_subject_export_bundles_everything_about_an_address() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    let email = app.subscriber_email().await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text": "Newsletter body as plain text",
+        "html": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": Uuid::new_v4().to_string(),
+    });
+    app.post_newsletters(&newsletter_request_body).await;
+    app.dispatch_all_pending_emails().await;
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_engagement_events (id, newsletter_issue_id, subscriber_id, kind, occurred_at)
+        SELECT gen_random_uuid(), newsletter_issue_id, subscriptions.id, 'open', now()
+        FROM newsletter_issues, subscriptions
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let response = app.get_data_subject_export(&email.to_uppercase()).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let export: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(export["subscription"]["email"], email.as_str());
+    assert_eq!(export["subscription"]["status"], "confirmed");
+    let history = export["status_history"].as_array().unwrap();
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[1]["status"], "confirmed");
+    assert_eq!(export["consent_events"].as_array().unwrap().len(), 2);
+    assert!(
+        export["confirmation_tokens"].as_array().unwrap().is_empty(),
+        "confirming uses up the tokens"
+    );
+    let deliveries = export["deliveries"].as_array().unwrap();
+    assert_eq!(deliveries.len(), 1);
+    assert_eq!(deliveries[0]["issue_title"], "Newsletter title");
+    assert_eq!(deliveries[0]["status"], "sent");
+    let engagement_events = export["engagement_events"].as_array().unwrap();
+    assert_eq!(engagement_events.len(), 1);
+    assert_eq!(engagement_events[0]["kind"], "open");
+    assert!(export["suppression"].is_null());
+}
+
+#[tokio::test]
+async fn addresses_we_no_longer_have_a_subscription_for_can_be_exported() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    sqlx::query!(
+        r#"
+        INSERT INTO suppressed_emails (email, reason, suppressed_at)
+        VALUES ('ursula@example.com', 'spam_complaint', now())
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let response = app.get_data_subject_export("ursula@example.com").await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let export: serde_json::Value = response.json().await.unwrap();
+    assert!(export["subscription"].is_null());
+    assert!(export["status_history"].as_array().unwrap().is_empty());
+    assert_eq!(export["suppression"]["reason"], "spam_complaint");
+}
@@ -0,0 +1,244 @@
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, PostmarkBatchResponder, TestApp};
+
+/// The id of the import the upload redirected to.
+fn import_id(response: &reqwest::Response) -> String {
+    assert_eq!(response.status().as_u16(), 303);
+    let location = response
+        .headers()
+        .get("Location")
+        .unwrap()
+        .to_str()
+        .unwrap();
+    location
+        .strip_prefix("/admin/subscribers/imports/")
+        .unwrap()
+        .to_owned()
+}
+
+async fn subscribers(app: &TestApp) -> Vec<(String, String, String)> {
+    sqlx::query!("SELECT email, name, status FROM subscriptions ORDER BY email")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| (r.email, r.name, r.status))
+        .collect()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_import_subscribers() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscriber_import("confirmed", "email,name\nursula@example.com,le guin\n")
+        .await;
+
+    assert_is_redirect_to(&response, "/login");
+    assert!(subscribers(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn valid_lines_are_imported_as_confirmed() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriber_import(
+            "confirmed",
+            "Name,Email,Notes\nle guin,ursula@example.com,\n butler , octavia@example.com ,met at a con\n",
+        )
+        .await;
+    let import_id = import_id(&response);
+
+    assert_eq!(
+        subscribers(&app).await,
+        vec![
+            (
+                "octavia@example.com".into(),
+                "butler".into(),
+                "confirmed".into()
+            ),
+            (
+                "ursula@example.com".into(),
+                "le guin".into(),
+                "confirmed".into()
+            ),
+        ]
+    );
+    let html_page = app.get_subscriber_import_html(&import_id).await;
+    assert!(html_page
+        .contains("<p><i>2 subscribers have been imported, 0 lines were rejected.</i></p>"));
+    assert!(html_page.contains("<li>Imported as confirmed: 2</li>"));
+}
+
+#[tokio::test]
+async fn pending_imports_are_sent_a_confirmation_email() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder { reject_first: true })
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriber_import(
+            "pending_confirmation",
+            "email,name\nursula@example.com,le guin\noctavia@example.com,butler\n",
+        )
+        .await;
+    let import_id = import_id(&response);
+    // The emails are left to the delivery worker
+    assert!(app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .is_empty());
+    let html_page = app.get_subscriber_import_html(&import_id).await;
+    assert!(html_page.contains("<li>Confirmation emails waiting to be sent: 2</li>"));
+
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let emails: Vec<serde_json::Value> = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(emails.len(), 2);
+    let n_tokens = sqlx::query!(r#"SELECT count(*) AS "count!" FROM subscription_tokens"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_tokens, 2);
+    assert!(subscribers(&app)
+        .await
+        .iter()
+        .all(|(_, _, status)| status == "pending_confirmation"));
+    let html_page = app.get_subscriber_import_html(&import_id).await;
+    assert!(html_page.contains("<li>Confirmation emails that could not be sent: 1</li>"));
+}
+
+#[tokio::test]
+async fn rejected_lines_can_be_downloaded_with_their_reason() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.create_confirmed_subscriber().await;
+    let existing_email = subscribers(&app).await.pop().unwrap().0;
+
+    let csv = format!(
+        "email,name\n\
+        ursula@example.com,le guin\n\
+        not-an-email,someone\n\
+        octavia@example.com,\n\
+        URSULA@example.com,ursula again\n\
+        {},already there\n",
+        existing_email.to_uppercase()
+    );
+    let response = app.post_subscriber_import("confirmed", &csv).await;
+    let import_id = import_id(&response);
+
+    assert_eq!(subscribers(&app).await.len(), 2);
+    let response = app.get_subscriber_import_rejections(&import_id).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers()["Content-Disposition"]
+        .to_str()
+        .unwrap()
+        .starts_with("attachment"));
+    let report = response.text().await.unwrap();
+    let lines: Vec<_> = report.lines().collect();
+    assert_eq!(lines[0], "line,email,name,reason");
+    assert!(lines[1].starts_with("3,not-an-email,someone,"));
+    assert!(lines[1].contains("is not a valid email"));
+    assert!(lines[2].starts_with("4,octavia@example.com,,"));
+    assert_eq!(
+        lines[3],
+        "5,URSULA@example.com,ursula again,duplicate of line 2"
+    );
+    assert!(lines[4].ends_with(",already there,already subscribed"));
+    assert_eq!(lines.len(), 5);
+}
+
+#[tokio::test]
+async fn files_without_an_email_and_a_name_column_are_not_imported() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_subscriber_import(
+            "confirmed",
+            "address,full name\nursula@example.com,le guin\n",
+        )
+        .await;
+
+    assert_is_redirect_to(&response, "/admin/subscribers/import");
+    assert!(subscribers(&app).await.is_empty());
+    let n_imports = sqlx::query!(r#"SELECT count(*) AS "count!" FROM subscriber_imports"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_imports, 0);
+}
+
+/// Larger than the pipe the upload goes through, see `import_file`.
+fn large_csv(header: &str) -> String {
+    let mut csv = format!("{}\n", header);
+    for i in 0..5_000 {
+        csv.push_str(&format!("reader{}@example.com,reader number {}\n", i, i));
+    }
+    csv.push_str("not-an-email,someone\n");
+    assert!(csv.len() > 64 * 1024);
+    csv
+}
+
+#[tokio::test]
+async fn large_files_without_an_email_and_a_name_column_are_not_imported() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_subscriber_import("confirmed", &large_csv("address,full name"))
+        .await;
+
+    assert_is_redirect_to(&response, "/admin/subscribers/import");
+    assert!(subscribers(&app).await.is_empty());
+    let html_page = app.get_import_subscribers_html().await;
+    assert!(html_page.contains("The file could not be imported"));
+}
+
+#[tokio::test]
+async fn large_files_are_imported_with_a_report_of_their_rejected_lines() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_subscriber_import("confirmed", &large_csv("email,name"))
+        .await;
+    let import_id = import_id(&response);
+
+    assert_eq!(subscribers(&app).await.len(), 5_000);
+    let html_page = app.get_subscriber_import_html(&import_id).await;
+    assert!(html_page
+        .contains("<p><i>5000 subscribers have been imported, 1 lines were rejected.</i></p>"));
+}
+
+#[tokio::test]
+async fn the_status_of_imported_subscribers_must_be_chosen() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_subscriber_import("unsubscribed", "email,name\nursula@example.com,le guin\n")
+        .await;
+
+    assert_is_redirect_to(&response, "/admin/subscribers/import");
+    assert!(subscribers(&app).await.is_empty());
+}
@@ -0,0 +1,237 @@
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{spawn_app, TestApp};
+
+/// Publish a newsletter issue, deliver it and return the request that was
+/// sent to the email API for the (only) confirmed subscriber.
+async fn deliver_newsletter(app: &TestApp) -> wiremock::Request {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_newsletters(&serde_json::json!({
+        "title": "Newsletter title",
+        "text": "Newsletter body as plain text",
+        "html": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": Uuid::new_v4().to_string(),
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap()
+}
+
+async fn deliver_newsletter_and_get_unsubscribe_link(app: &TestApp) -> reqwest::Url {
+    let email_request = deliver_newsletter(app).await;
+    app.get_unsubscribe_link(&email_request)
+}
+
+/// Extract the value of a custom header from a request to the email API.
+fn get_email_header(email_request: &wiremock::Request, name: &str) -> Option<String> {
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    body["Headers"]
+        .as_array()?
+        .iter()
+        .find(|h| h["Name"] == name)
+        .map(|h| h["Value"].as_str().unwrap().to_owned())
+}
+
+#[tokio::test]
+async fn the_unsubscribe_link_in_a_newsletter_returns_a_200_if_called() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(&app).await;
+
+    let unsubscribe_link = deliver_newsletter_and_get_unsubscribe_link(&app).await;
+    let response = reqwest::get(unsubscribe_link).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains("Unsubscribe"));
+}
+
+#[tokio::test]
+async fn following_the_unsubscribe_link_does_not_unsubscribe_without_confirmation() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(&app).await;
+
+    let unsubscribe_link = deliver_newsletter_and_get_unsubscribe_link(&app).await;
+    reqwest::get(unsubscribe_link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn confirming_the_unsubscription_stops_further_deliveries() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(&app).await;
+
+    let unsubscribe_link = deliver_newsletter_and_get_unsubscribe_link(&app).await;
+    let response = app.api_client.post(unsubscribe_link).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "unsubscribed");
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(&serde_json::json!({
+        "title": "Another newsletter title",
+        "text": "Newsletter body as plain text",
+        "html": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": Uuid::new_v4().to_string(),
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn unsubscribe_links_with_a_tampered_token_are_rejected_with_a_401() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(&app).await;
+
+    let mut unsubscribe_link = deliver_newsletter_and_get_unsubscribe_link(&app).await;
+    let subscriber_id: String = unsubscribe_link
+        .query_pairs()
+        .find(|(k, _)| k == "subscriber_id")
+        .unwrap()
+        .1
+        .into_owned();
+    unsubscribe_link
+        .query_pairs_mut()
+        .clear()
+        .append_pair("subscriber_id", &subscriber_id)
+        .append_pair("token", &"ab".repeat(32));
+
+    let response = reqwest::get(unsubscribe_link.clone()).await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+    let response = app.api_client.post(unsubscribe_link).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn unsubscribe_requests_without_a_token_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/subscriptions/unsubscribe", app.address))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn newsletter_issues_carry_list_unsubscribe_headers() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(&app).await;
+
+    let email_request = deliver_newsletter(&app).await;
+    let mut unsubscribe_link = app.get_unsubscribe_link(&email_request);
+    unsubscribe_link.set_port(None).unwrap();
+
+    let list_unsubscribe = get_email_header(&email_request, "List-Unsubscribe").unwrap();
+    assert!(list_unsubscribe.contains("<mailto:"));
+    assert!(list_unsubscribe.contains(&format!("<{}>", unsubscribe_link)));
+    assert_eq!(
+        get_email_header(&email_request, "List-Unsubscribe-Post").unwrap(),
+        "List-Unsubscribe=One-Click"
+    );
+}
+
+#[tokio::test]
+async fn confirmation_emails_do_not_carry_list_unsubscribe_headers() {
+    let app = spawn_app().await;
+    app.create_unconfirmed_subscriber().await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+
+    assert!(get_email_header(email_request, "List-Unsubscribe").is_none());
+}
+
+#[tokio::test]
+async fn a_one_click_unsubscribe_request_unsubscribes_the_subscriber() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(&app).await;
+
+    let email_request = deliver_newsletter(&app).await;
+    let list_unsubscribe = get_email_header(&email_request, "List-Unsubscribe").unwrap();
+    let https_link = list_unsubscribe
+        .split(", ")
+        .map(|l| l.trim_start_matches('<').trim_end_matches('>'))
+        .find(|l| l.starts_with("http"))
+        .unwrap();
+    let mut https_link = reqwest::Url::parse(https_link).unwrap();
+    https_link.set_port(Some(app.port)).unwrap();
+
+    // Mail clients send the request without any cookie or prior visit
+    let response = reqwest::Client::new()
+        .post(https_link)
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("List-Unsubscribe=One-Click")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn unsubscribing_a_suppressed_subscriber_keeps_them_suppressed() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(&app).await;
+    let unsubscribe_link = deliver_newsletter_and_get_unsubscribe_link(&app).await;
+    // The address bounced after the issue went out
+    sqlx::query!("UPDATE subscriptions SET status = 'suppressed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app.api_client.post(unsubscribe_link).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "suppressed");
+}
@@ -0,0 +1,192 @@
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+/// Publish an issue to a single confirmed subscriber and return its id,
+/// leaving the delivery in the queue.
+async fn publish_newsletter(app: &TestApp) -> Uuid {
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(app).await;
+    app.publish_newsletter_with(serde_json::json!({
+        "html": r#"<p>Read <a href="https://example.com/post?a=1&amp;b=2">the post</a> or <a href="mailto:ursula@example.com">write to us</a>.</p>"#,
+    }))
+    .await;
+    sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id
+}
+
+/// Deliver the queued issue and return the HTML body of the email.
+async fn deliver(app: &TestApp) -> String {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    body["HtmlBody"].as_str().unwrap().to_owned()
+}
+
+/// The links to `path` in an HTML body, pointing to the application under test.
+fn links_to(app: &TestApp, html: &str, path: &str) -> Vec<reqwest::Url> {
+    linkify::LinkFinder::new()
+        .links(html)
+        .filter(|l| *l.kind() == linkify::LinkKind::Url)
+        .filter(|l| l.as_str().contains(path))
+        .map(|l| {
+            let mut link = reqwest::Url::parse(&l.as_str().replace("&amp;", "&")).unwrap();
+            assert_eq!(link.host_str().unwrap(), "127.0.0.1");
+            link.set_port(Some(app.port)).unwrap();
+            link
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn links_are_tracked_and_a_pixel_is_added_by_default() {
+    let app = spawn_app().await;
+    publish_newsletter(&app).await;
+
+    let html = deliver(&app).await;
+
+    let click_links = links_to(&app, &html, "/tracking/click");
+    assert_eq!(click_links.len(), 1);
+    let url = click_links[0]
+        .query_pairs()
+        .find(|(k, _)| k == "url")
+        .unwrap()
+        .1;
+    assert_eq!(url, "https://example.com/post?a=1&b=2");
+    assert!(html.contains(r#"href="mailto:ursula@example.com""#));
+    assert_eq!(links_to(&app, &html, "/tracking/open").len(), 1);
+    // The unsubscribe link is not tracked
+    assert_eq!(links_to(&app, &html, "/subscriptions/unsubscribe").len(), 1);
+}
+
+#[tokio::test]
+async fn nothing_is_tracked_when_tracking_is_turned_off() {
+    let app = spawn_app().await;
+    let newsletter_issue_id = publish_newsletter(&app).await;
+    let response = app
+        .post_engagement_tracking(&newsletter_issue_id, false)
+        .await;
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/newsletters/{}", newsletter_issue_id),
+    );
+
+    let html = deliver(&app).await;
+
+    assert!(html.contains(r#"href="https://example.com/post?a=1&amp;b=2""#));
+    assert!(links_to(&app, &html, "/tracking/").is_empty());
+}
+
+#[tokio::test]
+async fn following_a_tracked_link_records_a_click_and_redirects() {
+    let app = spawn_app().await;
+    publish_newsletter(&app).await;
+    let html = deliver(&app).await;
+    let click_link = links_to(&app, &html, "/tracking/click").pop().unwrap();
+
+    let response = app.api_client.get(click_link).send().await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 302);
+    assert_eq!(
+        response.headers()["Location"],
+        "https://example.com/post?a=1&b=2"
+    );
+    let event = sqlx::query!("SELECT kind, url FROM issue_engagement_events")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(event.kind, "click");
+    assert_eq!(
+        event.url.as_deref(),
+        Some("https://example.com/post?a=1&b=2")
+    );
+}
+
+#[tokio::test]
+async fn tracked_links_to_another_destination_are_rejected() {
+    let app = spawn_app().await;
+    publish_newsletter(&app).await;
+    let html = deliver(&app).await;
+    let mut click_link = links_to(&app, &html, "/tracking/click").pop().unwrap();
+    let query: Vec<(String, String)> = click_link
+        .query_pairs()
+        .map(|(k, v)| {
+            let v = if k == "url" {
+                "https://evil.example.com/".into()
+            } else {
+                v.into_owned()
+            };
+            (k.into_owned(), v)
+        })
+        .collect();
+    click_link.query_pairs_mut().clear().extend_pairs(query);
+
+    let response = app.api_client.get(click_link).send().await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+    let n_events = sqlx::query!(r#"SELECT count(*) AS "count!" FROM issue_engagement_events"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_events, 0);
+}
+
+#[tokio::test]
+async fn loading_the_pixel_records_an_open() {
+    let app = spawn_app().await;
+    publish_newsletter(&app).await;
+    let html = deliver(&app).await;
+    let pixel_url = links_to(&app, &html, "/tracking/open").pop().unwrap();
+
+    let response = app.api_client.get(pixel_url).send().await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Content-Type"], "image/gif");
+    assert_eq!(response.headers()["Cache-Control"], "no-store");
+    let event = sqlx::query!("SELECT kind FROM issue_engagement_events")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(event.kind, "open");
+}
+
+#[tokio::test]
+async fn the_issue_page_shows_open_and_click_rates_and_top_links() {
+    let app = spawn_app().await;
+    let newsletter_issue_id = publish_newsletter(&app).await;
+    let html = deliver(&app).await;
+    let click_link = links_to(&app, &html, "/tracking/click").pop().unwrap();
+    // The pixel was blocked, but a click means the email was opened
+    app.api_client.get(click_link.clone()).send().await.unwrap();
+    app.api_client.get(click_link).send().await.unwrap();
+
+    let html_page = app
+        .get_newsletter_issue(&newsletter_issue_id)
+        .await
+        .text()
+        .await
+        .unwrap();
+
+    assert!(html_page.contains("<p>Opens and clicks are tracked.</p>"));
+    assert!(html_page.contains("<li>Open rate: 100.0% (1 of 1)</li>"));
+    assert!(html_page.contains("<li>Click rate: 100.0% (1 of 1)</li>"));
+    assert!(html_page.contains("https://example.com/post?a=1&amp;b=2</a></td><td>2</td><td>1</td>"));
+}
@@ -0,0 +1,266 @@
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+fn bounce(id: i64, bounce_type: &str, email: &str) -> serde_json::Value {
+    serde_json::json!({
+        "RecordType": "Bounce",
+        "ID": id,
+        "Type": bounce_type,
+        "TypeCode": 1,
+        "MessageID": Uuid::new_v4().to_string(),
+        "Description": "The server was unable to deliver your message",
+        "Email": email,
+        "BouncedAt": "2026-10-18T10:00:00Z",
+    })
+}
+
+fn spam_complaint(id: i64, email: &str) -> serde_json::Value {
+    serde_json::json!({
+        "RecordType": "SpamComplaint",
+        "ID": id,
+        "Type": "SpamComplaint",
+        "TypeCode": 512,
+        "MessageID": Uuid::new_v4().to_string(),
+        "Email": email,
+        "BouncedAt": "2026-10-18T10:00:00Z",
+    })
+}
+
+#[tokio::test]
+async fn webhooks_without_credentials_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/webhooks/postmark", &app.address))
+        .json(&bounce(1, "HardBounce", "ursula@example.com"))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(
+        response.headers()["WWW-Authenticate"],
+        r#"Basic realm="webhooks""#
+    );
+}
+
+#[tokio::test]
+async fn webhooks_with_the_wrong_password_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/webhooks/postmark", &app.address))
+        .basic_auth(&app.configuration.postmark_webhook.username, Some("nope"))
+        .json(&bounce(1, "HardBounce", "ursula@example.com"))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+    let n_events = sqlx::query!(r#"SELECT count(*) AS "count!" FROM email_events"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_events, 0);
+}
+
+#[tokio::test]
+async fn invalid_payloads_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_postmark_webhook(&serde_json::json!({ "RecordType": "Bounce" }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn hard_bounces_suppress_the_subscriber() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    let email = app.subscriber_email().await;
+
+    let response = app
+        .post_postmark_webhook(&bounce(1, "HardBounce", &email.to_uppercase()))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(app.subscriber_status().await, "suppressed");
+    let event = sqlx::query!("SELECT record_type, event_type, email FROM email_events")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(event.record_type, "Bounce");
+    assert_eq!(event.event_type, "HardBounce");
+    let suppressed = sqlx::query!("SELECT email, reason FROM suppressed_emails")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(suppressed.email, email.to_lowercase());
+    assert_eq!(suppressed.reason, "hard_bounce");
+}
+
+#[tokio::test]
+async fn spam_complaints_suppress_the_subscriber() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    let email = app.subscriber_email().await;
+
+    let response = app.post_postmark_webhook(&spam_complaint(1, &email)).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(app.subscriber_status().await, "suppressed");
+}
+
+#[tokio::test]
+async fn soft_bounces_are_recorded_without_suppressing_the_subscriber() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    let email = app.subscriber_email().await;
+
+    let response = app
+        .post_postmark_webhook(&bounce(1, "SoftBounce", &email))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(app.subscriber_status().await, "confirmed");
+    let n_events = sqlx::query!(r#"SELECT count(*) AS "count!" FROM email_events"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_events, 1);
+}
+
+async fn consecutive_soft_bounces(app: &TestApp) -> i16 {
+    sqlx::query!("SELECT consecutive_soft_bounces FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .consecutive_soft_bounces
+}
+
+#[tokio::test]
+async fn soft_bounces_are_counted_once_per_event() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    let email = app.subscriber_email().await;
+
+    app.post_postmark_webhook(&bounce(1, "SoftBounce", &email))
+        .await;
+    app.post_postmark_webhook(&bounce(1, "SoftBounce", &email))
+        .await;
+    app.post_postmark_webhook(&bounce(2, "Transient", &email.to_uppercase()))
+        .await;
+
+    assert_eq!(consecutive_soft_bounces(&app).await, 2);
+}
+
+#[tokio::test]
+async fn deliveries_reset_the_soft_bounce_count() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    let email = app.subscriber_email().await;
+    app.post_postmark_webhook(&bounce(1, "SoftBounce", &email))
+        .await;
+
+    let response = app
+        .post_postmark_webhook(&serde_json::json!({
+            "RecordType": "Delivery",
+            "MessageID": Uuid::new_v4().to_string(),
+            "Recipient": email,
+            "DeliveredAt": "2026-10-18T11:00:00Z",
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(consecutive_soft_bounces(&app).await, 0);
+}
+
+#[tokio::test]
+async fn redelivered_events_are_recorded_once() {
+    let app = spawn_app().await;
+    let event = bounce(42, "SoftBounce", "ursula@example.com");
+
+    app.post_postmark_webhook(&event).await;
+    let response = app.post_postmark_webhook(&event).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let n_events = sqlx::query!(r#"SELECT count(*) AS "count!" FROM email_events"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_events, 1);
+}
+
+#[tokio::test]
+async fn other_record_types_are_acknowledged_and_ignored() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_postmark_webhook(&serde_json::json!({
+            "RecordType": "Open",
+            "Recipient": "ursula@example.com",
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn suppressed_subscribers_do_not_get_new_issues() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    let email = app.subscriber_email().await;
+    app.post_postmark_webhook(&bounce(1, "HardBounce", &email))
+        .await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_newsletters(&serde_json::json!({
+            "title": "Newsletter title",
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": Uuid::new_v4().to_string(),
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    let n_queued = sqlx::query!(r#"SELECT count(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_queued, 0);
+}
+
+#[tokio::test]
+async fn suppressed_addresses_cannot_subscribe_again() {
+    let app = spawn_app().await;
+    app.post_postmark_webhook(&spam_complaint(1, "ursula_le_guin@gmail.com"))
+        .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_subscriptions("name=le%20guin&email=Ursula_Le_Guin%40gmail.com".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let n_subscribers = sqlx::query!(r#"SELECT count(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_subscribers, 0);
+}